@@ -0,0 +1,271 @@
+use crate::attachments::mime_type;
+use crate::opencode_events::{ToolPart, ToolState};
+use crate::workspace::relative_path;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Tools whose `filePath` input is a file they wrote
+const WRITING_TOOLS: [&str; 3] = ["write", "edit", "multiedit"];
+
+/// Largest part of a text file returned for a preview
+const MAX_TEXT_PREVIEW_BYTES: u64 = 256 * 1024;
+
+/// Largest image returned for a preview
+const MAX_IMAGE_PREVIEW_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Bytes looked at to tell text from binary files
+const SNIFF_BYTES: u64 = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Text,
+    Image,
+    Binary,
+}
+
+/// A file the agent wrote in the workspace while answering a message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Relative to the workspace, with `/` separators
+    pub path: String,
+    /// The tool that last wrote it
+    pub tool: String,
+    pub kind: ArtifactKind,
+    pub size: u64,
+}
+
+/// A file a tool call wrote, with the path as the tool was given it
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrittenFile {
+    pub tool: String,
+    pub path: String,
+}
+
+/// What the UI shows when previewing an artifact
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ArtifactPreview {
+    Text { content: String, truncated: bool },
+    /// Base64-encoded content, for a `data:` URL
+    Image { mime: String, data: String },
+    /// Binary files and images too large to show
+    Unsupported { size: u64 },
+}
+
+/// The file a successful write or edit call wrote
+pub fn written_file(part: &ToolPart) -> Option<WrittenFile> {
+    if !WRITING_TOOLS.contains(&part.tool.as_str()) {
+        return None;
+    }
+    let ToolState::Completed { input: Some(input), .. } = &part.state else {
+        return None;
+    };
+    Some(WrittenFile {
+        tool: part.tool.clone(),
+        path: input.get("filePath")?.as_str()?.to_string(),
+    })
+}
+
+/// Artifacts for the files written during a message, in the order they were first written.
+/// Files outside the workspace, or removed again since, are left out.
+pub fn collect(workspace: &Path, written: &[WrittenFile]) -> Vec<Artifact> {
+    let Ok(workspace) = workspace.canonicalize() else {
+        return Vec::new();
+    };
+    let mut artifacts: Vec<Artifact> = Vec::new();
+    for file in written {
+        // Absolute paths replace the workspace when joined
+        let Ok(path) = workspace.join(&file.path).canonicalize() else {
+            continue;
+        };
+        if !path.starts_with(&workspace) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let artifact = Artifact {
+            path: relative_path(&workspace, &path),
+            tool: file.tool.clone(),
+            kind: kind(&path),
+            size: metadata.len(),
+        };
+        match artifacts.iter_mut().find(|a| a.path == artifact.path) {
+            Some(existing) => *existing = artifact,
+            None => artifacts.push(artifact),
+        }
+    }
+    artifacts
+}
+
+/// Find an artifact in the workspace, refusing paths that lead out of it
+pub fn resolve(workspace: &Path, path: &str) -> Result<PathBuf, String> {
+    if !Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!("Invalid artifact path: {}", path));
+    }
+    let workspace = workspace
+        .canonicalize()
+        .map_err(|e| format!("Failed to find workspace {:?}: {}", workspace, e))?;
+    let resolved = workspace
+        .join(path)
+        .canonicalize()
+        .map_err(|e| format!("Failed to find {}: {}", path, e))?;
+    if !resolved.starts_with(&workspace) {
+        return Err(format!("{} is outside the workspace", path));
+    }
+    if !resolved.is_file() {
+        return Err(format!("{} is not a file", path));
+    }
+    Ok(resolved)
+}
+
+pub fn preview(workspace: &Path, path: &str) -> Result<ArtifactPreview, String> {
+    let file = resolve(workspace, path)?;
+    let size = fs::metadata(&file)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?
+        .len();
+    match kind(&file) {
+        ArtifactKind::Text => {
+            let content = read_start(&file, MAX_TEXT_PREVIEW_BYTES)?;
+            Ok(ArtifactPreview::Text {
+                content: String::from_utf8_lossy(&content).to_string(),
+                truncated: size > MAX_TEXT_PREVIEW_BYTES,
+            })
+        }
+        ArtifactKind::Image if size <= MAX_IMAGE_PREVIEW_BYTES => {
+            let content = fs::read(&file).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            Ok(ArtifactPreview::Image {
+                mime: mime_type(path).to_string(),
+                data: BASE64.encode(content),
+            })
+        }
+        ArtifactKind::Image | ArtifactKind::Binary => Ok(ArtifactPreview::Unsupported { size }),
+    }
+}
+
+/// Copy an artifact out of the workspace. If `destination` is a directory the file keeps
+/// its name there. Existing files are never overwritten.
+pub fn export(workspace: &Path, path: &str, destination: &Path) -> Result<PathBuf, String> {
+    let source = resolve(workspace, path)?;
+    if !destination.is_absolute() {
+        return Err(format!("Export destination must be an absolute path: {:?}", destination));
+    }
+    let target = match source.file_name() {
+        Some(name) if destination.is_dir() => destination.join(name),
+        _ => destination.to_path_buf(),
+    };
+    if target.exists() {
+        return Err(format!("{:?} already exists", target));
+    }
+    fs::copy(&source, &target).map_err(|e| format!("Failed to export {} to {:?}: {}", path, target, e))?;
+    Ok(target)
+}
+
+fn kind(path: &Path) -> ArtifactKind {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if mime_type(&name).starts_with("image/") {
+        return ArtifactKind::Image;
+    }
+    match read_start(path, SNIFF_BYTES) {
+        // A character may be cut off at the end
+        Ok(start) if !start.contains(&0) => match std::str::from_utf8(&start) {
+            Ok(_) => ArtifactKind::Text,
+            Err(e) if e.error_len().is_none() => ArtifactKind::Text,
+            Err(_) => ArtifactKind::Binary,
+        },
+        _ => ArtifactKind::Binary,
+    }
+}
+
+fn read_start(path: &Path, limit: u64) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    let mut content = Vec::new();
+    file.take(limit)
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(tool: &str, path: &str) -> WrittenFile {
+        WrittenFile {
+            tool: tool.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collect_preview_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        fs::create_dir_all(workspace.join("out")).unwrap();
+        fs::write(workspace.join("out/report.md"), "# Report\n").unwrap();
+        fs::write(workspace.join("chart.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(workspace.join("data.bin"), [0, 1, 2]).unwrap();
+        fs::write(dir.path().join("outside.txt"), "secret").unwrap();
+
+        let artifacts = collect(
+            &workspace,
+            &[
+                written("write", "out/report.md"),
+                written("write", &workspace.join("chart.png").to_string_lossy()),
+                written("write", "../outside.txt"),
+                written("write", "deleted.txt"),
+                written("write", "data.bin"),
+                written("edit", "out/report.md"),
+            ],
+        );
+        let summary: Vec<_> = artifacts
+            .iter()
+            .map(|a| (a.path.as_str(), a.tool.as_str(), a.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("out/report.md", "edit", ArtifactKind::Text),
+                ("chart.png", "write", ArtifactKind::Image),
+                ("data.bin", "write", ArtifactKind::Binary),
+            ]
+        );
+
+        assert_eq!(
+            preview(&workspace, "out/report.md").unwrap(),
+            ArtifactPreview::Text {
+                content: "# Report\n".to_string(),
+                truncated: false
+            }
+        );
+        assert!(matches!(
+            preview(&workspace, "chart.png").unwrap(),
+            ArtifactPreview::Image { ref mime, .. } if mime == "image/png"
+        ));
+        assert_eq!(
+            preview(&workspace, "data.bin").unwrap(),
+            ArtifactPreview::Unsupported { size: 3 }
+        );
+        assert!(preview(&workspace, "../outside.txt").is_err());
+        assert!(preview(&workspace, "out").is_err());
+
+        let exports = dir.path().join("exports");
+        fs::create_dir_all(&exports).unwrap();
+        let exported = export(&workspace, "out/report.md", &exports).unwrap();
+        assert_eq!(exported, exports.join("report.md"));
+        assert_eq!(fs::read_to_string(&exported).unwrap(), "# Report\n");
+        assert!(export(&workspace, "out/report.md", &exports).is_err());
+        assert!(export(&workspace, "out/report.md", Path::new("relative.md")).is_err());
+    }
+}
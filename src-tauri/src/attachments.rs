@@ -0,0 +1,207 @@
+use crate::workspace::content_hash;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Directory in the workspace attachments are copied to
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// Largest file that can be attached
+const MAX_FILE_BYTES: u64 = 25 * 1024 * 1024;
+
+/// Largest total size of the attachments of a session
+const MAX_SESSION_BYTES: u64 = 200 * 1024 * 1024;
+
+/// A file the user gave the agent, copied into the session's workspace
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Relative to the workspace, with `/` separators, e.g. `attachments/report.pdf`
+    pub path: String,
+    /// Where the file was copied from
+    pub source: PathBuf,
+    pub size: u64,
+    /// Hex SHA-256 of the content
+    pub hash: String,
+    /// Attach time in milliseconds since the Unix epoch
+    pub attached_at: u64,
+    /// Whether it has been passed to opencode with a message
+    #[serde(default)]
+    pub sent: bool,
+}
+
+impl Attachment {
+    /// File name shown to the user and to opencode
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// MIME type guessed from the file extension
+    pub fn mime_type(&self) -> &'static str {
+        mime_type(self.name())
+    }
+}
+
+/// MIME type guessed from a file name's extension
+pub fn mime_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("csv") => "text/csv",
+        Some("json") => "application/json",
+        Some("md") => "text/markdown",
+        Some("html" | "htm") => "text/html",
+        Some("txt" | "log") => "text/plain",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Copy a file into the workspace's `attachments` directory. A file with the same content
+/// as one of `existing` isn't copied again; the existing attachment is returned instead.
+pub fn attach(workspace: &Path, existing: &[Attachment], source: &Path, now: u64) -> Result<Attachment, String> {
+    let metadata = fs::metadata(source).map_err(|e| format!("Failed to read {:?}: {}", source, e))?;
+    if !metadata.is_file() {
+        return Err(format!("{:?} is not a file", source));
+    }
+    if metadata.len() > MAX_FILE_BYTES {
+        return Err(format!(
+            "{:?} is too large to attach ({} MB, the limit is {} MB)",
+            source,
+            metadata.len() / (1024 * 1024),
+            MAX_FILE_BYTES / (1024 * 1024)
+        ));
+    }
+
+    let content = fs::read(source).map_err(|e| format!("Failed to read {:?}: {}", source, e))?;
+    let hash = content_hash(&content);
+    if let Some(attachment) = existing.iter().find(|a| a.hash == hash) {
+        return Ok(attachment.clone());
+    }
+    let total: u64 = existing.iter().map(|a| a.size).sum();
+    if total + metadata.len() > MAX_SESSION_BYTES {
+        return Err(format!(
+            "Attachments of a conversation are limited to {} MB in total",
+            MAX_SESSION_BYTES / (1024 * 1024)
+        ));
+    }
+
+    // The agent can change the workspace, so don't follow links it may have left there
+    let dir = workspace.join(ATTACHMENTS_DIR);
+    if is_symlink(&dir) {
+        return Err(format!("{:?} is a symbolic link", dir));
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    let name = unique_name(&dir, &file_name(source)?);
+    let target = dir.join(&name);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target)
+        .map_err(|e| format!("Failed to write {:?}: {}", target, e))?;
+    file.write_all(&content).map_err(|e| format!("Failed to write {:?}: {}", target, e))?;
+
+    Ok(Attachment {
+        path: format!("{}/{}", ATTACHMENTS_DIR, name),
+        source: source.to_path_buf(),
+        size: metadata.len(),
+        hash,
+        attached_at: now,
+        sent: false,
+    })
+}
+
+fn file_name(source: &Path) -> Result<String, String> {
+    source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.'))
+        .ok_or_else(|| format!("Cannot attach {:?}", source))
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// `name`, or `name (2)`, `name (3)`... if it is taken, keeping the extension
+fn unique_name(dir: &Path, name: &str) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let mut candidate = name.to_string();
+    let mut counter = 2;
+    // symlink_metadata so that a dangling link counts as taken
+    while fs::symlink_metadata(dir.join(&candidate)).is_ok() {
+        candidate = format!("{} ({}){}", stem, counter, extension);
+        counter += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_copies_dedups_and_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        let downloads = dir.path().join("downloads");
+        fs::create_dir_all(downloads.join("other")).unwrap();
+        fs::write(downloads.join("report.csv"), "a,b\n1,2\n").unwrap();
+        fs::write(downloads.join("copy.csv"), "a,b\n1,2\n").unwrap();
+        fs::write(downloads.join("other/report.csv"), "c,d\n").unwrap();
+
+        let first = attach(&workspace, &[], &downloads.join("report.csv"), 1).unwrap();
+        assert_eq!(first.path, "attachments/report.csv");
+        assert_eq!(first.mime_type(), "text/csv");
+        assert_eq!(fs::read_to_string(workspace.join(&first.path)).unwrap(), "a,b\n1,2\n");
+
+        // Same content under another name is the same attachment
+        let existing = vec![first.clone()];
+        assert_eq!(attach(&workspace, &existing, &downloads.join("copy.csv"), 2).unwrap(), first);
+
+        let second = attach(&workspace, &existing, &downloads.join("other/report.csv"), 3).unwrap();
+        assert_eq!(second.path, "attachments/report (2).csv");
+        assert_eq!(second.name(), "report (2).csv");
+
+        assert!(attach(&workspace, &existing, &downloads, 4).is_err());
+        let large = vec![Attachment {
+            size: MAX_SESSION_BYTES,
+            hash: "other".to_string(),
+            ..first
+        }];
+        let error = attach(&workspace, &large, &downloads.join("other/report.csv"), 5).unwrap_err();
+        assert!(error.contains("limited"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_attach_does_not_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(workspace.join(ATTACHMENTS_DIR)).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(dir.path().join("report.csv"), "a,b\n").unwrap();
+
+        // A dangling link at the chosen name is skipped, not written through
+        std::os::unix::fs::symlink(outside.join("report.csv"), workspace.join("attachments/report.csv")).unwrap();
+        let attachment = attach(&workspace, &[], &dir.path().join("report.csv"), 1).unwrap();
+        assert_eq!(attachment.path, "attachments/report (2).csv");
+        assert!(!outside.join("report.csv").exists());
+
+        // A linked attachments directory is refused
+        fs::remove_dir_all(workspace.join(ATTACHMENTS_DIR)).unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join(ATTACHMENTS_DIR)).unwrap();
+        assert!(attach(&workspace, &[], &dir.path().join("report.csv"), 2).is_err());
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::latchkey::{BrowserCheck, BrowserProgress, LatchkeyError};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Number of output lines of the last check kept for the UI
+const OUTPUT_LINES: usize = 200;
+
+/// Where setting up the browser latchkey logs in with is at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrowserState {
+    /// No check has run since the app started
+    #[default]
+    Unknown,
+    Checking,
+    Ready,
+    Failed,
+    Cancelled,
+}
+
+/// What the UI shows about the browser check
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserStatus {
+    pub state: BrowserState,
+    /// Progress of the current download, if latchkey reported one
+    pub percent: Option<f32>,
+    /// stdout and stderr of the last check, in the order they were printed
+    pub output: String,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Check {
+    state: BrowserState,
+    percent: Option<f32>,
+    output: VecDeque<String>,
+    error: Option<String>,
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+/// Tracks the `latchkey ensure-browser` run, so that there is only one at a time
+/// and it can be cancelled from another command
+#[derive(Default)]
+pub struct BrowserSetup {
+    check: Mutex<Check>,
+}
+
+impl BrowserSetup {
+    pub fn status(&self) -> BrowserStatus {
+        let check = self.check.lock().unwrap();
+        BrowserStatus {
+            state: check.state,
+            percent: check.percent,
+            output: Vec::from(check.output.clone()).join("\n"),
+            error: check.error.clone(),
+        }
+    }
+
+    /// Begin a new check. The receiver fires when the check is cancelled.
+    pub fn start(&self) -> Result<oneshot::Receiver<()>, String> {
+        let mut check = self.check.lock().unwrap();
+        if check.state == BrowserState::Checking {
+            return Err("The browser is already being checked".to_string());
+        }
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        check.state = BrowserState::Checking;
+        check.percent = None;
+        check.output.clear();
+        check.error = None;
+        check.cancel = Some(cancel_tx);
+        Ok(cancel_rx)
+    }
+
+    pub fn record(&self, progress: &BrowserProgress) {
+        let mut check = self.check.lock().unwrap();
+        if check.output.len() == OUTPUT_LINES {
+            check.output.pop_front();
+        }
+        check.output.push_back(progress.line.clone());
+        if progress.percent.is_some() {
+            check.percent = progress.percent;
+        }
+    }
+
+    pub fn finish(&self, result: &Result<BrowserCheck, LatchkeyError>) -> BrowserStatus {
+        {
+            let mut check = self.check.lock().unwrap();
+            check.cancel = None;
+            check.percent = None;
+            match result {
+                Ok(_) => check.state = BrowserState::Ready,
+                Err(LatchkeyError::Cancelled) => check.state = BrowserState::Cancelled,
+                Err(e) => {
+                    check.state = BrowserState::Failed;
+                    check.error = Some(e.to_string());
+                }
+            }
+        }
+        self.status()
+    }
+
+    /// Stop the running check, if any. Returns whether one was running.
+    pub fn cancel(&self) -> bool {
+        let cancel = self.check.lock().unwrap().cancel.take();
+        match cancel {
+            Some(cancel) => {
+                println!("[browser] Cancelling browser check");
+                let _ = cancel.send(());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latchkey::OutputStream;
+
+    fn progress(line: &str, percent: Option<f32>) -> BrowserProgress {
+        BrowserProgress {
+            stream: OutputStream::Stdout,
+            line: line.to_string(),
+            percent,
+        }
+    }
+
+    #[test]
+    fn test_one_check_at_a_time() {
+        let setup = BrowserSetup::default();
+        assert_eq!(setup.status().state, BrowserState::Unknown);
+        assert!(!setup.cancel());
+
+        let mut cancelled = setup.start().unwrap();
+        assert!(setup.start().is_err());
+        setup.record(&progress("Downloading", None));
+        setup.record(&progress("|■■■■ | 40% of 100 MiB", Some(40.0)));
+        setup.record(&progress("still downloading", None));
+        let status = setup.status();
+        assert_eq!(status.state, BrowserState::Checking);
+        assert_eq!(status.percent, Some(40.0));
+        assert_eq!(status.output.lines().count(), 3);
+
+        assert!(setup.cancel());
+        assert!(cancelled.try_recv().is_ok());
+        let status = setup.finish(&Err(LatchkeyError::Cancelled));
+        assert_eq!(status.state, BrowserState::Cancelled);
+        assert_eq!(status.error, None);
+
+        // Retrying starts over
+        setup.start().unwrap();
+        assert_eq!(setup.status().output, "");
+        let status = setup.finish(&Err(LatchkeyError::NotInstalled));
+        assert_eq!(status.state, BrowserState::Failed);
+        assert!(status.error.is_some());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::{get_config_path, ConfigFile};
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Spending limits, stored as `budget` in `~/.passepartout.json` next to the API keys.
+/// Unset limits don't apply.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetLimits {
+    /// Tokens a single message may use, see [`Usage::total_tokens`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_tokens: Option<u64>,
+    /// Cost of a whole session, in US dollars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_cost: Option<f64>,
+    /// Cost per day in US dollars, by provider ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub daily_cost: BTreeMap<String, f64>,
+}
+
+impl BudgetLimits {
+    pub fn load() -> Result<Self, String> {
+        Ok(ConfigFile::load(&get_config_path()?)?.budget)
+    }
+
+    pub fn save(self) -> Result<(), String> {
+        self.validate()?;
+        ConfigFile::update(&get_config_path()?, |config| {
+            config.budget = self;
+            Ok(())
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == BudgetLimits::default()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let costs = self.session_cost.iter().chain(self.daily_cost.values());
+        for cost in costs {
+            if !cost.is_finite() || *cost < 0.0 {
+                return Err(format!("Invalid cost limit: {}", cost));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kinds of limits in [`BudgetLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetLimit {
+    MessageTokens,
+    SessionCost,
+    DailyCost,
+}
+
+/// A limit a message reached
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BudgetExceeded {
+    pub limit: BudgetLimit,
+    pub allowed: f64,
+    pub used: f64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            BudgetLimit::MessageTokens => write!(
+                f,
+                "The message used {} tokens, over the limit of {} tokens per message",
+                self.used, self.allowed
+            ),
+            BudgetLimit::SessionCost => write!(
+                f,
+                "This conversation has cost ${:.2}, reaching its limit of ${:.2}",
+                self.used, self.allowed
+            ),
+            BudgetLimit::DailyCost => write!(
+                f,
+                "This provider has cost ${:.2} today, reaching the daily limit of ${:.2}",
+                self.used, self.allowed
+            ),
+        }
+    }
+}
+
+/// Checks the usage of a message against the limits that apply to it
+#[derive(Debug, Clone, Default)]
+pub struct BudgetTracker {
+    message_tokens: Option<u64>,
+    session_cost: Option<f64>,
+    daily_cost: Option<f64>,
+    /// Session usage before the message
+    session_usage: Usage,
+    /// The provider's usage today before the message
+    daily_usage: Usage,
+}
+
+impl BudgetTracker {
+    pub fn new(limits: &BudgetLimits, provider_id: &str, session_usage: Usage, daily_usage: Usage) -> Self {
+        Self {
+            message_tokens: limits.message_tokens,
+            session_cost: limits.session_cost,
+            daily_cost: limits.daily_cost.get(provider_id).copied(),
+            session_usage,
+            daily_usage,
+        }
+    }
+
+    /// A cost limit that was already reached before the message was sent
+    pub fn check_start(&self) -> Option<BudgetExceeded> {
+        let reached = |limit, allowed: Option<f64>, used: f64| {
+            allowed
+                .filter(|allowed| used >= *allowed)
+                .map(|allowed| BudgetExceeded { limit, allowed, used })
+        };
+        reached(BudgetLimit::SessionCost, self.session_cost, self.session_usage.cost)
+            .or_else(|| reached(BudgetLimit::DailyCost, self.daily_cost, self.daily_usage.cost))
+    }
+
+    /// The first limit the message's usage so far has crossed
+    pub fn check(&self, message: &Usage) -> Option<BudgetExceeded> {
+        let crossed = |limit, allowed: Option<f64>, used: f64| {
+            allowed
+                .filter(|allowed| used > *allowed)
+                .map(|allowed| BudgetExceeded { limit, allowed, used })
+        };
+        crossed(
+            BudgetLimit::MessageTokens,
+            self.message_tokens.map(|tokens| tokens as f64),
+            message.total_tokens() as f64,
+        )
+        .or_else(|| {
+            crossed(
+                BudgetLimit::SessionCost,
+                self.session_cost,
+                self.session_usage.cost + message.cost,
+            )
+        })
+        .or_else(|| {
+            crossed(
+                BudgetLimit::DailyCost,
+                self.daily_cost,
+                self.daily_usage.cost + message.cost,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input_tokens: u64, cost: f64) -> Usage {
+        Usage {
+            input_tokens,
+            cost,
+            ..Default::default()
+        }
+    }
+
+    fn limits() -> BudgetLimits {
+        BudgetLimits {
+            message_tokens: Some(10_000),
+            session_cost: Some(2.0),
+            daily_cost: BTreeMap::from([("anthropic".to_string(), 5.0)]),
+        }
+    }
+
+    #[test]
+    fn test_message_crossing_limits() {
+        let tracker = BudgetTracker::new(&limits(), "anthropic", usage(0, 1.5), usage(0, 4.0));
+        assert_eq!(tracker.check_start(), None);
+        assert_eq!(tracker.check(&usage(10_000, 0.5)), None);
+
+        let exceeded = tracker.check(&usage(10_001, 0.1)).unwrap();
+        assert_eq!(exceeded.limit, BudgetLimit::MessageTokens);
+        assert_eq!(exceeded.used, 10_001.0);
+
+        let exceeded = tracker.check(&usage(100, 0.75)).unwrap();
+        assert_eq!(exceeded.limit, BudgetLimit::SessionCost);
+        assert_eq!(exceeded.used, 2.25);
+
+        // The daily limit only applies to its provider
+        let tracker = BudgetTracker::new(&limits(), "anthropic", usage(0, 0.0), usage(0, 4.0));
+        assert_eq!(tracker.check(&usage(100, 1.5)).unwrap().limit, BudgetLimit::DailyCost);
+        let tracker = BudgetTracker::new(&limits(), "openai", usage(0, 0.0), usage(0, 40.0));
+        assert_eq!(tracker.check(&usage(100, 1.5)), None);
+    }
+
+    #[test]
+    fn test_reached_limits_block_new_messages() {
+        let tracker = BudgetTracker::new(&limits(), "anthropic", usage(0, 2.0), usage(0, 0.0));
+        assert_eq!(tracker.check_start().unwrap().limit, BudgetLimit::SessionCost);
+        let tracker = BudgetTracker::new(&limits(), "anthropic", usage(0, 0.0), usage(0, 5.5));
+        assert_eq!(tracker.check_start().unwrap().limit, BudgetLimit::DailyCost);
+        assert!(BudgetTracker::default().check(&usage(u64::MAX, 1e9)).is_none());
+    }
+
+    #[test]
+    fn test_negative_limits_are_rejected() {
+        let mut limits = limits();
+        assert!(limits.validate().is_ok());
+        limits.session_cost = Some(-1.0);
+        assert!(limits.validate().is_err());
+    }
+}
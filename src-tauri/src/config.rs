@@ -0,0 +1,123 @@
+use crate::budget::BudgetLimits;
+use crate::fs_utils::write_file_atomic_with_mode;
+use crate::opencode::OpencodeRunner;
+use crate::permissions::PermissionPolicy;
+use crate::providers::ProviderInfo;
+use crate::secret_store::{EncryptedSecrets, SecretBackend};
+use crate::workspace::WorkspaceSettings;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Held while the config file is read, changed and written back,
+/// so that concurrent commands don't undo each other's changes
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Get the path to the configuration file
+pub fn get_config_path() -> Result<PathBuf, String> {
+    let home = std::env::var("HOME").map_err(|_| "HOME environment variable not set")?;
+    Ok(PathBuf::from(home).join(".passepartout.json"))
+}
+
+/// Settings and, depending on the secret backend, API keys stored in `~/.passepartout.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub secret_backend: SecretBackend,
+    /// Plaintext API keys, used by the JSON file backend
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub api_keys: HashMap<String, String>,
+    /// API keys sealed by the encrypted file backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_api_keys: Option<EncryptedSecrets>,
+    /// Whether messages run on a long-lived opencode server or a process per message
+    #[serde(default)]
+    pub opencode_runner: OpencodeRunner,
+    /// Providers added to or replacing the bundled ones, matched by ID
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<ProviderInfo>,
+    /// Spending limits enforced while messages run
+    #[serde(default, skip_serializing_if = "BudgetLimits::is_empty")]
+    pub budget: BudgetLimits,
+    /// Which tools opencode may use without asking
+    #[serde(default, skip_serializing_if = "PermissionPolicy::is_default")]
+    pub permissions: PermissionPolicy,
+    /// Where the workspaces opencode works in are kept
+    #[serde(default, skip_serializing_if = "WorkspaceSettings::is_default")]
+    pub workspace: WorkspaceSettings,
+    /// Fields this version doesn't know, kept so saving doesn't drop them
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ConfigFile {
+    /// Load the configuration file. A missing file yields the default configuration.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read config file: {}", e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse config file: {}", e))
+    }
+
+    /// Load the configuration file, change it and save it, with no other update in between
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        let _lock = CONFIG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut config = Self::load(path)?;
+        let result = change(&mut config)?;
+        config.save(path)?;
+        Ok(result)
+    }
+
+    /// Save the configuration file with restricted permissions
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        write_file_atomic_with_mode(path, content.as_bytes(), Some(0o600))?;
+        println!("[config] Saved config to {:?}", path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_update_keeps_unknown_fields_and_concurrent_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".passepartout.json");
+        fs::write(&path, r#"{"api_keys": {"openai": "sk-1"}, "future_setting": {"enabled": true}}"#).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    ConfigFile::update(&path, |config| {
+                        config.api_keys.insert(format!("provider-{}", i), "key".to_string());
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let config = ConfigFile::load(&path).unwrap();
+        assert_eq!(config.api_keys.len(), 9);
+        assert_eq!(config.extra["future_setting"], serde_json::json!({"enabled": true}));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
@@ -1,199 +1,393 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-
-/// Get the path to the credentials file
-fn get_credentials_path() -> Result<PathBuf, String> {
-    let home = std::env::var("HOME").map_err(|_| "HOME environment variable not set")?;
-    Ok(PathBuf::from(home).join(".passepartout.json"))
-}
-
-/// Credentials stored in the JSON file
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct CredentialsFile {
-    #[serde(default)]
-    api_keys: HashMap<String, String>,
-}
+use crate::config::{get_config_path, ConfigFile};
+use crate::providers::ProviderRegistry;
+use crate::secret_store::{
+    migrate_secrets, EncryptedFileStore, JsonFileStore, KdfParams, SecretBackend,
+    SecretServiceStore, SecretStore,
+};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
 
-/// Supported LLM providers
-#[derive(Debug, Clone, Copy)]
-pub enum Provider {
-    Anthropic,
-    OpenAI,
-    Google,
+/// Errors from credential operations that the frontend needs to tell apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialError {
+    /// API keys are encrypted and no passphrase has been entered in this app session
+    Locked,
+    /// The passphrase does not open the encrypted API keys
+    WrongPassphrase,
+    Other(String),
 }
 
-impl Provider {
-    /// Get the provider ID as a string
-    pub fn as_str(&self) -> &'static str {
+impl CredentialError {
+    fn kind(&self) -> &'static str {
         match self {
-            Provider::Anthropic => "anthropic",
-            Provider::OpenAI => "openai",
-            Provider::Google => "google",
+            CredentialError::Locked => "locked",
+            CredentialError::WrongPassphrase => "wrong-passphrase",
+            CredentialError::Other(_) => "other",
         }
     }
+}
 
-    /// Get the environment variable name for this provider's API key
-    pub fn env_var_name(&self) -> &'static str {
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Provider::Anthropic => "ANTHROPIC_API_KEY",
-            Provider::OpenAI => "OPENAI_API_KEY",
-            Provider::Google => "GOOGLE_API_KEY",
+            CredentialError::Locked => {
+                write!(f, "API keys are locked. Enter your passphrase in the API Keys settings.")
+            }
+            CredentialError::WrongPassphrase => write!(f, "Wrong passphrase"),
+            CredentialError::Other(message) => write!(f, "{}", message),
         }
     }
+}
 
-    /// Parse a provider from a string
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "anthropic" => Some(Provider::Anthropic),
-            "openai" => Some(Provider::OpenAI),
-            "google" => Some(Provider::Google),
-            _ => None,
-        }
+impl From<String> for CredentialError {
+    fn from(message: String) -> Self {
+        CredentialError::Other(message)
     }
+}
 
-    /// Get all supported providers
-    pub fn all() -> &'static [Provider] {
-        &[Provider::Anthropic, Provider::OpenAI, Provider::Google]
+/// Sent to the frontend as `{ kind, message }`
+impl Serialize for CredentialError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CredentialError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
     }
 }
 
-/// Credential manager for storing and retrieving API keys from a local JSON file
+/// Whether the API keys are encrypted with a passphrase and whether it has been entered
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LockStatus {
+    pub encrypted: bool,
+    pub unlocked: bool,
+}
+
+/// Key derived from the passphrase, with the parameters needed to store it alongside the secrets
+#[derive(Clone)]
+struct SessionKey {
+    key: [u8; 32],
+    kdf: KdfParams,
+}
+
+/// The passphrase-derived key, kept in memory only for as long as the app runs
+static SESSION_KEY: Mutex<Option<SessionKey>> = Mutex::new(None);
+
+/// Credential manager for storing and retrieving API keys through the configured secret store
 pub struct CredentialManager;
 
 impl CredentialManager {
-    /// Load credentials from the JSON file
-    fn load_credentials() -> Result<CredentialsFile, String> {
-        let path = get_credentials_path()?;
-        if !path.exists() {
-            return Ok(CredentialsFile::default());
-        }
-        let content =
-            fs::read_to_string(&path).map_err(|e| format!("Failed to read credentials file: {}", e))?;
-        serde_json::from_str(&content).map_err(|e| format!("Failed to parse credentials file: {}", e))
-    }
+    /// Open the secret store selected in the config file.
+    /// Plaintext keys left over in the config file are moved into the selected store.
+    fn open_store() -> Result<Box<dyn SecretStore>, CredentialError> {
+        let path = get_config_path()?;
+        let config = ConfigFile::load(&path)?;
+        let store = open_backend(config.secret_backend, &path, session_key())?;
 
-    /// Save credentials to the JSON file with restricted permissions
-    fn save_credentials(creds: &CredentialsFile) -> Result<(), String> {
-        let path = get_credentials_path()?;
-        let content = serde_json::to_string_pretty(creds)
-            .map_err(|e| format!("Failed to serialize credentials: {}", e))?;
+        if config.secret_backend != SecretBackend::JsonFile && !config.api_keys.is_empty() {
+            let moved = migrate_secrets(&JsonFileStore::new(&path), store.as_ref())?;
+            println!(
+                "[credentials] Migrated {} plaintext API keys to {:?}",
+                moved, config.secret_backend
+            );
+        }
 
-        // Write to a temp file first, then rename for atomicity
-        let temp_path = path.with_extension("json.tmp");
+        Ok(store)
+    }
 
-        let mut file = fs::File::create(&temp_path)
-            .map_err(|e| format!("Failed to create credentials file: {}", e))?;
+    /// Get the backend API keys are currently stored in
+    pub fn get_secret_backend() -> Result<SecretBackend, CredentialError> {
+        Ok(ConfigFile::load(&get_config_path()?)?.secret_backend)
+    }
 
-        // Set permissions to 600 (owner read/write only) before writing content
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let permissions = fs::Permissions::from_mode(0o600);
-            fs::set_permissions(&temp_path, permissions)
-                .map_err(|e| format!("Failed to set file permissions: {}", e))?;
+    /// Switch to another secret backend, moving all stored API keys into it.
+    /// Switching to the encrypted file requires the passphrase, so use `unlock` for that.
+    /// Returns the number of keys moved.
+    pub fn set_secret_backend(backend: SecretBackend) -> Result<usize, CredentialError> {
+        let path = get_config_path()?;
+        let current = Self::get_secret_backend()?;
+        if current == backend {
+            return Ok(0);
         }
 
-        file.write_all(content.as_bytes())
-            .map_err(|e| format!("Failed to write credentials file: {}", e))?;
+        let from = Self::open_store()?;
+        let to = open_backend(backend, &path, session_key())?;
+        let moved = migrate_secrets(from.as_ref(), to.as_ref())?;
 
-        // Rename temp file to actual file
-        fs::rename(&temp_path, &path)
-            .map_err(|e| format!("Failed to save credentials file: {}", e))?;
+        ConfigFile::update(&path, |config| {
+            config.secret_backend = backend;
+            Ok(())
+        })?;
+        println!(
+            "[credentials] Switched secret backend from {:?} to {:?} ({} keys moved)",
+            current, backend, moved
+        );
+        Ok(moved)
+    }
 
-        println!("[credentials] Saved credentials to {:?}", path);
+    /// Derive the encryption key from a passphrase and keep it for the rest of the app session.
+    /// If the API keys aren't encrypted yet, they are encrypted with this passphrase.
+    pub fn unlock(passphrase: &str) -> Result<(), CredentialError> {
+        let key = unlock_at(&get_config_path()?, passphrase, KdfParams::generate)?;
+        *SESSION_KEY.lock().unwrap() = Some(key);
+        println!("[credentials] Unlocked API keys");
         Ok(())
     }
 
+    /// Forget the passphrase-derived key
+    pub fn lock() {
+        *SESSION_KEY.lock().unwrap() = None;
+        println!("[credentials] Locked API keys");
+    }
+
+    pub fn lock_status() -> Result<LockStatus, CredentialError> {
+        Ok(LockStatus {
+            encrypted: Self::get_secret_backend()? == SecretBackend::EncryptedFile,
+            unlocked: session_key().is_some(),
+        })
+    }
+
     /// Save a credential for a provider
-    pub fn save_credential(provider: Provider, api_key: &str) -> Result<(), String> {
-        let mut creds = Self::load_credentials()?;
-        creds
-            .api_keys
-            .insert(provider.as_str().to_string(), api_key.to_string());
-        Self::save_credentials(&creds)?;
+    pub fn save_credential(provider_id: &str, api_key: &str) -> Result<(), CredentialError> {
+        ProviderRegistry::load()?.get(provider_id)?;
+        Self::open_store()?.set(provider_id, api_key)?;
         println!(
             "[credentials] Saved API key for {} ({} chars)",
-            provider.as_str(),
+            provider_id,
             api_key.len()
         );
         Ok(())
     }
 
-    /// Get a credential for a provider
-    pub fn get_credential(provider: Provider) -> Result<Option<String>, String> {
-        let creds = Self::load_credentials()?;
-        let result = creds.api_keys.get(provider.as_str()).cloned();
-        println!(
-            "[credentials] Get credential for {}: {}",
-            provider.as_str(),
-            if result.is_some() { "found" } else { "not found" }
-        );
-        Ok(result)
+    /// Get the stored API key for a provider
+    pub fn get_credential(provider_id: &str) -> Result<Option<String>, CredentialError> {
+        Ok(Self::open_store()?.get(provider_id)?)
     }
 
     /// Delete a credential for a provider
-    pub fn delete_credential(provider: Provider) -> Result<(), String> {
-        let mut creds = Self::load_credentials()?;
-        creds.api_keys.remove(provider.as_str());
-        Self::save_credentials(&creds)?;
-        println!("[credentials] Deleted API key for {}", provider.as_str());
+    pub fn delete_credential(provider_id: &str) -> Result<(), CredentialError> {
+        Self::open_store()?.delete(provider_id)?;
+        println!("[credentials] Deleted API key for {}", provider_id);
         Ok(())
     }
 
-    /// Check if a credential exists for a provider
-    pub fn has_credential(provider: Provider) -> Result<bool, String> {
-        Ok(Self::get_credential(provider)?.is_some())
+    /// Get all credentials as a list of (provider_id, has_key) pairs
+    pub fn list_credentials() -> Result<Vec<(String, bool)>, CredentialError> {
+        Ok(list_credentials_in(
+            Self::open_store()?.as_ref(),
+            &ProviderRegistry::load()?,
+        )?)
     }
 
-    /// Get all credentials as a list of (provider_id, has_key) pairs
-    pub fn list_credentials() -> Result<Vec<(String, bool)>, String> {
-        let mut result = Vec::new();
-        for provider in Provider::all() {
-            let has_key = Self::has_credential(*provider)?;
-            result.push((provider.as_str().to_string(), has_key));
+    /// Get all credentials as environment variables for process spawning
+    /// Returns a Vec of (env_var_name, api_key) pairs.
+    /// Fails with `CredentialError::Locked` while encrypted keys haven't been unlocked.
+    pub fn get_credentials_as_env_vars() -> Result<Vec<(String, String)>, CredentialError> {
+        Ok(credentials_as_env_vars_in(
+            Self::open_store()?.as_ref(),
+            &ProviderRegistry::load()?,
+        )?)
+    }
+}
+
+fn session_key() -> Option<SessionKey> {
+    SESSION_KEY.lock().unwrap().clone()
+}
+
+/// Open a secret backend. The encrypted file can only be opened with the session key.
+fn open_backend(
+    backend: SecretBackend,
+    config_path: &Path,
+    session_key: Option<SessionKey>,
+) -> Result<Box<dyn SecretStore>, CredentialError> {
+    Ok(match backend {
+        SecretBackend::JsonFile => Box::new(JsonFileStore::new(config_path)),
+        SecretBackend::SecretService => Box::new(SecretServiceStore),
+        SecretBackend::EncryptedFile => {
+            let SessionKey { key, kdf } = session_key.ok_or(CredentialError::Locked)?;
+            Box::new(EncryptedFileStore::new(config_path, key, kdf))
         }
-        Ok(result)
+    })
+}
+
+/// Derive the key for the config file at `path` and check it opens the encrypted API keys.
+/// A config file without encrypted keys is upgraded in place: a new salt is generated with
+/// `new_kdf` and the keys from the current backend are moved into the encrypted section.
+fn unlock_at(
+    path: &Path,
+    passphrase: &str,
+    new_kdf: impl FnOnce() -> KdfParams,
+) -> Result<SessionKey, CredentialError> {
+    let config = ConfigFile::load(path)?;
+    let (kdf, is_new) = match config.encrypted_api_keys {
+        Some(sealed) => (sealed.kdf, false),
+        None => (new_kdf(), true),
+    };
+    let session_key = SessionKey {
+        key: kdf.derive_key(passphrase)?,
+        kdf,
+    };
+    let store = EncryptedFileStore::new(path, session_key.key, session_key.kdf.clone());
+
+    if is_new {
+        // Record the salt right away so later unlocks must use the same passphrase
+        store.reseal()?;
+    } else if store.verify().is_err() {
+        return Err(CredentialError::WrongPassphrase);
     }
 
-    /// Get all credentials as environment variables for process spawning
-    /// Returns a Vec of (env_var_name, api_key) pairs
-    pub fn get_credentials_as_env_vars() -> Result<Vec<(String, String)>, String> {
-        let mut env_vars = Vec::new();
-        for provider in Provider::all() {
-            if let Some(api_key) = Self::get_credential(*provider)? {
-                env_vars.push((provider.env_var_name().to_string(), api_key));
+    if config.secret_backend != SecretBackend::EncryptedFile {
+        let from = open_backend(config.secret_backend, path, None)?;
+        let moved = migrate_secrets(from.as_ref(), &store)?;
+        ConfigFile::update(path, |config| {
+            config.secret_backend = SecretBackend::EncryptedFile;
+            Ok(())
+        })?;
+        println!("[credentials] Encrypted {} API keys with the passphrase", moved);
+    }
+
+    Ok(session_key)
+}
+
+fn list_credentials_in(
+    store: &dyn SecretStore,
+    registry: &ProviderRegistry,
+) -> Result<Vec<(String, bool)>, String> {
+    let providers = registry.all();
+    let ids: Vec<&str> = providers.iter().map(|provider| provider.id.as_str()).collect();
+    let keys = store.get_many(&ids)?;
+    Ok(providers
+        .iter()
+        .zip(keys)
+        .map(|(provider, key)| (provider.id.clone(), key.is_some()))
+        .collect())
+}
+
+fn credentials_as_env_vars_in(
+    store: &dyn SecretStore,
+    registry: &ProviderRegistry,
+) -> Result<Vec<(String, String)>, String> {
+    let providers = registry.all();
+    let ids: Vec<&str> = providers.iter().map(|provider| provider.id.as_str()).collect();
+    let keys = store.get_many(&ids)?;
+    let mut env_vars = Vec::new();
+    for (provider, key) in providers.iter().zip(keys) {
+        if let Some(api_key) = key {
+            for env_var in &provider.env_vars {
+                env_vars.push((env_var.clone(), api_key.clone()));
             }
         }
-        Ok(env_vars)
     }
+    Ok(env_vars)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::ProviderInfo;
+    use crate::secret_store::MemoryStore;
+
+    #[test]
+    fn test_list_credentials_in_store() {
+        let store = MemoryStore::default();
+        store.set("openai", "sk-test").unwrap();
+        let credentials = list_credentials_in(&store, &ProviderRegistry::bundled().unwrap()).unwrap();
+        assert_eq!(
+            credentials,
+            vec![
+                ("anthropic".to_string(), false),
+                ("openai".to_string(), true),
+                ("google".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_credentials_as_env_vars_in_store() {
+        let store = MemoryStore::default();
+        store.set("anthropic", "sk-ant-test").unwrap();
+        store.set("unrelated", "ignored").unwrap();
+        assert_eq!(
+            credentials_as_env_vars_in(&store, &ProviderRegistry::bundled().unwrap()).unwrap(),
+            vec![("ANTHROPIC_API_KEY".to_string(), "sk-ant-test".to_string())]
+        );
+    }
 
     #[test]
-    fn test_provider_as_str() {
-        assert_eq!(Provider::Anthropic.as_str(), "anthropic");
-        assert_eq!(Provider::OpenAI.as_str(), "openai");
-        assert_eq!(Provider::Google.as_str(), "google");
+    fn test_key_is_exported_to_every_env_var() {
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![ProviderInfo {
+            id: "google".to_string(),
+            display_name: "Google".to_string(),
+            env_vars: vec!["GOOGLE_API_KEY".to_string(), "GEMINI_API_KEY".to_string()],
+            ..Default::default()
+        }]);
+        let store = MemoryStore::default();
+        store.set("google", "AIza-test").unwrap();
+        assert_eq!(
+            credentials_as_env_vars_in(&store, &registry).unwrap(),
+            vec![
+                ("GOOGLE_API_KEY".to_string(), "AIza-test".to_string()),
+                ("GEMINI_API_KEY".to_string(), "AIza-test".to_string()),
+            ]
+        );
+    }
+
+    fn test_kdf() -> KdfParams {
+        KdfParams::generate_with_costs(64, 1, 1)
+    }
+
+    #[test]
+    fn test_unlock_upgrades_plaintext_file_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        JsonFileStore::new(&path).set("openai", "sk-plaintext").unwrap();
+
+        let session_key = unlock_at(&path, "hunter2", test_kdf).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("sk-plaintext"));
+        let config = ConfigFile::load(&path).unwrap();
+        assert_eq!(config.secret_backend, SecretBackend::EncryptedFile);
+        assert!(config.api_keys.is_empty());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let store = open_backend(SecretBackend::EncryptedFile, &path, Some(session_key)).unwrap();
+        assert_eq!(
+            credentials_as_env_vars_in(store.as_ref(), &ProviderRegistry::bundled().unwrap()).unwrap(),
+            vec![("OPENAI_API_KEY".to_string(), "sk-plaintext".to_string())]
+        );
     }
 
     #[test]
-    fn test_provider_env_var_name() {
-        assert_eq!(Provider::Anthropic.env_var_name(), "ANTHROPIC_API_KEY");
-        assert_eq!(Provider::OpenAI.env_var_name(), "OPENAI_API_KEY");
-        assert_eq!(Provider::Google.env_var_name(), "GOOGLE_API_KEY");
+    fn test_unlock_checks_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let first = unlock_at(&path, "hunter2", test_kdf).unwrap();
+
+        let again = unlock_at(&path, "hunter2", || panic!("salt must be reused")).unwrap();
+        assert_eq!(again.key, first.key);
+        assert_eq!(
+            unlock_at(&path, "hunter3", test_kdf).err(),
+            Some(CredentialError::WrongPassphrase)
+        );
     }
 
     #[test]
-    fn test_provider_from_str() {
-        assert!(matches!(Provider::from_str("anthropic"), Some(Provider::Anthropic)));
-        assert!(matches!(Provider::from_str("OPENAI"), Some(Provider::OpenAI)));
-        assert!(matches!(Provider::from_str("Google"), Some(Provider::Google)));
-        assert!(Provider::from_str("unknown").is_none());
+    fn test_encrypted_backend_is_locked_without_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        assert_eq!(
+            open_backend(SecretBackend::EncryptedFile, &path, None).err(),
+            Some(CredentialError::Locked)
+        );
+        let error = serde_json::to_value(CredentialError::Locked).unwrap();
+        assert_eq!(error["kind"], "locked");
     }
 }
@@ -0,0 +1,718 @@
+use crate::time_utils::now_millis;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// First argument of the app binary when it runs as the `latchkey` shim
+pub const SHIM_FLAG: &str = "--latchkey-shim";
+
+/// Directory in the dry run root that holds the shim
+const SHIM_DIR: &str = "bin";
+
+/// Start of what the shim prints instead of the response of a request it held back
+pub const HELD_BACK_NOTICE: &str = "Dry run: this";
+
+/// Stands in for the URL in the notice of a request without one
+pub const UNKNOWN_URL: &str = "an unknown URL";
+
+/// Methods that only read, and are passed through even in dry run mode
+const READ_ONLY_METHODS: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
+
+/// A `latchkey curl` invocation that was held back in dry run mode,
+/// emitted as `chat:proposedRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProposedRequest {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+    pub id: String,
+    pub method: String,
+    pub url: Option<String>,
+    pub body: Option<String>,
+    /// The arguments latchkey was called with, run as they are once confirmed
+    pub args: Vec<String>,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// Output of a proposed request that was sent
+#[derive(Debug, Clone, Serialize)]
+pub struct ProposedRequestResult {
+    pub success: bool,
+    pub output: String,
+}
+
+/// What a curl command line would send
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurlRequest {
+    pub method: String,
+    pub url: Option<String>,
+    pub body: Option<String>,
+    /// Set by `-K` config files and `--next`, which can change the request in ways the
+    /// method and URL here don't show
+    pub opaque: bool,
+}
+
+/// The curl options this parser cares about, by their long name
+#[derive(PartialEq)]
+enum CurlOption {
+    Request,
+    Data,
+    Upload,
+    Head,
+    Get,
+    Url,
+    /// `-K`, which reads more options from a file
+    Config,
+    /// `-:`, which starts another request
+    Next,
+    /// An option whose value doesn't matter here, e.g. a header
+    OtherWithValue,
+    Flag,
+}
+
+fn short_option(c: char) -> CurlOption {
+    match c {
+        'X' => CurlOption::Request,
+        'd' | 'F' => CurlOption::Data,
+        'T' => CurlOption::Upload,
+        'I' => CurlOption::Head,
+        'G' => CurlOption::Get,
+        'K' => CurlOption::Config,
+        ':' => CurlOption::Next,
+        'H' | 'o' | 'u' | 'A' | 'e' | 'b' | 'c' | 'm' | 'w' | 'x' | 'r' | 'E' | 'U' | 'y'
+        | 'Y' | 'z' | 'C' | 'P' | 'Q' | 't' => CurlOption::OtherWithValue,
+        _ => CurlOption::Flag,
+    }
+}
+
+fn long_option(name: &str) -> CurlOption {
+    match name {
+        "request" => CurlOption::Request,
+        "data" | "data-raw" | "data-binary" | "data-ascii" | "data-urlencode" | "json" | "form"
+        | "form-string" => CurlOption::Data,
+        "upload-file" => CurlOption::Upload,
+        "head" => CurlOption::Head,
+        "get" => CurlOption::Get,
+        "url" => CurlOption::Url,
+        "config" => CurlOption::Config,
+        "next" => CurlOption::Next,
+        "header" | "output" | "user" | "user-agent" | "referer" | "cookie" | "cookie-jar"
+        | "max-time" | "connect-timeout" | "write-out" | "proxy" | "range" | "retry"
+        | "cert" | "key" | "cacert" | "resolve" | "proxy-user" | "oauth2-bearer" | "variable"
+        | "url-query" => CurlOption::OtherWithValue,
+        _ => CurlOption::Flag,
+    }
+}
+
+impl CurlRequest {
+    /// Work out the method, URL and body from curl's arguments, the way curl does
+    pub fn parse(args: &[String]) -> Self {
+        let mut method = None;
+        let mut url = None;
+        let mut data = Vec::new();
+        let mut upload = false;
+        let mut head = false;
+        let mut get = false;
+        let mut opaque = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // The option and its value, if it was attached to it
+            let (option, attached) = if arg == "--" || !arg.starts_with('-') || arg == "-" {
+                if url.is_none() && arg != "--" {
+                    url = Some(arg.clone());
+                }
+                continue;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                match long.split_once('=') {
+                    Some((name, value)) => (long_option(name), Some(value.to_string())),
+                    None => (long_option(long), None),
+                }
+            } else {
+                // Short options can be combined, e.g. `-sX POST` or `-sXPOST`
+                let cluster: Vec<char> = arg[1..].chars().collect();
+                let mut found = (CurlOption::Flag, None);
+                for (i, c) in cluster.iter().enumerate() {
+                    match short_option(*c) {
+                        CurlOption::Flag => {}
+                        CurlOption::Head => head = true,
+                        CurlOption::Get => get = true,
+                        CurlOption::Next => opaque = true,
+                        option => {
+                            let rest: String = cluster[i + 1..].iter().collect();
+                            found = (option, Some(rest).filter(|rest| !rest.is_empty()));
+                            break;
+                        }
+                    }
+                }
+                found
+            };
+
+            let mut value = || attached.clone().or_else(|| args.next().cloned());
+            match option {
+                CurlOption::Request => method = value().map(|m| m.to_uppercase()),
+                CurlOption::Data => data.extend(value()),
+                CurlOption::Upload => {
+                    upload = true;
+                    value();
+                }
+                CurlOption::Url => {
+                    let value = value();
+                    if url.is_none() {
+                        url = value;
+                    }
+                }
+                CurlOption::Config => {
+                    opaque = true;
+                    value();
+                }
+                CurlOption::Next => opaque = true,
+                CurlOption::OtherWithValue => {
+                    value();
+                }
+                CurlOption::Head => head = true,
+                CurlOption::Get => get = true,
+                CurlOption::Flag => {}
+            }
+        }
+
+        let method = method.unwrap_or_else(|| {
+            let method = if head {
+                "HEAD"
+            } else if get {
+                "GET"
+            } else if upload {
+                "PUT"
+            } else if !data.is_empty() {
+                "POST"
+            } else {
+                "GET"
+            };
+            method.to_string()
+        });
+        Self {
+            method,
+            url,
+            body: Some(data.join("&")).filter(|body| !body.is_empty()),
+            opaque,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        !self.opaque && READ_ONLY_METHODS.contains(&self.method.as_str())
+    }
+}
+
+/// What the shim does with a `latchkey` call
+#[derive(Debug, PartialEq)]
+enum ShimAction {
+    /// Run the real latchkey
+    Pass,
+    /// Hold the request back for the session with this ID
+    Propose(String),
+    /// Send nothing, as it isn't clear which session the request belongs to
+    Refuse(&'static str),
+}
+
+/// Dry run state shared by the app and the shim: which sessions are in dry run mode,
+/// which workspaces they are processing a message in, and the requests held back in them.
+pub struct DryRunDir {
+    root: PathBuf,
+}
+
+/// Marks a session as processing a message until dropped
+pub struct RunningSession<'a> {
+    dry_run: &'a DryRunDir,
+    session_id: String,
+}
+
+impl Drop for RunningSession<'_> {
+    fn drop(&mut self) {
+        let marker = self.dry_run.session_dir(&self.session_id).join("workspace");
+        if let Err(e) = fs::remove_file(&marker) {
+            eprintln!("[dry-run] Failed to remove {:?}: {}", marker, e);
+        }
+    }
+}
+
+impl DryRunDir {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Directory holding the `latchkey` shim and the other native tools, to be put in `PATH`
+    /// instead of native_tools, so that the real latchkey can't be run around the shim
+    pub fn shim_dir(&self) -> PathBuf {
+        self.root.join(SHIM_DIR)
+    }
+
+    /// Write a `latchkey` script that runs this app binary as the shim, with the dry run
+    /// directory baked in rather than passed through the agent's environment, and link
+    /// the other tools in `native_tools` next to it. The shim finds the real latchkey itself.
+    pub fn install_shim(&self, native_tools: &Path) -> Result<(), String> {
+        let exe = env::current_exe().map_err(|e| format!("Failed to find the app binary: {}", e))?;
+        let dir = self.shim_dir();
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join("latchkey");
+            let quote = |path: &Path| format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"));
+            let script = format!(
+                "#!/bin/sh\nexec {} {} {} \"$@\"\n",
+                quote(&exe),
+                SHIM_FLAG,
+                quote(&self.root)
+            );
+            fs::write(&path, script).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .map_err(|e| format!("Failed to make {:?} executable: {}", path, e))?;
+            link_native_tools(native_tools, &dir)?;
+        }
+        #[cfg(windows)]
+        {
+            let _ = native_tools;
+            let path = dir.join("latchkey.cmd");
+            let script = format!("@\"{}\" {} \"{}\" %*\r\n", exe.display(), SHIM_FLAG, self.root.display());
+            fs::write(&path, script).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        }
+        Ok(())
+    }
+
+    fn session_dir(&self, session_id: &str) -> PathBuf {
+        self.root.join("dry_run").join(session_id)
+    }
+
+    pub fn set_enabled(&self, session_id: &str, enabled: bool) -> Result<(), String> {
+        let marker = self.session_dir(session_id).join("enabled");
+        if enabled {
+            fs::create_dir_all(self.session_dir(session_id))
+                .and_then(|_| fs::write(&marker, ""))
+                .map_err(|e| format!("Failed to write {:?}: {}", marker, e))
+        } else if marker.exists() {
+            fs::remove_file(&marker).map_err(|e| format!("Failed to remove {:?}: {}", marker, e))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn is_enabled(&self, session_id: &str) -> bool {
+        self.session_dir(session_id).join("enabled").exists()
+    }
+
+    fn any_enabled(&self) -> bool {
+        fs::read_dir(self.root.join("dry_run"))
+            .map(|entries| entries.flatten().any(|entry| entry.path().join("enabled").exists()))
+            .unwrap_or(false)
+    }
+
+    /// Record that a session is processing a message in `workspace`, so the shim can tell
+    /// which session a request belongs to, also when all sessions share one workspace
+    pub fn start_run(&self, session_id: &str, workspace: &Path) -> Result<RunningSession<'_>, String> {
+        let workspace = workspace
+            .canonicalize()
+            .map_err(|e| format!("Failed to find workspace {:?}: {}", workspace, e))?;
+        let marker = self.session_dir(session_id).join("workspace");
+        fs::create_dir_all(self.session_dir(session_id))
+            .and_then(|_| fs::write(&marker, workspace.to_string_lossy().as_bytes()))
+            .map_err(|e| format!("Failed to write {:?}: {}", marker, e))?;
+        Ok(RunningSession {
+            dry_run: self,
+            session_id: session_id.to_string(),
+        })
+    }
+
+    /// Sessions processing a message in a workspace that contains `dir`
+    fn running_sessions(&self, dir: &Path) -> Vec<String> {
+        let (Ok(dir), Ok(entries)) = (dir.canonicalize(), fs::read_dir(self.root.join("dry_run"))) else {
+            return Vec::new();
+        };
+        let mut sessions: Vec<String> = entries
+            .flatten()
+            .filter(|entry| {
+                fs::read_to_string(entry.path().join("workspace"))
+                    .is_ok_and(|workspace| dir.starts_with(workspace))
+            })
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    /// Whether to hold back a request the agent made from `dir`
+    fn shim_action(&self, dir: Option<&Path>, request: &CurlRequest) -> ShimAction {
+        if request.is_read_only() {
+            return ShimAction::Pass;
+        }
+        let sessions = dir.map(|dir| self.running_sessions(dir)).unwrap_or_default();
+        match sessions.as_slice() {
+            [session_id] if self.is_enabled(session_id) => ShimAction::Propose(session_id.clone()),
+            [_] => ShimAction::Pass,
+            // Err on the side of caution outside of a workspace with a message in flight
+            [] if self.any_enabled() => {
+                ShimAction::Refuse("are only proposed from within the conversation's workspace")
+            }
+            [] => ShimAction::Pass,
+            // Conversations sharing a workspace can't be told apart
+            _ if sessions.iter().any(|id| self.is_enabled(id)) => {
+                ShimAction::Refuse("can't be proposed while several conversations are working in the workspace")
+            }
+            _ => ShimAction::Pass,
+        }
+    }
+
+    /// Hold back a request until the user confirms it
+    pub fn propose(&self, session_id: &str, args: &[String]) -> Result<ProposedRequest, String> {
+        let request = CurlRequest::parse(&args[1..]);
+        let proposal = ProposedRequest {
+            session_id: session_id.to_string(),
+            id: uuid::Uuid::new_v4().to_string(),
+            method: request.method,
+            url: request.url,
+            body: request.body,
+            args: args.to_vec(),
+            created_at: now_millis(),
+        };
+        let dir = self.session_dir(session_id).join("proposals");
+        let path = dir.join(format!("{}.json", proposal.id));
+        let content = serde_json::to_string_pretty(&proposal)
+            .map_err(|e| format!("Failed to serialize proposed request: {}", e))?;
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, content))
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        Ok(proposal)
+    }
+
+    /// Requests waiting for confirmation in a session, oldest first
+    pub fn proposals(&self, session_id: &str) -> Result<Vec<ProposedRequest>, String> {
+        let dir = self.session_dir(session_id).join("proposals");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&dir).map_err(|e| format!("Failed to read {:?}: {}", dir, e))?;
+        let mut proposals = Vec::new();
+        for entry in entries.flatten() {
+            let content = fs::read_to_string(entry.path())
+                .map_err(|e| format!("Failed to read {:?}: {}", entry.path(), e))?;
+            match serde_json::from_str::<ProposedRequest>(&content) {
+                Ok(proposal) => proposals.push(proposal),
+                Err(e) => eprintln!("[dry-run] Skipping invalid proposal {:?}: {}", entry.path(), e),
+            }
+        }
+        proposals.sort_by_key(|p| p.created_at);
+        Ok(proposals)
+    }
+
+    /// Remove a proposed request, returning it
+    pub fn take(&self, session_id: &str, id: &str) -> Result<ProposedRequest, String> {
+        let proposal = self
+            .proposals(session_id)?
+            .into_iter()
+            .find(|p| p.id == id)
+            .ok_or_else(|| format!("No proposed request {}", id))?;
+        let path = self
+            .session_dir(session_id)
+            .join("proposals")
+            .join(format!("{}.json", proposal.id));
+        fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+        Ok(proposal)
+    }
+
+    /// Forget a deleted session
+    pub fn remove_session(&self, session_id: &str) -> Result<(), String> {
+        let dir = self.session_dir(session_id);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove {:?}: {}", dir, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Link every tool in `native_tools` but latchkey into the shim directory
+#[cfg(unix)]
+fn link_native_tools(native_tools: &Path, shim_dir: &Path) -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(native_tools) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name == "latchkey" || !entry.path().is_file() {
+            continue;
+        }
+        let link = shim_dir.join(&name);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link).map_err(|e| format!("Failed to remove {:?}: {}", link, e))?;
+        }
+        std::os::unix::fs::symlink(entry.path(), &link)
+            .map_err(|e| format!("Failed to link {:?}: {}", link, e))?;
+    }
+    Ok(())
+}
+
+/// The latchkey binary the shim stands in for: the one in native_tools, or else
+/// the first one in PATH that isn't the shim itself
+fn real_latchkey(native_tools: &Path, root: &Path) -> PathBuf {
+    let bundled = native_tools.join("latchkey");
+    if bundled.is_file() {
+        return bundled;
+    }
+    let shim_dir = root.join(SHIM_DIR);
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .filter(|dir| *dir != shim_dir)
+        .map(|dir| dir.join("latchkey"))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from("latchkey"))
+}
+
+/// Run as `latchkey`: hold back requests that would change something in sessions
+/// in dry run mode, and pass everything else on to the real latchkey.
+/// `args` start with the dry run directory. Returns the exit code.
+pub fn run_shim(native_tools: &Path, args: &[String]) -> i32 {
+    run_shim_in(native_tools, args, env::current_dir().ok().as_deref())
+}
+
+/// `run_shim` with the agent working in `dir`
+fn run_shim_in(native_tools: &Path, args: &[String], dir: Option<&Path>) -> i32 {
+    let Some((root, args)) = args.split_first() else {
+        eprintln!("The latchkey shim needs the dry run directory");
+        return 1;
+    };
+    let root = PathBuf::from(root);
+    if args.first().map(String::as_str) == Some("curl") {
+        let request = CurlRequest::parse(&args[1..]);
+        let dry_run = DryRunDir::new(&root);
+        let action = if root.is_dir() {
+            dry_run.shim_action(dir, &request)
+        } else if request.is_read_only() {
+            ShimAction::Pass
+        } else {
+            // Without the app's state it can't be told whether the session is in dry run mode
+            ShimAction::Refuse("are not sent while the app's dry run state is missing")
+        };
+        let session_id = match action {
+            ShimAction::Pass => None,
+            ShimAction::Propose(session_id) => Some(session_id),
+            ShimAction::Refuse(reason) => {
+                eprintln!("Dry run: {} requests {}. Nothing was sent.", request.method, reason);
+                return 1;
+            }
+        };
+        if let Some(session_id) = session_id {
+            return match dry_run.propose(&session_id, args) {
+                Ok(proposal) => {
+                    println!(
+                        "{} {} request to {} was NOT sent. It was shown to the user, \
+                         who can send it themselves. Don't retry it; tell the user what it would do.",
+                        HELD_BACK_NOTICE,
+                        proposal.method,
+                        proposal.url.as_deref().unwrap_or(UNKNOWN_URL)
+                    );
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Dry run: failed to record the request, nothing was sent: {}", e);
+                    1
+                }
+            };
+        }
+    }
+
+    match Command::new(real_latchkey(native_tools, &root)).args(args).status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("Failed to run latchkey: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> CurlRequest {
+        CurlRequest::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_curl_methods() {
+        let request = parse(&[
+            "-X",
+            "POST",
+            "https://slack.com/api/conversations.create",
+            "-H",
+            "Content-Type: application/json",
+            "-d",
+            r#"{"name":"my-channel"}"#,
+        ]);
+        assert_eq!(
+            request,
+            CurlRequest {
+                method: "POST".to_string(),
+                url: Some("https://slack.com/api/conversations.create".to_string()),
+                body: Some(r#"{"name":"my-channel"}"#.to_string()),
+                opaque: false,
+            }
+        );
+        assert!(!request.is_read_only());
+
+        // Header values aren't mistaken for the URL
+        let request = parse(&["-s", "-H", "Accept: application/json", "https://discord.com/api/v10/users/@me"]);
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.url.as_deref(), Some("https://discord.com/api/v10/users/@me"));
+        assert!(request.is_read_only());
+
+        assert_eq!(parse(&["-sXdelete", "https://api.github.com/x"]).method, "DELETE");
+        assert_eq!(parse(&["--request=PATCH", "--url", "https://a"]).url.as_deref(), Some("https://a"));
+        assert_eq!(parse(&["--data-raw", "a=1", "https://a"]).method, "POST");
+        assert_eq!(parse(&["-G", "-d", "q=1", "https://a"]).method, "GET");
+        assert_eq!(parse(&["-T", "file.txt", "https://a"]).method, "PUT");
+        assert_eq!(parse(&["-I", "https://a"]).method, "HEAD");
+
+        // Config files and further requests may set any method and body
+        for args in [
+            &["-K", "request.txt", "https://a"][..],
+            &["-sKrequest.txt", "https://a"],
+            &["--config", "request.txt", "https://a"],
+            &["--config=request.txt", "https://a"],
+            &["https://a", "--next", "-X", "DELETE", "https://b"],
+            &["https://a", "-:", "-d", "x=1", "https://b"],
+        ] {
+            assert!(!parse(args).is_read_only(), "{:?}", args);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shim_hides_the_real_latchkey() {
+        let dir = tempfile::tempdir().unwrap();
+        let native_tools = dir.path().join("native_tools");
+        fs::create_dir_all(native_tools.join("playwright_browsers")).unwrap();
+        fs::write(native_tools.join("latchkey"), "").unwrap();
+        fs::write(native_tools.join("rg"), "").unwrap();
+
+        let dry_run = DryRunDir::new(&dir.path().join("dry_run"));
+        dry_run.install_shim(&native_tools).unwrap();
+        // Installing again at the next start replaces the links
+        dry_run.install_shim(&native_tools).unwrap();
+
+        let shim_dir = dry_run.shim_dir();
+        let script = fs::read_to_string(shim_dir.join("latchkey")).unwrap();
+        assert!(script.contains(&format!("{} '{}'", SHIM_FLAG, dir.path().join("dry_run").display())));
+        assert!(!script.contains("native_tools"));
+        assert_eq!(fs::read_link(shim_dir.join("rg")).unwrap(), native_tools.join("rg"));
+        assert!(!shim_dir.join("playwright_browsers").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shim_ignores_the_environment() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let native_tools = dir.path().join("native_tools");
+        fs::create_dir_all(&native_tools).unwrap();
+        let calls = dir.path().join("calls");
+        let latchkey = native_tools.join("latchkey");
+        fs::write(&latchkey, format!("#!/bin/sh\necho \"$*\" >> '{}'\n", calls.display())).unwrap();
+        fs::set_permissions(&latchkey, fs::Permissions::from_mode(0o755)).unwrap();
+        let workspace = dir.path().join("workspace");
+        fs::create_dir_all(&workspace).unwrap();
+
+        let root = dir.path().join("dry_run");
+        let dry_run = DryRunDir::new(&root);
+        dry_run.set_enabled("session-1", true).unwrap();
+        let _run = dry_run.start_run("session-1", &workspace).unwrap();
+
+        // What `env -u PASSEPARTOUT_DRY_RUN_ROOT latchkey curl ...` did before the
+        // directory was baked into the shim
+        env::remove_var("PASSEPARTOUT_DRY_RUN_ROOT");
+        let shim = |root: &Path, args: &[&str]| {
+            let mut all = vec![root.to_string_lossy().to_string()];
+            all.extend(args.iter().map(|a| a.to_string()));
+            run_shim_in(&native_tools, &all, Some(&workspace))
+        };
+        let delete = ["curl", "-X", "DELETE", "https://api.github.com/repos/o/r"];
+        assert_eq!(shim(&root, &delete), 0);
+        assert_eq!(dry_run.proposals("session-1").unwrap().len(), 1);
+        assert!(!calls.exists());
+
+        // Without the dry run state only requests that can't change anything are sent
+        let missing = dir.path().join("missing");
+        assert_eq!(shim(&missing, &delete), 1);
+        assert!(!calls.exists());
+        assert_eq!(shim(&missing, &["curl", "https://api.github.com/user"]), 0);
+        assert_eq!(fs::read_to_string(&calls).unwrap(), "curl https://api.github.com/user\n");
+        assert_eq!(run_shim_in(&native_tools, &[], Some(&workspace)), 1);
+    }
+
+    #[test]
+    fn test_proposals_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("sessions").join("session-1");
+        fs::create_dir_all(workspace.join("notes")).unwrap();
+
+        let dry_run = DryRunDir::new(dir.path());
+        let _run = dry_run.start_run("session-1", &workspace).unwrap();
+        assert_eq!(dry_run.running_sessions(&workspace.join("notes")), vec!["session-1"]);
+        assert!(dry_run.running_sessions(dir.path()).is_empty());
+
+        assert!(!dry_run.any_enabled());
+        dry_run.set_enabled("session-1", true).unwrap();
+        assert!(dry_run.is_enabled("session-1"));
+        assert!(dry_run.any_enabled());
+
+        let args: Vec<String> = ["curl", "-X", "DELETE", "https://api.github.com/repos/o/r"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let proposal = dry_run.propose("session-1", &args).unwrap();
+        assert_eq!(proposal.method, "DELETE");
+        assert_eq!(dry_run.proposals("session-1").unwrap(), vec![proposal.clone()]);
+        assert!(dry_run.proposals("session-2").unwrap().is_empty());
+
+        assert_eq!(dry_run.take("session-1", &proposal.id).unwrap().args, args);
+        assert!(dry_run.take("session-1", &proposal.id).is_err());
+
+        dry_run.set_enabled("session-1", false).unwrap();
+        assert!(!dry_run.is_enabled("session-1"));
+    }
+
+    #[test]
+    fn test_shim_in_a_shared_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("workspaces").join("shared");
+        fs::create_dir_all(shared.join("reports")).unwrap();
+        let dry_run = DryRunDir::new(&dir.path().join("dry_run"));
+        dry_run.set_enabled("session-a", true).unwrap();
+        let post = parse(&["-X", "POST", "https://slack.com/api/chat.postMessage"]);
+        let get = parse(&["https://slack.com/api/conversations.list"]);
+        let from = |path: &Path| dry_run.shim_action(Some(path), &post);
+
+        // Both sessions work in the same directory, so the running message tells them apart
+        {
+            let _run = dry_run.start_run("session-a", &shared).unwrap();
+            assert_eq!(from(&shared.join("reports")), ShimAction::Propose("session-a".to_string()));
+            assert_eq!(dry_run.shim_action(Some(&shared), &get), ShimAction::Pass);
+        }
+        {
+            let _run = dry_run.start_run("session-b", &shared).unwrap();
+            assert_eq!(from(&shared), ShimAction::Pass);
+
+            let _other = dry_run.start_run("session-a", &shared).unwrap();
+            assert!(matches!(from(&shared), ShimAction::Refuse(_)));
+        }
+        // Once no message is in flight, nothing is sent while a session is in dry run mode
+        assert!(dry_run.running_sessions(&shared).is_empty());
+        assert!(matches!(from(&shared), ShimAction::Refuse(_)));
+        assert!(matches!(dry_run.shim_action(None, &post), ShimAction::Refuse(_)));
+        dry_run.set_enabled("session-a", false).unwrap();
+        assert_eq!(from(&shared), ShimAction::Pass);
+    }
+}
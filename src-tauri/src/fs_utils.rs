@@ -1,25 +1,39 @@
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 
-/// Copy contents of a directory into another directory
-pub fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), String> {
-    let entries = fs::read_dir(src)
-        .map_err(|e| format!("Failed to read directory {:?}: {}", src, e))?;
+/// Write a file by writing a sibling temp file and renaming it into place,
+/// so readers never observe a partially written file
+pub fn write_file_atomic(path: &Path, content: &[u8]) -> Result<(), String> {
+    write_file_atomic_with_mode(path, content, None)
+}
+
+/// Like `write_file_atomic`, but on Unix the file gets `mode`, e.g. 0o600 for files
+/// only their owner may read. The mode is set before any content is written.
+pub fn write_file_atomic_with_mode(path: &Path, content: &[u8], mode: Option<u32>) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory {:?}: {}", parent, e))?;
+    }
 
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let path = entry.path();
-        let dest_path = dst.join(entry.file_name());
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
 
-        if path.is_dir() {
-            fs::create_dir_all(&dest_path)
-                .map_err(|e| format!("Failed to create directory {:?}: {}", dest_path, e))?;
-            copy_dir_contents(&path, &dest_path)?;
-        } else {
-            fs::copy(&path, &dest_path)
-                .map_err(|e| format!("Failed to copy {:?} to {:?}: {}", path, dest_path, e))?;
-        }
+    let mut file =
+        fs::File::create(&temp_path).map_err(|e| format!("Failed to create {:?}: {}", temp_path, e))?;
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(mode))
+            .map_err(|e| format!("Failed to set permissions of {:?}: {}", temp_path, e))?;
     }
+    #[cfg(not(unix))]
+    let _ = mode;
+    file.write_all(content)
+        .map_err(|e| format!("Failed to write {:?}: {}", temp_path, e))?;
+    drop(file);
 
-    Ok(())
+    fs::rename(&temp_path, path)
+        .map_err(|e| format!("Failed to move {:?} into place: {}", path, e))
 }
@@ -0,0 +1,196 @@
+use crate::providers::{KeyAuth, ProviderInfo};
+use serde::Serialize;
+use std::time::Duration;
+
+/// How long to wait for a provider before reporting a network error
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// API version sent to Anthropic, which requires one on every request
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Outcome of checking an API key against the provider
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum KeyValidation {
+    Valid,
+    /// The provider rejected the key
+    Invalid { message: String },
+    /// The provider couldn't be reached or failed, so the key may still be fine
+    NetworkError { message: String },
+    /// The key was accepted but has hit its rate limit
+    RateLimited,
+}
+
+/// Check an API key by listing the provider's models, which is free and needs authentication
+pub async fn validate_key(provider: &ProviderInfo, api_key: &str) -> Result<KeyValidation, String> {
+    let base_url = provider
+        .api_base_url()
+        .ok_or_else(|| format!("No API endpoint is known for provider {}", provider.id))?;
+    let url = format!("{}/models", base_url);
+
+    let client = reqwest::Client::builder()
+        .timeout(VALIDATION_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let request = match provider.auth {
+        KeyAuth::Bearer => client.get(&url).bearer_auth(api_key),
+        KeyAuth::Anthropic => client
+            .get(&url)
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION),
+        KeyAuth::Google => client.get(&url).header("x-goog-api-key", api_key),
+    };
+
+    println!("[credentials] Validating API key for {} against {}", provider.id, url);
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(KeyValidation::NetworkError {
+                message: format!("Could not reach {}: {}", base_url, e),
+            })
+        }
+    };
+
+    let status = response.status().as_u16();
+    let body = response.text().await.unwrap_or_default();
+    Ok(classify_response(status, &body))
+}
+
+fn classify_response(status: u16, body: &str) -> KeyValidation {
+    match status {
+        200..=299 => KeyValidation::Valid,
+        429 => KeyValidation::RateLimited,
+        // Google answers 400 for malformed keys
+        400 | 401 | 403 => KeyValidation::Invalid {
+            message: error_message(body).unwrap_or_else(|| format!("Rejected with status {}", status)),
+        },
+        _ => KeyValidation::NetworkError {
+            message: format!("Provider responded with status {}", status),
+        },
+    }
+}
+
+/// The error message in a provider's JSON error response, e.g. `{"error": {"message": "..."}}`
+fn error_message(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    let error = value.get("error")?;
+    error
+        .get("message")
+        .or(Some(error))
+        .and_then(|message| message.as_str())
+        .map(|message| message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Answer one request with a fixed status and body. Returns the base URL
+    /// and a receiver for the request's header lines.
+    fn spawn_stub_server(status: u16, body: &'static str) -> (String, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                head.push(line.trim_end().to_lowercase());
+                line.clear();
+            }
+            let _ = tx.send(head);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        });
+        (base_url, rx)
+    }
+
+    fn provider(auth: KeyAuth, base_url: String) -> ProviderInfo {
+        ProviderInfo {
+            id: "stub".to_string(),
+            display_name: "Stub".to_string(),
+            base_url: Some(base_url),
+            auth,
+            ..Default::default()
+        }
+    }
+
+    fn validate(provider: &ProviderInfo, api_key: &str) -> KeyValidation {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(validate_key(provider, api_key))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_valid_key_is_sent_to_models_endpoint() {
+        let (base_url, head) = spawn_stub_server(200, r#"{"data": []}"#);
+        let result = validate(&provider(KeyAuth::Anthropic, base_url), "sk-ant-good");
+        assert_eq!(result, KeyValidation::Valid);
+
+        let head = head.recv().unwrap();
+        assert_eq!(head[0], "get /v1/models http/1.1");
+        assert!(head.contains(&"x-api-key: sk-ant-good".to_string()));
+        assert!(head.contains(&format!("anthropic-version: {}", ANTHROPIC_VERSION)));
+    }
+
+    #[test]
+    fn test_rejected_key_is_invalid() {
+        let (base_url, head) = spawn_stub_server(
+            401,
+            r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error"}}"#,
+        );
+        let result = validate(&provider(KeyAuth::Bearer, base_url), "sk-typo");
+        assert_eq!(
+            result,
+            KeyValidation::Invalid {
+                message: "Incorrect API key provided".to_string()
+            }
+        );
+        assert!(head.recv().unwrap().contains(&"authorization: bearer sk-typo".to_string()));
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let (base_url, _head) = spawn_stub_server(429, "{}");
+        let result = validate(&provider(KeyAuth::Google, base_url), "AIza");
+        assert_eq!(result, KeyValidation::RateLimited);
+    }
+
+    #[test]
+    fn test_unreachable_server_is_network_error() {
+        // Bind and drop a listener to get a port nobody listens on
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let result = validate(
+            &provider(KeyAuth::Bearer, format!("http://127.0.0.1:{}/v1", port)),
+            "sk",
+        );
+        assert!(matches!(result, KeyValidation::NetworkError { .. }));
+    }
+
+    #[test]
+    fn test_classify_server_errors() {
+        assert!(matches!(
+            classify_response(503, ""),
+            KeyValidation::NetworkError { .. }
+        ));
+        assert_eq!(
+            classify_response(400, r#"{"error": "API key not valid"}"#),
+            KeyValidation::Invalid {
+                message: "API key not valid".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,659 @@
+use crate::audit_log::response_status;
+use crate::paths::AppPaths;
+use crate::process_utils::{isolate_process_group, kill_process_tree};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+/// How long quick subcommands such as `status` may take
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `ensure-browser` may take, which downloads a browser on first use
+const ENSURE_BROWSER_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// How long a `curl` request may take, including a login in the browser
+const CURL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long the user has to finish logging in to a service in the browser
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Errors from running latchkey that the frontend needs to tell apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum LatchkeyError {
+    /// The latchkey binary is neither in native_tools nor in PATH
+    NotInstalled,
+    /// The subcommand didn't finish in time and was killed
+    TimedOut { command: String, seconds: u64 },
+    /// The subcommand exited with an error
+    Failed { command: String, message: String },
+    /// Service names are passed as arguments, so only plain names are allowed
+    InvalidService(String),
+    /// The user stopped the subcommand
+    Cancelled,
+    Other(String),
+}
+
+impl LatchkeyError {
+    fn kind(&self) -> &'static str {
+        match self {
+            LatchkeyError::NotInstalled => "not-installed",
+            LatchkeyError::TimedOut { .. } => "timed-out",
+            LatchkeyError::Failed { .. } => "failed",
+            LatchkeyError::InvalidService(_) => "invalid-service",
+            LatchkeyError::Cancelled => "cancelled",
+            LatchkeyError::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for LatchkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatchkeyError::NotInstalled => write!(
+                f,
+                "latchkey was not found. Put it in native_tools or on your PATH."
+            ),
+            LatchkeyError::TimedOut { command, seconds } => {
+                write!(f, "latchkey {} did not finish within {} seconds", command, seconds)
+            }
+            LatchkeyError::Failed { command, message } => {
+                write!(f, "latchkey {} failed: {}", command, message)
+            }
+            LatchkeyError::InvalidService(service) => write!(f, "Invalid service name: {:?}", service),
+            LatchkeyError::Cancelled => write!(f, "Cancelled"),
+            LatchkeyError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for LatchkeyError {
+    fn from(message: String) -> Self {
+        LatchkeyError::Other(message)
+    }
+}
+
+/// Sent to the frontend as `{ kind, message }`
+impl Serialize for LatchkeyError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LatchkeyError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Whether latchkey has working credentials for a service
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStatus {
+    /// Logged in, the credentials work
+    Valid,
+    /// Logged in, but the credentials were rejected or have expired
+    Invalid,
+    /// Not logged in yet. latchkey asks for a login on the next request.
+    Missing,
+    /// A status this version doesn't know, as latchkey printed it
+    Other(String),
+    /// `latchkey status` failed for this service
+    Error(LatchkeyError),
+}
+
+/// A service latchkey can make requests to
+#[derive(Debug, Clone, Serialize)]
+pub struct LatchkeyService {
+    pub name: String,
+    pub status: ServiceStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A line `latchkey ensure-browser` printed while it runs
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserProgress {
+    pub stream: OutputStream,
+    pub line: String,
+    /// How far the current download is, if the line is a progress bar
+    pub percent: Option<f32>,
+}
+
+/// What `latchkey ensure-browser` printed once the browser is ready
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserCheck {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// The result of a `latchkey curl` request. curl exiting with an error,
+/// e.g. because the host couldn't be reached, isn't a [`LatchkeyError`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CurlOutput {
+    pub exit_code: Option<i32>,
+    /// The HTTP status, if the arguments made curl print it
+    pub status: Option<u16>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CurlOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Runs latchkey subcommands with the tool paths and browsers of the app
+#[derive(Debug, Clone)]
+pub struct LatchkeyClient {
+    binary: PathBuf,
+    path_env: String,
+    playwright_browsers_path: PathBuf,
+}
+
+impl LatchkeyClient {
+    pub fn new(paths: &AppPaths) -> Self {
+        Self {
+            binary: paths.get_binary_path("latchkey"),
+            path_env: paths.get_path_env(),
+            playwright_browsers_path: paths.get_playwright_browsers_path(),
+        }
+    }
+
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    /// A latchkey command in its own process group, so that browsers it starts,
+    /// e.g. to log in, can be stopped along with it
+    pub fn command(&self) -> Command {
+        let mut cmd = std::process::Command::new(&self.binary);
+        cmd.env("PATH", &self.path_env)
+            .env("PLAYWRIGHT_BROWSERS_PATH", &self.playwright_browsers_path);
+        isolate_process_group(&mut cmd);
+        let mut cmd = Command::from(cmd);
+        cmd.kill_on_drop(true);
+        cmd
+    }
+
+    /// Run a subcommand to completion, killing it and everything it started after `limit`
+    async fn output(&self, mut cmd: Command, args: &[&str], limit: Duration) -> Result<Output, LatchkeyError> {
+        let command = args.first().copied().unwrap_or_default().to_string();
+        let child = cmd
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error)?;
+        let pid = child.id();
+        match timeout(limit, child.wait_with_output()).await {
+            Err(_) => {
+                if let Some(pid) = pid {
+                    kill_process_tree(pid)?;
+                }
+                Err(LatchkeyError::TimedOut {
+                    command,
+                    seconds: limit.as_secs(),
+                })
+            }
+            Ok(Err(e)) => Err(LatchkeyError::Other(format!("Failed to wait for latchkey: {}", e))),
+            Ok(Ok(output)) => Ok(output),
+        }
+    }
+
+    /// Run a subcommand and return its stdout, failing if it exits with an error
+    async fn run(&self, args: &[&str], limit: Duration) -> Result<String, LatchkeyError> {
+        let output = self.output(self.command(), args, limit).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+            return Err(LatchkeyError::Failed {
+                command: args.join(" "),
+                message: message.to_string(),
+            });
+        }
+        Ok(stdout)
+    }
+
+    /// Make sure the browser latchkey logs in with is installed, downloading it if needed.
+    /// Each line of output is passed to `on_progress` as it is printed, and the
+    /// download is stopped once `cancel` fires.
+    pub async fn ensure_browser<F>(
+        &self,
+        on_progress: F,
+        cancel: oneshot::Receiver<()>,
+    ) -> Result<BrowserCheck, LatchkeyError>
+    where
+        F: Fn(BrowserProgress) + Send + Sync + 'static,
+    {
+        let mut cmd = std::process::Command::new(&self.binary);
+        cmd.arg("ensure-browser")
+            .env("PATH", &self.path_env)
+            .env("PLAYWRIGHT_BROWSERS_PATH", &self.playwright_browsers_path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Playwright downloads in child processes that have to be stopped as well
+        isolate_process_group(&mut cmd);
+        let mut child = Command::from(cmd)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(spawn_error)?;
+        let pid = child.id();
+
+        let on_progress = Arc::new(on_progress);
+        let (stdout, stderr) = match (child.stdout.take(), child.stderr.take()) {
+            (Some(stdout), Some(stderr)) => (stdout, stderr),
+            _ => return Err(LatchkeyError::Other("Failed to capture latchkey output".to_string())),
+        };
+        let stdout_reader = tokio::spawn(read_progress(stdout, OutputStream::Stdout, on_progress.clone()));
+        let stderr_reader = tokio::spawn(read_progress(stderr, OutputStream::Stderr, on_progress));
+
+        let stopped = tokio::select! {
+            result = timeout(ENSURE_BROWSER_TIMEOUT, child.wait()) => match result {
+                Ok(status) => Ok(status),
+                Err(_) => Err(LatchkeyError::TimedOut {
+                    command: "ensure-browser".to_string(),
+                    seconds: ENSURE_BROWSER_TIMEOUT.as_secs(),
+                }),
+            },
+            Ok(()) = cancel => Err(LatchkeyError::Cancelled),
+        };
+        let status = match stopped {
+            Ok(status) => status.map_err(|e| LatchkeyError::Other(format!("Failed to wait for latchkey: {}", e)))?,
+            Err(e) => {
+                if let Some(pid) = pid {
+                    kill_process_tree(pid)?;
+                }
+                let _ = child.wait().await;
+                return Err(e);
+            }
+        };
+
+        let collect = |reader: tokio::task::JoinHandle<String>| async {
+            timeout(Duration::from_secs(5), reader)
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+        };
+        let stdout = collect(stdout_reader).await;
+        let stderr = collect(stderr_reader).await;
+        if !status.success() {
+            return Err(LatchkeyError::Failed {
+                command: "ensure-browser".to_string(),
+                message: if stderr.is_empty() { stdout } else { stderr },
+            });
+        }
+        Ok(BrowserCheck { stdout, stderr })
+    }
+
+    /// Names of the services latchkey supports, from `latchkey services list`
+    pub async fn list_services(&self) -> Result<Vec<String>, LatchkeyError> {
+        Ok(parse_services(&self.run(&["services", "list"], COMMAND_TIMEOUT).await?))
+    }
+
+    /// Every supported service with its status. A service whose status can't be read
+    /// gets an `Error` status rather than failing the whole list.
+    pub async fn services(&self) -> Result<Vec<LatchkeyService>, LatchkeyError> {
+        let mut services = Vec::new();
+        for name in self.list_services().await? {
+            let status = self
+                .service_status(&name)
+                .await
+                .unwrap_or_else(ServiceStatus::Error);
+            services.push(LatchkeyService { name, status });
+        }
+        Ok(services)
+    }
+
+    /// Whether latchkey has working credentials for a service, from `latchkey status <service>`
+    pub async fn service_status(&self, service: &str) -> Result<ServiceStatus, LatchkeyError> {
+        check_service_name(service)?;
+        Ok(parse_status(&self.run(&["status", service], COMMAND_TIMEOUT).await?))
+    }
+
+    /// Forget the credentials of a service with `latchkey clear <service>`
+    pub async fn clear_service(&self, service: &str) -> Result<(), LatchkeyError> {
+        check_service_name(service)?;
+        self.run(&["clear", service], COMMAND_TIMEOUT).await?;
+        Ok(())
+    }
+
+    /// Log in to a service with `latchkey auth browser <service>`, which opens a browser
+    /// window and returns once the user has logged in. Returns the status afterwards.
+    pub async fn login_service(&self, service: &str) -> Result<ServiceStatus, LatchkeyError> {
+        check_service_name(service)?;
+        self.run(&["auth", "browser", service], LOGIN_TIMEOUT).await?;
+        self.service_status(service).await
+    }
+
+    /// Make a request with `latchkey curl`. Relative paths in the arguments,
+    /// e.g. in `-d @body.json`, refer to `dir`.
+    pub async fn curl(&self, curl_args: &[String], dir: &Path) -> Result<CurlOutput, LatchkeyError> {
+        let mut cmd = self.command();
+        cmd.current_dir(dir);
+        let mut args = vec!["curl"];
+        args.extend(curl_args.iter().map(String::as_str));
+        let output = self.output(cmd, &args, CURL_TIMEOUT).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        Ok(CurlOutput {
+            exit_code: output.status.code(),
+            status: response_status(curl_args, &stdout),
+            stdout,
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+fn spawn_error(e: std::io::Error) -> LatchkeyError {
+    if e.kind() == ErrorKind::NotFound {
+        LatchkeyError::NotInstalled
+    } else {
+        LatchkeyError::Other(format!("Failed to run latchkey: {}", e))
+    }
+}
+
+/// Pass each line of `output` to `on_progress` and return all of them once it closes.
+/// Progress bars redraw themselves with `\r`, so that ends a line too.
+async fn read_progress<R, F>(output: R, stream: OutputStream, on_progress: Arc<F>) -> String
+where
+    R: AsyncRead + Unpin,
+    F: Fn(BrowserProgress),
+{
+    let mut lines = Vec::new();
+    let mut chunks = BufReader::new(output).split(b'\n');
+    while let Ok(Some(chunk)) = chunks.next_segment().await {
+        for line in String::from_utf8_lossy(&chunk).split('\r') {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            on_progress(BrowserProgress {
+                stream,
+                line: line.to_string(),
+                percent: parse_percent(line),
+            });
+            lines.push(line.to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+/// The last percentage in a line, e.g. 40 in `|■■■■      |  40% of 164.7 MiB`
+fn parse_percent(line: &str) -> Option<f32> {
+    line.split_whitespace()
+        .rev()
+        .filter_map(|word| word.trim_start_matches('|').strip_suffix('%'))
+        .filter_map(|number| number.parse::<f32>().ok())
+        .find(|percent| (0.0..=100.0).contains(percent))
+}
+
+/// Service names are passed to latchkey as arguments, so only allow plain names
+fn check_service_name(service: &str) -> Result<(), LatchkeyError> {
+    let valid = !service.is_empty()
+        && !service.starts_with('-')
+        && service
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(LatchkeyError::InvalidService(service.to_string()))
+    }
+}
+
+/// One service per line, possibly as a bullet list or followed by a description
+fn parse_services(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*', '•']).trim())
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.trim_end_matches(':').to_string())
+        .filter(|name| check_service_name(name).is_ok())
+        .collect()
+}
+
+fn parse_status(output: &str) -> ServiceStatus {
+    let status = output.trim().to_lowercase();
+    // Only the last word matters, in case the service name is printed first
+    match status.split_whitespace().last().unwrap_or_default() {
+        "valid" => ServiceStatus::Valid,
+        "invalid" | "expired" => ServiceStatus::Invalid,
+        "missing" | "none" | "unknown" => ServiceStatus::Missing,
+        _ => ServiceStatus::Other(output.trim().to_string()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A latchkey that knows two services, is logged in to slack and logs out on `clear`
+    const FAKE_LATCHKEY: &str = r#"#!/bin/sh
+state="$(dirname "$0")/logged_in"
+case "$1" in
+  services)
+    printf 'slack\ngithub\n'
+    if [ -e "$(dirname "$0")/broken" ]; then echo discord; fi ;;
+  status)
+    case "$2" in
+      slack) if [ -e "$state" ]; then echo valid; else echo missing; fi ;;
+      github) echo invalid ;;
+      *) echo "Unknown service: $2" >&2; exit 2 ;;
+    esac ;;
+  clear) rm -f "$state" ;;
+  auth)
+    case "$3" in
+      slack) touch "$state" ;;
+      *) echo "Login to $3 was closed" >&2; exit 1 ;;
+    esac ;;
+  ensure-browser)
+    echo "Downloading Chromium"
+    printf '|■■      |  20%% of 10 MiB\r|■■■■■■■■| 100%% of 10 MiB\n'
+    if [ -e "$(dirname "$0")/slow" ]; then sleep 5; fi
+    echo "Browser is ready"; echo "Using $PLAYWRIGHT_BROWSERS_PATH" >&2 ;;
+  curl) shift; echo "{\"args\":\"$*\",\"dir\":\"$(basename "$PWD")\"}"; echo 201 ;;
+  hang)
+    # Like a login, which leaves a browser running in the background
+    sleep 5 & echo $! > "$(dirname "$0")/browser_pid"; wait ;;
+esac
+"#;
+
+    fn fake_client(dir: &Path) -> LatchkeyClient {
+        let latchkey = dir.join("latchkey");
+        fs::write(&latchkey, FAKE_LATCHKEY).unwrap();
+        fs::set_permissions(&latchkey, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("logged_in"), "").unwrap();
+        LatchkeyClient::new(&AppPaths {
+            native_tools_path: dir.to_path_buf(),
+            opencode_workspace_path: dir.join("workspace"),
+            app_data_path: dir.join("data"),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_services_status_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+
+        assert_eq!(client.list_services().await.unwrap(), vec!["slack", "github"]);
+        assert_eq!(client.service_status("slack").await.unwrap(), ServiceStatus::Valid);
+        assert_eq!(client.service_status("github").await.unwrap(), ServiceStatus::Invalid);
+
+        client.clear_service("slack").await.unwrap();
+        assert_eq!(client.service_status("slack").await.unwrap(), ServiceStatus::Missing);
+
+        let error = client.service_status("discord").await.unwrap_err();
+        assert_eq!(
+            error,
+            LatchkeyError::Failed {
+                command: "status discord".to_string(),
+                message: "Unknown service: discord".to_string(),
+            }
+        );
+        assert_eq!(
+            client.service_status("--help").await.unwrap_err(),
+            LatchkeyError::InvalidService("--help".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_services_with_a_failing_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+        fs::write(dir.path().join("broken"), "").unwrap();
+
+        let services = client.services().await.unwrap();
+        let statuses: Vec<_> = services.iter().map(|s| (s.name.as_str(), &s.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("slack", &ServiceStatus::Valid),
+                ("github", &ServiceStatus::Invalid),
+                (
+                    "discord",
+                    &ServiceStatus::Error(LatchkeyError::Failed {
+                        command: "status discord".to_string(),
+                        message: "Unknown service: discord".to_string(),
+                    })
+                ),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&services[2]).unwrap(),
+            serde_json::json!({
+                "name": "discord",
+                "status": {"error": {"kind": "failed", "message": "latchkey status discord failed: Unknown service: discord"}}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+        client.clear_service("slack").await.unwrap();
+
+        assert_eq!(client.login_service("slack").await.unwrap(), ServiceStatus::Valid);
+        assert_eq!(client.service_status("slack").await.unwrap(), ServiceStatus::Valid);
+        assert_eq!(
+            client.login_service("github").await.unwrap_err(),
+            LatchkeyError::Failed {
+                command: "auth browser github".to_string(),
+                message: "Login to github was closed".to_string(),
+            }
+        );
+        assert_eq!(
+            client.login_service("../slack").await.unwrap_err(),
+            LatchkeyError::InvalidService("../slack".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ensure_browser_and_curl() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = progress.clone();
+        let (_cancel_tx, cancel_rx) = oneshot::channel();
+        let check = client
+            .ensure_browser(move |line| seen.lock().unwrap().push(line), cancel_rx)
+            .await
+            .unwrap();
+        assert!(check.stdout.starts_with("Downloading Chromium\n"));
+        assert!(check.stdout.ends_with("Browser is ready"));
+        assert!(check.stderr.ends_with("playwright_browsers"));
+        let progress = progress.lock().unwrap().clone();
+        assert_eq!(progress.len(), 5);
+        let percents: Vec<f32> = progress.iter().filter_map(|line| line.percent).collect();
+        assert_eq!(percents, vec![20.0, 100.0]);
+        assert!(progress
+            .iter()
+            .any(|line| line.stream == OutputStream::Stderr && line.line.starts_with("Using ")));
+
+        let workspace = dir.path().join("workspace");
+        fs::create_dir_all(&workspace).unwrap();
+        let args: Vec<String> = ["-s", "-w", "%{http_code}", "-X", "POST", "https://a"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let output = client.curl(&args, &workspace).await.unwrap();
+        assert!(output.success());
+        assert_eq!(output.status, Some(201));
+        assert!(output.stdout.contains("\"dir\":\"workspace\""));
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_and_missing_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+        let error = client
+            .run(&["hang"], Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, LatchkeyError::TimedOut { ref command, .. } if command == "hang"));
+        // The process it started is stopped too
+        #[cfg(target_os = "linux")]
+        {
+            let browser_pid = fs::read_to_string(dir.path().join("browser_pid")).unwrap();
+            let stat = format!("/proc/{}/stat", browser_pid.trim());
+            let stopped = || fs::read_to_string(&stat).map_or(true, |stat| stat.contains(") Z "));
+            let started = std::time::Instant::now();
+            while !stopped() && started.elapsed() < Duration::from_secs(2) {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            assert!(stopped());
+        }
+
+        let client = LatchkeyClient {
+            binary: dir.path().join("missing"),
+            ..client
+        };
+        assert_eq!(client.list_services().await.unwrap_err(), LatchkeyError::NotInstalled);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_ensure_browser() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = fake_client(dir.path());
+        fs::write(dir.path().join("slow"), "").unwrap();
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        let started = std::time::Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let _ = cancel_tx.send(());
+        });
+        let error = client.ensure_browser(|_| {}, cancel_rx).await.unwrap_err();
+        assert_eq!(error, LatchkeyError::Cancelled);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_parse_outputs() {
+        assert_eq!(parse_services("- slack: Slack\n* linear\n\n"), vec!["slack", "linear"]);
+        assert_eq!(parse_status("discord: Invalid\n"), ServiceStatus::Invalid);
+        assert_eq!(
+            parse_status("logging in"),
+            ServiceStatus::Other("logging in".to_string())
+        );
+        assert_eq!(parse_percent("|■■■■      |  40% of 164.7 MiB"), Some(40.0));
+        assert_eq!(parse_percent("Downloading 2 of 3 browsers"), None);
+    }
+}
@@ -1,48 +1,273 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod artifacts;
+mod attachments;
+mod audit_log;
+mod browser;
+mod budget;
+mod config;
 mod credentials;
+mod dry_run;
 mod fs_utils;
+mod key_validation;
+mod latchkey;
+mod models;
 mod opencode;
+mod opencode_events;
+mod opencode_server;
 mod paths;
+mod permissions;
+mod process_utils;
+mod providers;
+mod secret_store;
+mod session_store;
+mod sessions;
+mod snapshots;
+mod time_utils;
+mod usage;
+mod workspace;
+mod workspace_config;
 
-use credentials::{CredentialManager, Provider};
-use opencode::OpencodeManager;
+use artifacts::ArtifactPreview;
+use attachments::Attachment;
+use audit_log::{AuditEntry, AuditQuery};
+use browser::{BrowserSetup, BrowserStatus};
+use budget::BudgetLimits;
+use credentials::{CredentialError, CredentialManager, LockStatus};
+use dry_run::{ProposedRequest, ProposedRequestResult};
+use key_validation::{validate_key, KeyValidation};
+use latchkey::{LatchkeyClient, LatchkeyError, LatchkeyService};
+use models::ModelInfo;
+use opencode::{MessageError, MessageEvent, MessageResponse, OpencodeManager};
 use paths::AppPaths;
-use std::process::Command;
+use permissions::PermissionPolicy;
+use providers::{ProviderInfo, ProviderRegistry};
+use secret_store::SecretBackend;
+use session_store::SessionHistory;
+use sessions::{SessionInfo, SessionListing};
+use snapshots::{FileChange, SnapshotInfo};
+use usage::UsageReport;
+use workspace::{WorkspaceFile, WorkspaceSettings};
+use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::Mutex;
 
 // State wrapper for the OpenCode manager and app paths
 struct AppState {
-    opencode: Arc<Mutex<Option<OpencodeManager>>>,
+    /// Shared so that runs in different sessions don't hold the lock while they execute
+    opencode: Arc<Mutex<Option<Arc<OpencodeManager>>>>,
     paths: Arc<Mutex<Option<AppPaths>>>,
+    browser: Arc<BrowserSetup>,
+}
+
+/// Get the OpenCode manager without keeping the state locked
+async fn get_opencode(state: &State<'_, AppState>) -> Result<Arc<OpencodeManager>, String> {
+    state
+        .opencode
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "OpenCode SDK not initialized. Please restart the app.".to_string())
 }
 
 #[tauri::command]
 async fn send_message(
+    session_id: String,
     message: String,
     provider_id: String,
     model_id: String,
     state: State<'_, AppState>,
     app: AppHandle,
-) -> Result<String, String> {
-    let opencode_guard = state.opencode.lock().await;
-    let opencode = opencode_guard
-        .as_ref()
-        .ok_or_else(|| "OpenCode SDK not initialized. Please restart the app.".to_string())?;
-
-    // Clone the app handle for status updates
-    let app_clone = app.clone();
+) -> Result<MessageResponse, MessageError> {
+    let opencode = get_opencode(&state).await?;
 
     opencode
-        .send_message(&message, &provider_id, &model_id, move |status| {
-            let _ = app_clone.emit("chat:statusUpdate", &status);
+        .send_message(&session_id, &message, &provider_id, &model_id, move |event| {
+            let _ = match &event {
+                MessageEvent::Status(status) => app.emit("chat:statusUpdate", status),
+                MessageEvent::Text(delta) => app.emit("chat:textDelta", delta),
+                MessageEvent::Permission(request) => app.emit("chat:permissionRequest", request),
+                MessageEvent::Proposal(proposal) => app.emit("chat:proposedRequest", proposal),
+            };
         })
         .await
 }
 
+/// Let opencode go ahead with a tool call it asked about. With `always`, matching
+/// calls are allowed for the rest of the conversation.
+#[tauri::command]
+async fn approve_permission(
+    session_id: String,
+    permission_id: String,
+    always: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    println!("[permissions] Approving {} in session {}", permission_id, session_id);
+    get_opencode(&state)
+        .await?
+        .respond_permission(&session_id, &permission_id, true, always.unwrap_or(false))
+        .await
+}
+
+/// Refuse a tool call opencode asked about
+#[tauri::command]
+async fn deny_permission(
+    session_id: String,
+    permission_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    println!("[permissions] Denying {} in session {}", permission_id, session_id);
+    get_opencode(&state)
+        .await?
+        .respond_permission(&session_id, &permission_id, false, false)
+        .await
+}
+
+/// Turn dry run mode on or off for a session
+#[tauri::command]
+async fn set_dry_run(session_id: String, enabled: bool, state: State<'_, AppState>) -> Result<SessionInfo, String> {
+    println!("[dry-run] Setting dry run mode of session {} to {}", session_id, enabled);
+    get_opencode(&state).await?.set_dry_run(&session_id, enabled)
+}
+
+/// List the latchkey requests held back in a session
+#[tauri::command]
+async fn list_proposed_requests(
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<ProposedRequest>, String> {
+    get_opencode(&state).await?.list_proposed_requests(&session_id)
+}
+
+/// Send a latchkey request that was held back in dry run mode
+#[tauri::command]
+async fn send_proposed_request(
+    session_id: String,
+    request_id: String,
+    state: State<'_, AppState>,
+) -> Result<ProposedRequestResult, String> {
+    println!("[dry-run] Sending proposed request {} in session {}", request_id, session_id);
+    get_opencode(&state)
+        .await?
+        .send_proposed_request(&session_id, &request_id)
+        .await
+}
+
+/// Drop a latchkey request that was held back in dry run mode
+#[tauri::command]
+async fn discard_proposed_request(
+    session_id: String,
+    request_id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    println!("[dry-run] Discarding proposed request {} in session {}", request_id, session_id);
+    get_opencode(&state)
+        .await?
+        .discard_proposed_request(&session_id, &request_id)
+}
+
+/// Get the rules for which tools opencode may use without asking
+#[tauri::command]
+fn get_permission_policy() -> Result<PermissionPolicy, String> {
+    PermissionPolicy::load()
+}
+
+/// Replace the permission policy. It applies from the next message on.
+#[tauri::command]
+fn set_permission_policy(policy: PermissionPolicy) -> Result<(), String> {
+    println!("[permissions] Updating permission policy");
+    policy.save()
+}
+
+/// Cancel the message that is currently being processed in a session.
+/// Returns false if no message was in flight.
+#[tauri::command]
+async fn cancel_message(session_id: String, state: State<'_, AppState>) -> Result<bool, String> {
+    println!("[opencode] Cancelling in-flight message in session {}", session_id);
+    get_opencode(&state).await?.cancel_message(&session_id)
+}
+
+/// Create a new chat session and make it the active one
+#[tauri::command]
+async fn create_session(
+    title: Option<String>,
+    provider_id: String,
+    model_id: String,
+    state: State<'_, AppState>,
+) -> Result<SessionInfo, String> {
+    println!("[sessions] Creating session");
+    get_opencode(&state)
+        .await?
+        .create_session(title, &provider_id, &model_id)
+}
+
+/// List all chat sessions and which one is active
+#[tauri::command]
+async fn list_sessions(state: State<'_, AppState>) -> Result<SessionListing, String> {
+    Ok(get_opencode(&state).await?.list_sessions())
+}
+
+/// Third-party API calls made through latchkey, oldest first. Dates are local
+/// `YYYY-MM-DD` dates and both ends of the range are included.
+#[tauri::command]
+async fn query_audit_log(
+    service: Option<String>,
+    session_id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<AuditEntry>, String> {
+    let query = AuditQuery {
+        service,
+        session_id,
+        from,
+        to,
+    };
+    get_opencode(&state).await?.query_audit_log(&query)
+}
+
+/// Tokens and cost spent per provider and day, and per session
+#[tauri::command]
+async fn get_usage(state: State<'_, AppState>) -> Result<UsageReport, String> {
+    Ok(get_opencode(&state).await?.get_usage())
+}
+
+/// Make an existing chat session the active one
+#[tauri::command]
+async fn switch_session(session_id: String, state: State<'_, AppState>) -> Result<SessionInfo, String> {
+    println!("[sessions] Switching to session {}", session_id);
+    get_opencode(&state).await?.switch_session(&session_id)
+}
+
+/// Load the message history of a past conversation
+#[tauri::command]
+async fn load_session(session_id: String, state: State<'_, AppState>) -> Result<SessionHistory, String> {
+    println!("[sessions] Loading session {}", session_id);
+    get_opencode(&state).await?.load_session(&session_id)
+}
+
+/// Delete a chat session and its workspace
+#[tauri::command]
+async fn delete_session(session_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    println!("[sessions] Deleting session {}", session_id);
+    get_opencode(&state).await?.delete_session(&session_id)
+}
+
+/// List the known LLM providers, including those added in the config file
+#[tauri::command]
+fn list_providers() -> Result<Vec<ProviderInfo>, String> {
+    Ok(ProviderRegistry::load()?.all().to_vec())
+}
+
+/// List the models opencode offers for providers that have credentials.
+/// The list is cached; pass `refresh` to ask opencode again.
+#[tauri::command]
+async fn list_models(refresh: Option<bool>, state: State<'_, AppState>) -> Result<Vec<ModelInfo>, String> {
+    get_opencode(&state).await?.list_models(refresh.unwrap_or(false)).await
+}
+
 /// Credential status for a single provider
 #[derive(serde::Serialize)]
 struct CredentialStatus {
@@ -50,16 +275,32 @@ struct CredentialStatus {
     has_key: bool,
 }
 
-/// Save an API key for a provider to the system keychain
+/// Save an API key for a provider to the configured secret store.
+/// With `validate`, the key is checked with the provider first and refused if it is rejected;
+/// the check's result is returned so the UI can warn about network errors or rate limits.
 #[tauri::command]
-fn save_credential(provider_id: String, api_key: String) -> Result<(), String> {
+async fn save_credential(
+    provider_id: String,
+    api_key: String,
+    validate: Option<bool>,
+) -> Result<Option<KeyValidation>, CredentialError> {
     println!("[credentials] Saving credential for provider: {}", provider_id);
-    let provider = Provider::from_str(&provider_id)
-        .ok_or_else(|| format!("Unknown provider: {}", provider_id))?;
-    match CredentialManager::save_credential(provider, &api_key) {
+    let validation = if validate.unwrap_or(false) {
+        let provider = ProviderRegistry::load()?.get(&provider_id)?.clone();
+        let validation = validate_key(&provider, &api_key).await?;
+        if let KeyValidation::Invalid { message } = &validation {
+            eprintln!("[credentials] Not saving rejected API key for {}: {}", provider_id, message);
+            return Err(CredentialError::Other(format!("The API key was rejected: {}", message)));
+        }
+        Some(validation)
+    } else {
+        None
+    };
+
+    match CredentialManager::save_credential(&provider_id, &api_key) {
         Ok(()) => {
             println!("[credentials] Successfully saved credential for: {}", provider_id);
-            Ok(())
+            Ok(validation)
         }
         Err(e) => {
             eprintln!("[credentials] Failed to save credential for {}: {}", provider_id, e);
@@ -68,13 +309,29 @@ fn save_credential(provider_id: String, api_key: String) -> Result<(), String> {
     }
 }
 
-/// Delete an API key for a provider from the system keychain
+/// Check an API key with the provider by making a cheap authenticated request.
+/// Without `api_key`, the stored key is checked.
+#[tauri::command]
+async fn validate_credential(
+    provider_id: String,
+    api_key: Option<String>,
+) -> Result<KeyValidation, CredentialError> {
+    let provider = ProviderRegistry::load()?.get(&provider_id)?.clone();
+    let api_key = match api_key {
+        Some(api_key) => api_key,
+        None => CredentialManager::get_credential(&provider_id)?
+            .ok_or_else(|| format!("No API key stored for {}", provider_id))?,
+    };
+    let validation = validate_key(&provider, &api_key).await?;
+    println!("[credentials] Validation result for {}: {:?}", provider_id, validation);
+    Ok(validation)
+}
+
+/// Delete an API key for a provider from the configured secret store
 #[tauri::command]
-fn delete_credential(provider_id: String) -> Result<(), String> {
+fn delete_credential(provider_id: String) -> Result<(), CredentialError> {
     println!("[credentials] Deleting credential for provider: {}", provider_id);
-    let provider = Provider::from_str(&provider_id)
-        .ok_or_else(|| format!("Unknown provider: {}", provider_id))?;
-    match CredentialManager::delete_credential(provider) {
+    match CredentialManager::delete_credential(&provider_id) {
         Ok(()) => {
             println!("[credentials] Successfully deleted credential for: {}", provider_id);
             Ok(())
@@ -88,7 +345,7 @@ fn delete_credential(provider_id: String) -> Result<(), String> {
 
 /// Get the status of all credentials (which providers have keys stored)
 #[tauri::command]
-fn list_credentials() -> Result<Vec<CredentialStatus>, String> {
+fn list_credentials() -> Result<Vec<CredentialStatus>, CredentialError> {
     println!("[credentials] Listing all credentials");
     match CredentialManager::list_credentials() {
         Ok(credentials) => {
@@ -111,73 +368,293 @@ fn list_credentials() -> Result<Vec<CredentialStatus>, String> {
     }
 }
 
-/// Result of running latchkey ensure-browser
-#[derive(serde::Serialize)]
-struct BrowserCheckResult {
-    success: bool,
-    output: String,
+/// Get the spending limits enforced while messages run
+#[tauri::command]
+fn get_budget_limits() -> Result<BudgetLimits, String> {
+    BudgetLimits::load()
 }
 
-/// Run `latchkey ensure-browser` to ensure browser is available
+/// Replace the spending limits. They apply from the next message on.
 #[tauri::command]
-async fn ensure_browser(state: State<'_, AppState>) -> Result<BrowserCheckResult, String> {
-    println!("[browser] Running latchkey ensure-browser...");
+fn set_budget_limits(limits: BudgetLimits) -> Result<(), String> {
+    println!("[budget] Updating spending limits: {:?}", limits);
+    limits.save()
+}
+
+/// Copy files into a session's workspace under `attachments/`.
+/// They are passed to opencode with the next message.
+#[tauri::command]
+async fn attach_files(
+    session_id: String,
+    paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<Attachment>, String> {
+    println!("[attachments] Attaching {} files to session {}", paths.len(), session_id);
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    get_opencode(&state).await?.attach_files(&session_id, &paths)
+}
+
+/// Get where the workspaces opencode works in are kept
+#[tauri::command]
+fn get_workspace_settings() -> Result<WorkspaceSettings, String> {
+    WorkspaceSettings::load()
+}
+
+/// Change where the workspaces are kept. It applies the next time the app starts.
+#[tauri::command]
+fn set_workspace_settings(settings: WorkspaceSettings) -> Result<(), String> {
+    println!("[workspace] Updating workspace settings: {:?}", settings);
+    settings.save()
+}
+
+/// Files in a session's workspace
+#[tauri::command]
+async fn list_workspace(session_id: String, state: State<'_, AppState>) -> Result<Vec<WorkspaceFile>, String> {
+    get_opencode(&state).await?.list_workspace(&session_id)
+}
+
+/// Show a session's workspace in the system file manager. Returns its path.
+#[tauri::command]
+async fn open_workspace(
+    app: AppHandle,
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let workspace = get_opencode(&state).await?.workspace_dir(&session_id)?;
+    println!("[workspace] Opening {:?}", workspace);
+    workspace::reveal(&app, &workspace)?;
+    Ok(workspace.to_string_lossy().to_string())
+}
+
+/// Delete the files in a session's workspace and start over from the template
+#[tauri::command]
+async fn reset_workspace(session_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    println!("[workspace] Resetting workspace of session {}", session_id);
+    get_opencode(&state).await?.reset_workspace(&session_id)?;
+    Ok(())
+}
+
+/// Snapshots of a session's workspace taken before each message, newest first
+#[tauri::command]
+async fn list_snapshots(session_id: String, state: State<'_, AppState>) -> Result<Vec<SnapshotInfo>, String> {
+    get_opencode(&state).await?.list_snapshots(&session_id)
+}
+
+/// What changed in a session's workspace since a snapshot
+#[tauri::command]
+async fn diff_snapshot(
+    session_id: String,
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileChange>, String> {
+    get_opencode(&state).await?.diff_snapshot(&session_id, &snapshot_id)
+}
 
+/// Put a session's workspace back the way it was when a snapshot was taken.
+/// Returns the changes that were reverted.
+#[tauri::command]
+async fn restore_snapshot(
+    session_id: String,
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileChange>, String> {
+    println!("[workspace] Restoring snapshot {} of session {}", snapshot_id, session_id);
+    get_opencode(&state).await?.restore_snapshot(&session_id, &snapshot_id)
+}
+
+/// Content of a file the agent produced, for showing it in the app
+#[tauri::command]
+async fn preview_artifact(
+    session_id: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<ArtifactPreview, String> {
+    get_opencode(&state).await?.preview_artifact(&session_id, &path)
+}
+
+/// Show a file the agent produced in the system file manager
+#[tauri::command]
+async fn reveal_artifact(
+    app: AppHandle,
+    session_id: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let file = get_opencode(&state).await?.artifact_path(&session_id, &path)?;
+    println!("[workspace] Revealing {:?}", file);
+    workspace::reveal(&app, &file)
+}
+
+/// Copy a file the agent produced to a file or directory the user picked.
+/// Returns where it was copied to.
+#[tauri::command]
+async fn export_artifact(
+    session_id: String,
+    path: String,
+    destination: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let target = get_opencode(&state)
+        .await?
+        .export_artifact(&session_id, &path, &PathBuf::from(destination))?;
+    Ok(target.to_string_lossy().to_string())
+}
+
+/// Get the backend API keys are stored in
+#[tauri::command]
+fn get_secret_backend() -> Result<SecretBackend, CredentialError> {
+    CredentialManager::get_secret_backend()
+}
+
+/// Switch the secret backend, moving all stored API keys into the new one.
+/// Returns the number of keys moved.
+#[tauri::command]
+fn set_secret_backend(backend: SecretBackend) -> Result<usize, CredentialError> {
+    println!("[credentials] Switching secret backend to {:?}", backend);
+    match CredentialManager::set_secret_backend(backend) {
+        Ok(moved) => Ok(moved),
+        Err(e) => {
+            eprintln!("[credentials] Failed to switch secret backend: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Unlock passphrase-encrypted API keys for the rest of the app session.
+/// API keys that aren't encrypted yet are encrypted with the passphrase.
+#[tauri::command]
+fn unlock_credentials(passphrase: String) -> Result<(), CredentialError> {
+    println!("[credentials] Unlocking API keys");
+    match CredentialManager::unlock(&passphrase) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            eprintln!("[credentials] Failed to unlock API keys: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Forget the passphrase until it is entered again
+#[tauri::command]
+fn lock_credentials() {
+    CredentialManager::lock();
+}
+
+/// Whether API keys are encrypted with a passphrase and whether it has been entered
+#[tauri::command]
+fn credentials_lock_status() -> Result<LockStatus, CredentialError> {
+    CredentialManager::lock_status()
+}
+
+/// A latchkey client for the app paths, without holding on to the paths lock
+async fn latchkey_client(state: &State<'_, AppState>) -> Result<LatchkeyClient, LatchkeyError> {
     let paths_guard = state.paths.lock().await;
     let paths = paths_guard
         .as_ref()
         .ok_or_else(|| "App paths not initialized".to_string())?;
+    Ok(LatchkeyClient::new(paths))
+}
 
-    let latchkey_binary = paths.get_binary_path("latchkey");
-    let path_env = paths.get_path_env();
-    let playwright_browsers_path = paths.get_playwright_browsers_path();
-
-    println!("[browser] Using latchkey binary: {:?}", latchkey_binary);
-    println!("[browser] PATH: {}", path_env);
-
-    let result = Command::new(&latchkey_binary)
-        .arg("ensure-browser")
-        .env("PATH", &path_env)
-        .env("PLAYWRIGHT_BROWSERS_PATH", &playwright_browsers_path)
-        .output();
-
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            let combined_output = if stderr.is_empty() {
-                stdout
-            } else if stdout.is_empty() {
-                stderr
-            } else {
-                format!("{}\n{}", stdout, stderr)
-            };
+/// List the services latchkey supports and whether it is logged in to each
+#[tauri::command]
+async fn list_latchkey_services(state: State<'_, AppState>) -> Result<Vec<LatchkeyService>, LatchkeyError> {
+    latchkey_client(&state).await?.services().await
+}
 
-            let success = output.status.success();
-            println!("[browser] Command completed with success={}, output: {}", success, combined_output);
+/// Check whether latchkey is logged in to a service
+#[tauri::command]
+async fn get_latchkey_service(service: String, state: State<'_, AppState>) -> Result<LatchkeyService, LatchkeyError> {
+    let client = latchkey_client(&state).await?;
+    let status = client.service_status(&service).await?;
+    Ok(LatchkeyService { name: service, status })
+}
 
-            Ok(BrowserCheckResult {
-                success,
-                output: combined_output,
-            })
-        }
-        Err(e) => {
-            let error_msg = format!("Failed to run latchkey: {}", e);
-            eprintln!("[browser] {}", error_msg);
-            Ok(BrowserCheckResult {
-                success: false,
-                output: error_msg,
-            })
+/// Log out of a service. latchkey asks for a new login on the next request to it.
+#[tauri::command]
+async fn clear_latchkey_service(service: String, state: State<'_, AppState>) -> Result<LatchkeyService, LatchkeyError> {
+    println!("[latchkey] Clearing credentials of {}", service);
+    let client = latchkey_client(&state).await?;
+    client.clear_service(&service).await?;
+    let status = client.service_status(&service).await?;
+    Ok(LatchkeyService { name: service, status })
+}
+
+/// Log in to a service in the browser, e.g. before the assistant first uses it
+#[tauri::command]
+async fn login_latchkey_service(service: String, state: State<'_, AppState>) -> Result<LatchkeyService, LatchkeyError> {
+    println!("[latchkey] Logging in to {}", service);
+    let client = latchkey_client(&state).await?;
+    let status = client.login_service(&service).await?;
+    Ok(LatchkeyService { name: service, status })
+}
+
+/// Run `latchkey ensure-browser` to ensure browser is available.
+/// Output is streamed as `browser:progress` events while the browser downloads.
+#[tauri::command]
+async fn ensure_browser(app: AppHandle, state: State<'_, AppState>) -> Result<BrowserStatus, String> {
+    println!("[browser] Running latchkey ensure-browser...");
+    let browser = state.browser.clone();
+    let cancel = browser.start()?;
+    let app_handle = app.clone();
+
+    let result = match latchkey_client(&state).await {
+        Ok(client) => {
+            println!("[browser] Using latchkey binary: {:?}", client.binary());
+            let progress_browser = browser.clone();
+            let on_progress = move |progress: latchkey::BrowserProgress| {
+                progress_browser.record(&progress);
+                if let Err(e) = app.emit("browser:progress", &progress) {
+                    eprintln!("[browser] Failed to emit progress: {}", e);
+                }
+            };
+            client.ensure_browser(on_progress, cancel).await
         }
+        Err(e) => Err(e),
+    };
+
+    match &result {
+        Ok(_) => println!("[browser] Browser is available"),
+        Err(e) => eprintln!("[browser] {}", e),
     }
+    let status = browser.finish(&result);
+    // Windows that didn't start the check, e.g. after a reload, learn about the result here
+    if let Err(e) = app_handle.emit("browser:status", &status) {
+        eprintln!("[browser] Failed to emit status: {}", e);
+    }
+    Ok(status)
+}
+
+/// State of the browser check, without starting one
+#[tauri::command]
+fn browser_status(state: State<'_, AppState>) -> BrowserStatus {
+    state.browser.status()
+}
+
+/// Stop a running browser check. Returns whether one was running.
+#[tauri::command]
+fn cancel_browser_check(state: State<'_, AppState>) -> bool {
+    state.browser.cancel()
 }
 
 fn main() {
+    // opencode runs this binary in place of latchkey, see `dry_run::DryRunDir::install_shim`
+    let context = tauri::generate_context!();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(dry_run::SHIM_FLAG) {
+        // The shim finds latchkey itself, so its path isn't written anywhere the agent looks
+        let resource_path =
+            tauri::utils::platform::resource_dir(context.package_info(), &tauri::utils::Env::default())
+                .unwrap_or_default();
+        let native_tools = AppPaths::resources(&resource_path).join("native_tools");
+        std::process::exit(dry_run::run_shim(&native_tools, &args[2..]));
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .manage(AppState {
             opencode: Arc::new(Mutex::new(None)),
             paths: Arc::new(Mutex::new(None)),
+            browser: Arc::new(BrowserSetup::default()),
         })
         .setup(|app| {
             let app_handle = app.handle().clone();
@@ -203,7 +680,7 @@ fn main() {
                 match OpencodeManager::new(&app_handle).await {
                     Ok(manager) => {
                         let mut opencode_guard = opencode_arc.lock().await;
-                        *opencode_guard = Some(manager);
+                        *opencode_guard = Some(Arc::new(manager));
                         println!("OpenCode manager initialized successfully");
                     }
                     Err(e) => {
@@ -216,11 +693,65 @@ fn main() {
         })
         .invoke_handler(tauri::generate_handler![
             send_message,
+            cancel_message,
+            approve_permission,
+            deny_permission,
+            get_permission_policy,
+            set_permission_policy,
+            set_dry_run,
+            list_proposed_requests,
+            send_proposed_request,
+            discard_proposed_request,
+            create_session,
+            list_sessions,
+            switch_session,
+            load_session,
+            delete_session,
+            get_usage,
+            query_audit_log,
+            list_providers,
+            list_models,
             save_credential,
+            validate_credential,
             delete_credential,
             list_credentials,
-            ensure_browser
+            get_budget_limits,
+            set_budget_limits,
+            attach_files,
+            get_workspace_settings,
+            set_workspace_settings,
+            list_workspace,
+            open_workspace,
+            reset_workspace,
+            list_snapshots,
+            diff_snapshot,
+            restore_snapshot,
+            preview_artifact,
+            reveal_artifact,
+            export_artifact,
+            get_secret_backend,
+            set_secret_backend,
+            unlock_credentials,
+            lock_credentials,
+            credentials_lock_status,
+            list_latchkey_services,
+            get_latchkey_service,
+            clear_latchkey_service,
+            login_latchkey_service,
+            ensure_browser,
+            browser_status,
+            cancel_browser_check
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Don't leave the opencode server running after the app quits
+            if let tauri::RunEvent::Exit = event {
+                let state = app.state::<AppState>();
+                let manager = state.opencode.try_lock().ok().and_then(|opencode| opencode.clone());
+                if let Some(manager) = manager {
+                    manager.shutdown();
+                }
+            }
+        });
 }
@@ -0,0 +1,118 @@
+use crate::providers::ProviderRegistry;
+use serde::Serialize;
+
+/// A model the user can chat with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelInfo {
+    pub provider_id: String,
+    /// Model ID as understood by opencode, passed back in `send_message`
+    pub model_id: String,
+    pub display_name: String,
+    pub provider_name: String,
+}
+
+/// Parse the output of `opencode models`, which lists one `provider/model` per line.
+/// Model IDs may contain further slashes (e.g. OpenRouter's `vendor/model`).
+pub fn parse_models_output(stdout: &str) -> Vec<(String, String)> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.split_once('/'))
+        .filter(|(provider, model)| {
+            !provider.is_empty() && !model.is_empty() && !provider.contains(char::is_whitespace)
+        })
+        .map(|(provider, model)| (provider.to_string(), model.to_string()))
+        .collect()
+}
+
+/// The models of providers the user can use: known providers that have an API key,
+/// or that don't need one
+pub fn usable_models(
+    models: &[(String, String)],
+    registry: &ProviderRegistry,
+    credentials: &[(String, bool)],
+) -> Vec<ModelInfo> {
+    models
+        .iter()
+        .filter_map(|(provider_id, model_id)| {
+            let provider = registry.get(provider_id).ok()?;
+            let has_key = credentials
+                .iter()
+                .any(|(id, has_key)| id == provider_id && *has_key);
+            if !has_key && !provider.env_vars.is_empty() {
+                return None;
+            }
+            let display_name = provider
+                .models
+                .iter()
+                .find(|m| &m.id == model_id)
+                .and_then(|m| m.display_name.clone())
+                .unwrap_or_else(|| model_id.clone());
+            Some(ModelInfo {
+                provider_id: provider_id.clone(),
+                model_id: model_id.clone(),
+                display_name,
+                provider_name: provider.display_name.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{ProviderInfo, ProviderModel};
+
+    #[test]
+    fn test_parse_models_output() {
+        let output = "anthropic/claude-sonnet-4-5\nopenrouter/anthropic/claude-3.5\n\nINFO  some log line\nollama/llama3:8b\n";
+        assert_eq!(
+            parse_models_output(output),
+            vec![
+                ("anthropic".to_string(), "claude-sonnet-4-5".to_string()),
+                ("openrouter".to_string(), "anthropic/claude-3.5".to_string()),
+                ("ollama".to_string(), "llama3:8b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usable_models_need_credentials() {
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![ProviderInfo {
+            id: "ollama".to_string(),
+            display_name: "Ollama".to_string(),
+            openai_compatible: true,
+            models: vec![ProviderModel {
+                id: "llama3:8b".to_string(),
+                display_name: Some("Llama 3 8B".to_string()),
+            }],
+            ..Default::default()
+        }]);
+        let models = parse_models_output(
+            "anthropic/claude-sonnet-4-5\nopenai/o3\nopenrouter/x/y\nollama/llama3:8b",
+        );
+        let credentials = vec![
+            ("anthropic".to_string(), true),
+            ("openai".to_string(), false),
+        ];
+
+        let usable = usable_models(&models, &registry, &credentials);
+        assert_eq!(
+            usable,
+            vec![
+                ModelInfo {
+                    provider_id: "anthropic".to_string(),
+                    model_id: "claude-sonnet-4-5".to_string(),
+                    display_name: "claude-sonnet-4-5".to_string(),
+                    provider_name: "Anthropic".to_string(),
+                },
+                ModelInfo {
+                    provider_id: "ollama".to_string(),
+                    model_id: "llama3:8b".to_string(),
+                    display_name: "Llama 3 8B".to_string(),
+                    provider_name: "Ollama".to_string(),
+                },
+            ]
+        );
+    }
+}
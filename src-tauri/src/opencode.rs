@@ -1,13 +1,65 @@
-use crate::credentials::CredentialManager;
-use crate::fs_utils::copy_dir_contents;
+use crate::artifacts::{self, Artifact, ArtifactPreview, WrittenFile};
+use crate::attachments::{self, Attachment};
+use crate::audit_log::{AuditEntry, AuditLog, AuditOutcome, AuditQuery};
+use crate::budget::{BudgetExceeded, BudgetLimits, BudgetTracker};
+use crate::config::{get_config_path, ConfigFile};
+use crate::credentials::{CredentialError, CredentialManager};
+use crate::dry_run::{DryRunDir, ProposedRequest, ProposedRequestResult};
+use crate::latchkey::LatchkeyClient;
+use crate::models::{parse_models_output, usable_models, ModelInfo};
+use crate::opencode_events::{EventKind, OpencodeEvent, ToolPart, ToolState};
+use crate::opencode_server::{OpencodeServer, Prompt, ServerEvent};
 use crate::paths::AppPaths;
+use crate::permissions::{PermissionPolicy, PermissionRequest};
+use crate::process_utils::{isolate_process_group, kill_process_tree};
+use crate::providers::ProviderRegistry;
+use crate::session_store::{ChatMessage, MessageSender, SessionHistory, SessionStore};
+use crate::sessions::{SessionInfo, SessionListing, SessionRegistry};
+use crate::snapshots::{FileChange, SnapshotInfo, Snapshots};
+use crate::time_utils::now_millis;
+use crate::usage::{today, SessionUsage, Usage, UsageLedger, UsageReport};
+use crate::workspace::{WorkspaceFile, WorkspaceMode, WorkspaceSettings, Workspaces};
+use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
 use tauri::AppHandle;
 use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Upper bound on a single `opencode run`; the process tree is killed once it is exceeded
+const RUN_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Number of events buffered between the stdout reader and the callbacks
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Number of trailing stderr lines kept to diagnose failed runs
+const STDERR_TAIL_LINES: usize = 50;
+
+/// How long `opencode models` may take, including fetching the model list on first use
+const LIST_MODELS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a run on the opencode server checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How messages are run, selected by `opencode_runner` in the config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OpencodeRunner {
+    /// A long-lived `opencode serve` process, falling back to the CLI if it can't be started
+    #[default]
+    Server,
+    /// A new `opencode run` process for every message
+    Cli,
+}
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StatusUpdateDetails {
@@ -29,6 +81,9 @@ pub struct StatusUpdateDetails {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatusUpdate {
+    /// The session the update belongs to, filled in when it is forwarded
+    #[serde(rename = "sessionId", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
     #[serde(rename = "type")]
     pub update_type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -37,181 +92,1629 @@ pub struct StatusUpdate {
     pub details: Option<StatusUpdateDetails>,
 }
 
-/// JSON output from `opencode run --format json`
-#[derive(Debug, Deserialize)]
-struct OpencodeEvent {
-    #[serde(rename = "type")]
-    event_type: String,
-    #[serde(rename = "sessionID")]
-    session_id: Option<String>,
-    part: Option<EventPart>,
-}
+/// Incremental assistant text for a single part, emitted as `chat:textDelta`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextDelta {
+    /// The session the text belongs to, filled in when it is forwarded
+    #[serde(rename = "sessionId", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+    #[serde(rename = "partId")]
+    pub part_id: String,
+    /// Text appended to the part since the previous delta
+    pub delta: String,
+    /// Set when the part's text was rewritten rather than extended;
+    /// `delta` then holds the part's whole text
+    pub reset: bool,
+}
+
+/// Assistant text parts in the order they first appeared during a run.
+/// The final response is derived from the same parts that were streamed.
+#[derive(Debug, Default)]
+struct ResponseText {
+    parts: Vec<TextPart>,
+}
+
+#[derive(Debug)]
+struct TextPart {
+    message_id: String,
+    part_id: String,
+    text: String,
+}
+
+impl ResponseText {
+    /// Record the latest text of a part and return what changed, if anything
+    fn update(&mut self, message_id: &str, part_id: &str, text: &str) -> Option<TextDelta> {
+        let index = match self
+            .parts
+            .iter()
+            .position(|p| p.message_id == message_id && p.part_id == part_id)
+        {
+            Some(index) => index,
+            None => {
+                self.parts.push(TextPart {
+                    message_id: message_id.to_string(),
+                    part_id: part_id.to_string(),
+                    text: String::new(),
+                });
+                self.parts.len() - 1
+            }
+        };
+        let part = &mut self.parts[index];
+
+        if part.text == text {
+            return None;
+        }
+
+        let (delta, reset) = match text.strip_prefix(part.text.as_str()) {
+            Some(suffix) => (suffix.to_string(), false),
+            None => (text.to_string(), true),
+        };
+        part.text = text.to_string();
+
+        Some(TextDelta {
+            session_id: None,
+            message_id: message_id.to_string(),
+            part_id: part_id.to_string(),
+            delta,
+            reset,
+        })
+    }
+
+    /// All non-empty parts joined the same way the frontend renders them
+    fn full_text(&self) -> String {
+        self.parts
+            .iter()
+            .map(|p| p.text.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// What happens while a message runs, passed on to the event sink of `send_message`
+#[derive(Debug, Clone)]
+pub enum MessageEvent {
+    Status(StatusUpdate),
+    Text(TextDelta),
+    Permission(PermissionRequest),
+    Proposal(ProposedRequest),
+}
+
+/// Why a message failed, for the frontend to tell apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageError {
+    /// The message was stopped, or not started, because it reached a spending limit
+    BudgetExceeded(BudgetExceeded),
+    Other(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::BudgetExceeded(exceeded) => write!(f, "{}", exceeded),
+            MessageError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for MessageError {
+    fn from(message: String) -> Self {
+        MessageError::Other(message)
+    }
+}
+
+/// Sent to the frontend as `{ kind, message }`, with the limit's details for budget errors
+impl Serialize for MessageError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MessageError::BudgetExceeded(exceeded) => {
+                let mut state = serializer.serialize_struct("MessageError", 5)?;
+                state.serialize_field("kind", "budget-exceeded")?;
+                state.serialize_field("message", &self.to_string())?;
+                state.serialize_field("limit", &exceeded.limit)?;
+                state.serialize_field("allowed", &exceeded.allowed)?;
+                state.serialize_field("used", &exceeded.used)?;
+                state.end()
+            }
+            MessageError::Other(message) => {
+                let mut state = serializer.serialize_struct("MessageError", 2)?;
+                state.serialize_field("kind", "other")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+        }
+    }
+}
+
+/// The response to a message, returned by `send_message`
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageResponse {
+    pub text: String,
+    /// Tokens and cost of this message
+    pub usage: Usage,
+    /// Tokens and cost of the whole session so far
+    pub session_usage: Usage,
+    /// Files the agent wrote in the workspace
+    pub artifacts: Vec<Artifact>,
+}
+
+/// What a run has reported so far
+#[derive(Debug, Default)]
+struct RunProgress {
+    text: ResponseText,
+    /// Parts whose status has been reported. The server reports parts every time they change.
+    generating_part_ids: HashSet<String>,
+    reasoning_part_ids: HashSet<String>,
+    started_tool_ids: HashSet<String>,
+    finished_tool_ids: HashSet<String>,
+    finished_step_ids: HashSet<String>,
+    /// The last error opencode reported
+    error: Option<String>,
+    /// Tokens and cost of the message so far
+    usage: Usage,
+    budget: BudgetTracker,
+    /// Set once the message crosses a spending limit, after which the run is stopped
+    budget_exceeded: Option<BudgetExceeded>,
+    /// Dry run requests that have been reported
+    proposed_request_ids: HashSet<String>,
+    /// Finished bash calls whose latchkey requests have been logged
+    audited_tool_ids: HashSet<String>,
+    /// Files written by finished write and edit calls
+    written_files: Vec<WrittenFile>,
+}
+
+impl RunProgress {
+    /// Record an event and return the status updates it causes
+    fn process_event(&mut self, event: &OpencodeEvent) -> Vec<StatusUpdate> {
+        match &event.kind {
+            EventKind::StepStart { .. } => vec![status_update("busy", "Thinking...".to_string())],
+            EventKind::StepFinish { part } => vec![status_update(
+                "idle",
+                format!("Finished ({})", part.reason.as_deref().unwrap_or_default()),
+            )],
+            EventKind::Text { part } => {
+                if self.generating_part_ids.insert(part.id.clone()) {
+                    vec![status_update("generating", "Generating response...".to_string())]
+                } else {
+                    vec![]
+                }
+            }
+            EventKind::Reasoning { part } => {
+                let text = part.text.trim();
+                if text.is_empty()
+                    || !part.is_finished()
+                    || !self.reasoning_part_ids.insert(part.id.clone())
+                {
+                    return vec![];
+                }
+                let first_line = text.lines().next().unwrap_or_default();
+                let mut status = status_update(
+                    "reasoning",
+                    OpencodeManager::truncate_for_status(first_line, 80),
+                );
+                if let Some(details) = status.details.as_mut() {
+                    details.full_message = Some(text.to_string());
+                }
+                vec![status]
+            }
+            EventKind::ToolUse { part }
+            | EventKind::ToolStart { part }
+            | EventKind::ToolFinish { part } => {
+                let mut updates = Vec::new();
+                if matches!(part.state, ToolState::Pending { .. }) {
+                    return updates;
+                }
+                if self.started_tool_ids.insert(part.id.clone()) {
+                    updates.push(tool_started(part));
+                }
+                if part.state.is_finished() && self.finished_tool_ids.insert(part.id.clone()) {
+                    updates.push(tool_finished(part));
+                    self.written_files.extend(artifacts::written_file(part));
+                }
+                updates
+            }
+            EventKind::Retry {
+                attempt, message, ..
+            } => {
+                let message = if message.is_empty() {
+                    format!("Retrying (attempt {})", attempt)
+                } else {
+                    format!("Retrying (attempt {}): {}", attempt, message)
+                };
+                vec![status_update("retry", message)]
+            }
+            EventKind::Error { error } => {
+                let message = error.message();
+                let mut status = status_update("error", format!("Error: {}", message));
+                if let Some(details) = status.details.as_mut() {
+                    details.error = Some(message.clone());
+                }
+                self.error = Some(message);
+                vec![status]
+            }
+            EventKind::Raw(_) => vec![],
+        }
+    }
+}
+
+fn budget_exceeded_status(exceeded: &BudgetExceeded) -> StatusUpdate {
+    status_update("budget-exceeded", exceeded.to_string())
+}
+
+fn status_update(update_type: &str, message: String) -> StatusUpdate {
+    StatusUpdate {
+        session_id: None,
+        update_type: update_type.to_string(),
+        message: Some(message),
+        details: Some(StatusUpdateDetails {
+            timestamp: now_millis(),
+            ..Default::default()
+        }),
+    }
+}
+
+fn tool_started(part: &ToolPart) -> StatusUpdate {
+    let description = OpencodeManager::get_tool_description(&part.tool, part.state.title());
+    let input = part.state.input().cloned();
+    let input_short = OpencodeManager::format_tool_input_for_status(&part.tool, &input);
+    let input_full = OpencodeManager::format_tool_input_for_log(&part.tool, &input);
+
+    let format_with_input = |desc: &str, input_str: &str| {
+        if input_str.is_empty() {
+            desc.to_string()
+        } else {
+            format!("{}: {}", desc, input_str)
+        }
+    };
+
+    StatusUpdate {
+        session_id: None,
+        update_type: "tool".to_string(),
+        message: Some(format_with_input(&description, &input_short)),
+        details: Some(StatusUpdateDetails {
+            full_message: Some(format_with_input(&description, &input_full)),
+            tool_name: Some(part.tool.clone()),
+            timestamp: now_millis(),
+            input,
+            ..Default::default()
+        }),
+    }
+}
+
+fn tool_finished(part: &ToolPart) -> StatusUpdate {
+    let description = OpencodeManager::get_tool_description(&part.tool, part.state.title());
+    let (update_type, message, output, error) = match &part.state {
+        ToolState::Error { error, .. } => (
+            "tool-error",
+            format!("Error: {}", error),
+            None,
+            Some(error.clone()),
+        ),
+        ToolState::Completed { output, .. } => (
+            "tool-completed",
+            format!("{} completed", description),
+            Some(output.clone()),
+            None,
+        ),
+        ToolState::Pending { .. } | ToolState::Running { .. } => {
+            ("tool-completed", format!("{} completed", description), None, None)
+        }
+    };
+
+    StatusUpdate {
+        session_id: None,
+        update_type: update_type.to_string(),
+        message: Some(message),
+        details: Some(StatusUpdateDetails {
+            tool_name: Some(part.tool.clone()),
+            timestamp: now_millis(),
+            output,
+            error,
+            duration: part.state.duration(),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Turns the events of a run on the opencode server into the events `opencode run` prints
+#[derive(Debug, Default)]
+struct ServerRun {
+    /// Messages sent by the user, whose text is not part of the response
+    user_message_ids: HashSet<String>,
+}
+
+/// What a server event means for the run
+#[derive(Debug)]
+enum ServerUpdate {
+    Event(Box<OpencodeEvent>),
+    /// A tool call waits for the user's approval
+    Permission(PermissionRequest),
+    /// The session has finished processing the message
+    Idle,
+    /// The event belongs to another session or doesn't matter for the run
+    Ignore,
+}
+
+impl ServerRun {
+    fn translate(&mut self, event: &ServerEvent, opencode_session_id: &str) -> ServerUpdate {
+        let properties = &event.properties;
+        let cli_event = match event.event_type.as_str() {
+            "message.updated" => {
+                let info = &properties["info"];
+                if info["sessionID"] == opencode_session_id && info["role"] == "user" {
+                    if let Some(id) = info["id"].as_str() {
+                        self.user_message_ids.insert(id.to_string());
+                    }
+                }
+                return ServerUpdate::Ignore;
+            }
+            "message.part.updated" => {
+                let part = &properties["part"];
+                if part["sessionID"] != opencode_session_id
+                    || part["messageID"]
+                        .as_str()
+                        .is_some_and(|id| self.user_message_ids.contains(id))
+                {
+                    return ServerUpdate::Ignore;
+                }
+                let event_type = match part["type"].as_str() {
+                    Some("step-start") => "step_start",
+                    Some("step-finish") => "step_finish",
+                    Some("text") => "text",
+                    Some("reasoning") => "reasoning",
+                    Some("tool") => "tool_use",
+                    _ => return ServerUpdate::Ignore,
+                };
+                serde_json::json!({"type": event_type, "part": part})
+            }
+            "session.status" if properties["sessionID"] == opencode_session_id => {
+                // Busy and idle are reported by the steps and `session.idle`
+                let status = &properties["status"];
+                if status["type"] != "retry" {
+                    return ServerUpdate::Ignore;
+                }
+                status.clone()
+            }
+            "session.error" if properties["sessionID"] == opencode_session_id => {
+                serde_json::json!({"type": "error", "error": properties["error"]})
+            }
+            "permission.updated" | "permission.asked" if properties["sessionID"] == opencode_session_id => {
+                return match PermissionRequest::from_event(properties) {
+                    Some(request) => ServerUpdate::Permission(request),
+                    None => ServerUpdate::Ignore,
+                };
+            }
+            "session.idle" if properties["sessionID"] == opencode_session_id => return ServerUpdate::Idle,
+            _ => return ServerUpdate::Ignore,
+        };
+
+        match serde_json::from_value::<OpencodeEvent>(cli_event) {
+            Ok(event) if !matches!(event.kind, EventKind::Raw(_)) => ServerUpdate::Event(Box::new(event)),
+            _ => ServerUpdate::Ignore,
+        }
+    }
+}
+
+/// A message to run, with what the runners need to report on it
+/// A message to run, with what the session registry knows about it
+struct OutgoingMessage<'a> {
+    session_id: &'a str,
+    message: &'a str,
+    provider_id: &'a str,
+    model_id: &'a str,
+    opencode_session_id: Option<String>,
+    /// Files passed to opencode along with the message
+    attachments: Vec<Attachment>,
+}
+
+/// What running a message produced. The log, usage and artifacts are kept
+/// whether or not the message succeeded.
+struct RunOutcome {
+    result: Result<String, MessageError>,
+    execution_log: Vec<StatusUpdate>,
+    usage: Usage,
+    artifacts: Vec<Artifact>,
+}
+
+struct RunRequest<'a> {
+    run_guard: &'a ActiveRunGuard<'a>,
+    session_id: &'a str,
+    message: &'a str,
+    provider_id: &'a str,
+    model_id: &'a str,
+    opencode_session_id: Option<&'a str>,
+    /// Files passed to opencode along with the message
+    attachments: &'a [Attachment],
+    workspace: &'a Path,
+    event_tx: &'a mpsc::Sender<MessageEvent>,
+}
+
+/// Opencode runs currently in flight, keyed by session ID.
+/// A session can only process one message at a time.
+#[derive(Default)]
+struct ActiveRuns {
+    runs: Mutex<HashMap<String, ActiveRun>>,
+}
+
+struct ActiveRun {
+    /// Not known until the process has been spawned
+    pid: Option<u32>,
+    cancelled: bool,
+}
+
+/// Marks a session's run as active for as long as it is alive
+struct ActiveRunGuard<'a> {
+    active_runs: &'a ActiveRuns,
+    session_id: String,
+}
+
+impl ActiveRuns {
+    /// Reserve a session for a new run
+    fn begin(&self, session_id: &str) -> Result<ActiveRunGuard<'_>, String> {
+        let mut runs = self.runs.lock().unwrap();
+        if runs.contains_key(session_id) {
+            return Err("A message is already being processed in this session".to_string());
+        }
+        runs.insert(
+            session_id.to_string(),
+            ActiveRun {
+                pid: None,
+                cancelled: false,
+            },
+        );
+        Ok(ActiveRunGuard {
+            active_runs: self,
+            session_id: session_id.to_string(),
+        })
+    }
+
+    /// Kill the in-flight run of a session, if any. Returns whether a run was cancelled.
+    fn cancel(&self, session_id: &str) -> Result<bool, String> {
+        let mut runs = self.runs.lock().unwrap();
+        match runs.get_mut(session_id) {
+            Some(run) => {
+                eprintln!("[opencode] Cancelling run for session {} (pid {:?})", session_id, run.pid);
+                run.cancelled = true;
+                if let Some(pid) = run.pid {
+                    kill_process_tree(pid)?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn is_running(&self, session_id: &str) -> bool {
+        self.runs.lock().unwrap().contains_key(session_id)
+    }
+
+    fn count(&self) -> usize {
+        self.runs.lock().unwrap().len()
+    }
+}
+
+impl ActiveRunGuard<'_> {
+    /// Record the spawned process, killing it straight away if the run
+    /// was cancelled before it started
+    fn set_pid(&self, pid: u32) -> Result<(), String> {
+        let mut runs = self.active_runs.runs.lock().unwrap();
+        if let Some(run) = runs.get_mut(&self.session_id) {
+            run.pid = Some(pid);
+            if run.cancelled {
+                kill_process_tree(pid)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn was_cancelled(&self) -> bool {
+        self.active_runs
+            .runs
+            .lock()
+            .unwrap()
+            .get(&self.session_id)
+            .is_some_and(|run| run.cancelled)
+    }
+}
+
+impl Drop for ActiveRunGuard<'_> {
+    fn drop(&mut self) {
+        self.active_runs.runs.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+pub struct OpencodeManager {
+    sessions: Mutex<SessionRegistry>,
+    store: SessionStore,
+    active_runs: ActiveRuns,
+    paths: AppPaths,
+    opencode_binary: PathBuf,
+    /// Persistent directories opencode works in
+    workspaces: Workspaces,
+    /// Workspace contents before each message, for undoing what the agent did
+    snapshots: Snapshots,
+    /// Temporary directory for the dry run shim and model listing. When dropped, it is automatically removed.
+    temp_dir: TempDir,
+    /// `provider/model` pairs reported by `opencode models`, fetched on first use
+    model_cache: Mutex<Option<Vec<(String, String)>>>,
+    /// Started on the first message when running messages on a server
+    server: OpencodeServer,
+    usage_ledger: Mutex<UsageLedger>,
+    /// Tool calls waiting for the user's approval, keyed by opencode's request ID
+    pending_permissions: Mutex<HashMap<String, PendingPermission>>,
+    /// Sessions in dry run mode and the requests held back in them
+    dry_run: DryRunDir,
+    audit_log: AuditLog,
+}
+
+/// Where to send the answer to a permission request
+struct PendingPermission {
+    session_id: String,
+    opencode_session_id: String,
+    base_url: String,
+    workspace: PathBuf,
+}
+
+impl OpencodeManager {
+    pub async fn new(app: &AppHandle) -> Result<Self, String> {
+        let paths = AppPaths::new(app)?;
+        let opencode_binary = paths.get_binary_path("opencode");
+
+        // Restore the conversations of previous runs
+        let store = SessionStore::new(&paths.app_data_path);
+        let listing = store.load_listing()?;
+        eprintln!(
+            "[opencode] Loaded {} sessions from {:?}",
+            listing.sessions.len(),
+            paths.app_data_path
+        );
+        let usage_ledger = UsageLedger::load(&paths.app_data_path)?;
+        let audit_log = AuditLog::new(&paths.app_data_path);
+
+        let workspaces = Workspaces::new(
+            &WorkspaceSettings::load()?,
+            &paths.app_data_path,
+            &paths.opencode_workspace_path,
+        );
+        eprintln!(
+            "[opencode] Using {:?} workspaces in: {:?}",
+            workspaces.mode(),
+            workspaces.sessions_dir().parent()
+        );
+
+        let temp_dir = TempDir::new()
+            .map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+        eprintln!("[opencode] Created temporary directory at: {:?}", temp_dir.path());
+        // The shim has to be in place even if no session is in dry run mode yet,
+        // since a running opencode server keeps its PATH
+        let dry_run = DryRunDir::new(temp_dir.path());
+        dry_run.install_shim(&paths.native_tools_path)?;
+
+        Ok(Self {
+            sessions: Mutex::new(SessionRegistry::from_listing(listing)),
+            store,
+            active_runs: ActiveRuns::default(),
+            paths,
+            server: OpencodeServer::new(&opencode_binary, temp_dir.path()),
+            opencode_binary,
+            workspaces,
+            snapshots: Snapshots::default(),
+            temp_dir,
+            model_cache: Mutex::new(None),
+            usage_ledger: Mutex::new(usage_ledger),
+            pending_permissions: Mutex::new(HashMap::new()),
+            dry_run,
+            audit_log,
+        })
+    }
+
+    /// Create the session's workspace from the template if it doesn't exist yet
+    fn ensure_session_workspace(&self, session_id: &str) -> Result<PathBuf, String> {
+        self.workspaces.ensure(session_id)
+    }
+
+    /// List the models opencode knows for providers that have credentials.
+    /// The opencode model list is cached until `refresh` is set.
+    pub async fn list_models(&self, refresh: bool) -> Result<Vec<ModelInfo>, String> {
+        let cached = if refresh {
+            None
+        } else {
+            self.model_cache.lock().unwrap().clone()
+        };
+        let models = match cached {
+            Some(models) => models,
+            None => {
+                let models = self.fetch_models().await?;
+                *self.model_cache.lock().unwrap() = Some(models.clone());
+                models
+            }
+        };
+
+        let credentials = CredentialManager::list_credentials().map_err(|e| e.to_string())?;
+        Ok(usable_models(&models, &ProviderRegistry::load()?, &credentials))
+    }
+
+    /// Ask opencode which models it knows, including custom providers
+    async fn fetch_models(&self) -> Result<Vec<(String, String)>, String> {
+        // Run in a workspace of its own so opencode sees the custom providers
+        let workspace = self.temp_dir.path().join("models");
+        fs::create_dir_all(&workspace)
+            .map_err(|e| format!("Failed to create workspace {:?}: {}", workspace, e))?;
+        ProviderRegistry::load()?.write_opencode_config(&workspace)?;
+
+        let mut cmd = Command::new(&self.opencode_binary);
+        cmd.arg("models")
+            .env("PATH", self.paths.get_path_env())
+            .current_dir(&workspace)
+            .kill_on_drop(true);
+        // opencode only lists providers it can authenticate with
+        if let Ok(env_vars) = CredentialManager::get_credentials_as_env_vars() {
+            cmd.envs(env_vars);
+        }
+        eprintln!("[opencode] Running: {:?} models", self.opencode_binary);
+
+        let output = timeout(LIST_MODELS_TIMEOUT, cmd.output())
+            .await
+            .map_err(|_| "Timed out listing opencode models".to_string())?
+            .map_err(|e| format!("Failed to run opencode models: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "opencode models exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let models = parse_models_output(&String::from_utf8_lossy(&output.stdout));
+        eprintln!("[opencode] opencode knows {} models", models.len());
+        Ok(models)
+    }
+
+    /// Persist the session index after a change to the registry
+    fn save_sessions(&self, sessions: &SessionRegistry) -> Result<(), String> {
+        self.store.save_listing(&sessions.listing())
+    }
+
+    /// Create a session with its own copy of the workspace and make it active
+    pub fn create_session(
+        &self,
+        title: Option<String>,
+        provider_id: &str,
+        model_id: &str,
+    ) -> Result<SessionInfo, String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.create(title, provider_id, model_id);
+        if let Err(e) = self.ensure_session_workspace(&session.id) {
+            let _ = sessions.delete(&session.id);
+            return Err(e);
+        }
+        self.save_sessions(&sessions)?;
+        eprintln!("[opencode] Created session {}", session.id);
+        Ok(session)
+    }
+
+    pub fn list_sessions(&self) -> SessionListing {
+        self.sessions.lock().unwrap().listing()
+    }
+
+    pub fn switch_session(&self, session_id: &str) -> Result<SessionInfo, String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.switch(session_id)?;
+        self.save_sessions(&sessions)?;
+        Ok(session)
+    }
+
+    /// Load a past conversation so it can be shown and continued
+    pub fn load_session(&self, session_id: &str) -> Result<SessionHistory, String> {
+        let session = self.sessions.lock().unwrap().get(session_id)?.clone();
+        let messages = self.store.load_messages(session_id)?;
+        Ok(SessionHistory { session, messages })
+    }
+
+    /// Delete a session, its history and its workspace.
+    /// Sessions with a message in flight must be cancelled first.
+    pub fn delete_session(&self, session_id: &str) -> Result<(), String> {
+        if self.active_runs.is_running(session_id) {
+            return Err("Cannot delete a session while a message is being processed".to_string());
+        }
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.delete(session_id)?;
+            self.save_sessions(&sessions)?;
+        }
+        self.store.delete_session(session_id)?;
+
+        self.workspaces.remove(session_id)?;
+        self.dry_run.remove_session(session_id)?;
+        eprintln!("[opencode] Deleted session {}", session_id);
+        Ok(())
+    }
+
+    /// The session's workspace directory, created if needed
+    pub fn workspace_dir(&self, session_id: &str) -> Result<PathBuf, String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        self.ensure_session_workspace(session_id)
+    }
+
+    /// Files in the session's workspace
+    pub fn list_workspace(&self, session_id: &str) -> Result<Vec<WorkspaceFile>, String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        self.workspaces.list(session_id)
+    }
+
+    /// Delete the files in the session's workspace and start over from the template.
+    /// A shared workspace can only be reset while no message is being processed.
+    pub fn reset_workspace(&self, session_id: &str) -> Result<PathBuf, String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        if self.workspace_busy(session_id) {
+            return Err("Cannot reset the workspace while a message is being processed".to_string());
+        }
+        eprintln!("[opencode] Resetting workspace of session {}", session_id);
+        self.workspaces.reset(session_id)
+    }
+
+    /// Whether a message being processed may be changing the session's workspace
+    fn workspace_busy(&self, session_id: &str) -> bool {
+        match self.workspaces.mode() {
+            WorkspaceMode::PerSession => self.active_runs.is_running(session_id),
+            WorkspaceMode::Shared => self.active_runs.count() > 0,
+        }
+    }
+
+    /// Snapshots of the session's workspace, taken before each message, newest first
+    pub fn list_snapshots(&self, session_id: &str) -> Result<Vec<SnapshotInfo>, String> {
+        self.snapshots.list(&self.workspace_dir(session_id)?, session_id)
+    }
+
+    /// What changed in the session's workspace since a snapshot
+    pub fn diff_snapshot(&self, session_id: &str, snapshot_id: &str) -> Result<Vec<FileChange>, String> {
+        self.snapshots.diff(&self.workspace_dir(session_id)?, snapshot_id)
+    }
+
+    /// Undo the changes made to the session's workspace since a snapshot.
+    /// Returns the changes that were reverted.
+    pub fn restore_snapshot(&self, session_id: &str, snapshot_id: &str) -> Result<Vec<FileChange>, String> {
+        let workspace = self.workspace_dir(session_id)?;
+        if self.workspace_busy(session_id) {
+            return Err("Cannot restore the workspace while a message is being processed".to_string());
+        }
+        let changes = self
+            .snapshots
+            .restore(&workspace, session_id, snapshot_id, now_millis())?;
+        eprintln!(
+            "[opencode] Restored snapshot {} of session {}, reverting {} changes",
+            snapshot_id,
+            session_id,
+            changes.len()
+        );
+        Ok(changes)
+    }
+
+    /// Text or image content of a file in the session's workspace
+    pub fn preview_artifact(&self, session_id: &str, path: &str) -> Result<ArtifactPreview, String> {
+        artifacts::preview(&self.workspace_dir(session_id)?, path)
+    }
+
+    /// Where a file in the session's workspace is on disk
+    pub fn artifact_path(&self, session_id: &str, path: &str) -> Result<PathBuf, String> {
+        artifacts::resolve(&self.workspace_dir(session_id)?, path)
+    }
+
+    /// Copy a file out of the session's workspace. Returns where it was copied to.
+    pub fn export_artifact(&self, session_id: &str, path: &str, destination: &Path) -> Result<PathBuf, String> {
+        let target = artifacts::export(&self.workspace_dir(session_id)?, path, destination)?;
+        eprintln!("[opencode] Exported {} to {:?}", path, target);
+        Ok(target)
+    }
+
+    /// Copy files into the session's workspace and pass them to opencode with the next message.
+    /// Returns the attachments, which may be existing ones with the same content.
+    pub fn attach_files(&self, session_id: &str, sources: &[PathBuf]) -> Result<Vec<Attachment>, String> {
+        let mut existing = self.sessions.lock().unwrap().get(session_id)?.attachments.clone();
+        let workspace = self.ensure_session_workspace(session_id)?;
+
+        let mut attached = Vec::new();
+        let mut result = Ok(());
+        for source in sources {
+            match attachments::attach(&workspace, &existing, source, now_millis()) {
+                Ok(attachment) => {
+                    eprintln!("[opencode] Attached {:?} as {}", source, attachment.path);
+                    existing.push(attachment.clone());
+                    attached.push(attachment);
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        // Keep track of the files that were copied even if a later one failed
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.add_attachments(session_id, &attached)?;
+        self.save_sessions(&sessions)?;
+        result.map(|_| attached)
+    }
+
+    /// Turn dry run mode on or off. In dry run mode, `latchkey curl` requests that
+    /// would change something are held back until the user sends them.
+    pub fn set_dry_run(&self, session_id: &str, enabled: bool) -> Result<SessionInfo, String> {
+        let session = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.set_dry_run(session_id, enabled)?;
+            self.save_sessions(&sessions)?;
+            session
+        };
+        // Applies straight away, even to a message in flight
+        self.dry_run.set_enabled(session_id, enabled)?;
+        Ok(session)
+    }
+
+    /// Requests held back in a session, oldest first
+    pub fn list_proposed_requests(&self, session_id: &str) -> Result<Vec<ProposedRequest>, String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        self.dry_run.proposals(session_id)
+    }
+
+    /// Send a request that was held back in dry run mode, as the agent wrote it
+    pub async fn send_proposed_request(
+        &self,
+        session_id: &str,
+        request_id: &str,
+    ) -> Result<ProposedRequestResult, String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        let proposal = self.dry_run.take(session_id, request_id)?;
+        eprintln!(
+            "[dry-run] Sending {} {}",
+            proposal.method,
+            proposal.url.as_deref().unwrap_or_default()
+        );
+
+        // Relative paths, e.g. in `-d @body.json`, refer to the workspace
+        let dir = self.ensure_session_workspace(session_id)?;
+        let curl_args = proposal.args.get(1..).unwrap_or_default();
+        let output = LatchkeyClient::new(&self.paths)
+            .curl(curl_args, &dir)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let entry = AuditEntry::new(
+            session_id,
+            now_millis(),
+            curl_args,
+            output.status,
+            AuditOutcome::SentByUser,
+        );
+        if let Err(e) = self.audit_log.append(&[entry]) {
+            eprintln!("[audit] Warning: Failed to log latchkey request: {}", e);
+        }
+        let output_text = [output.stdout.trim(), output.stderr.trim()]
+            .iter()
+            .filter(|text| !text.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(ProposedRequestResult {
+            success: output.success(),
+            output: output_text,
+        })
+    }
+
+    /// Third-party API calls matching a query, oldest first
+    pub fn query_audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+        self.audit_log.query(query)
+    }
+
+    /// Drop a request that was held back in dry run mode without sending it
+    pub fn discard_proposed_request(&self, session_id: &str, request_id: &str) -> Result<(), String> {
+        self.sessions.lock().unwrap().get(session_id)?;
+        let proposal = self.dry_run.take(session_id, request_id)?;
+        eprintln!("[dry-run] Discarded {} {:?}", proposal.method, proposal.url);
+        Ok(())
+    }
+
+    /// Kill the in-flight message of a session. Returns false if none was in flight.
+    pub fn cancel_message(&self, session_id: &str) -> Result<bool, String> {
+        self.active_runs.cancel(session_id)
+    }
+
+    /// Answer a tool call waiting for approval. With `always`, opencode also allows
+    /// matching calls for the rest of the conversation.
+    pub async fn respond_permission(
+        &self,
+        session_id: &str,
+        permission_id: &str,
+        approve: bool,
+        always: bool,
+    ) -> Result<(), String> {
+        let pending = {
+            let mut pending_permissions = self.pending_permissions.lock().unwrap();
+            match pending_permissions.get(permission_id) {
+                Some(pending) if pending.session_id == session_id => {
+                    pending_permissions.remove(permission_id).unwrap()
+                }
+                _ => return Err(format!("No approval is pending for {}", permission_id)),
+            }
+        };
+        let response = match (approve, always) {
+            (false, _) => "reject",
+            (true, false) => "once",
+            (true, true) => "always",
+        };
+        eprintln!("[opencode] Answering permission request {}: {}", permission_id, response);
+        self.server
+            .respond_permission(
+                &pending.base_url,
+                &pending.workspace,
+                &pending.opencode_session_id,
+                permission_id,
+                response,
+            )
+            .await
+    }
+
+    /// Send a message in a session, recording it and the response in the session's history.
+    /// Everything that happens while it runs is passed to `on_event`.
+    pub async fn send_message<F>(
+        &self,
+        session_id: &str,
+        message: &str,
+        provider_id: &str,
+        model_id: &str,
+        on_event: F,
+    ) -> Result<MessageResponse, MessageError>
+    where
+        F: Fn(MessageEvent) + Send + 'static,
+    {
+        let run_guard = self.active_runs.begin(session_id)?;
+        let (opencode_session_id, attachments) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.record_message(session_id, message, provider_id, model_id)?;
+            self.save_sessions(&sessions)?;
+            (
+                sessions.get(session_id)?.opencode_session_id.clone(),
+                sessions.unsent_attachments(session_id)?,
+            )
+        };
+        self.store.append_message(
+            session_id,
+            ChatMessage {
+                sender: MessageSender::User,
+                text: message.to_string(),
+                timestamp: now_millis(),
+                execution_log: Vec::new(),
+                usage: None,
+                artifacts: Vec::new(),
+            },
+        )?;
+
+        let outgoing = OutgoingMessage {
+            session_id,
+            message,
+            provider_id,
+            model_id,
+            opencode_session_id,
+            attachments,
+        };
+        let RunOutcome {
+            result,
+            execution_log,
+            usage,
+            artifacts,
+        } = match self.run_opencode(&run_guard, &outgoing, on_event).await {
+            Ok(outcome) => outcome,
+            Err(e) => RunOutcome {
+                result: Err(e),
+                execution_log: Vec::new(),
+                usage: Usage::default(),
+                artifacts: Vec::new(),
+            },
+        };
+        let attachments = outgoing.attachments;
+
+        if result.is_ok() && !attachments.is_empty() {
+            let mut sessions = self.sessions.lock().unwrap();
+            if let Err(e) = sessions
+                .mark_attachments_sent(session_id, &attachments)
+                .and_then(|_| self.save_sessions(&sessions))
+            {
+                eprintln!("[opencode] Warning: Failed to record sent attachments: {}", e);
+            }
+        }
+
+        // Failed and cancelled messages may have cost something too
+        let session_usage = match self.record_usage(session_id, provider_id, &usage) {
+            Ok(session_usage) => session_usage,
+            Err(e) => {
+                eprintln!("[opencode] Warning: Failed to record usage: {}", e);
+                usage
+            }
+        };
+
+        let text = match &result {
+            Ok(text) => text.clone(),
+            Err(e) => format!("Error: {}", e),
+        };
+        let bot_message = ChatMessage {
+            sender: MessageSender::Bot,
+            text,
+            timestamp: now_millis(),
+            execution_log,
+            usage: Some(usage).filter(|u| !u.is_empty()),
+            artifacts: artifacts.clone(),
+        };
+        if let Err(e) = self.store.append_message(session_id, bot_message) {
+            eprintln!("[opencode] Warning: Failed to save response: {}", e);
+        }
+
+        result.map(|text| MessageResponse {
+            text,
+            usage,
+            session_usage,
+            artifacts,
+        })
+    }
+
+    /// Add a message's usage to its session and to the provider's daily total.
+    /// Returns the session's new totals.
+    fn record_usage(&self, session_id: &str, provider_id: &str, usage: &Usage) -> Result<Usage, String> {
+        let session_usage = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session_usage = sessions.record_usage(session_id, usage)?;
+            self.save_sessions(&sessions)?;
+            session_usage
+        };
+        if !usage.is_empty() {
+            eprintln!(
+                "[opencode] Message used {} input and {} output tokens (${:.4})",
+                usage.input_tokens, usage.output_tokens, usage.cost
+            );
+            self.usage_ledger
+                .lock()
+                .unwrap()
+                .record(&today(), provider_id, usage)?;
+        }
+        Ok(session_usage)
+    }
+
+    /// Daily usage per provider and the totals of every session
+    pub fn get_usage(&self) -> UsageReport {
+        let sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .listing()
+            .sessions
+            .into_iter()
+            .map(|s| SessionUsage {
+                session_id: s.id,
+                title: s.title,
+                usage: s.usage,
+            })
+            .collect();
+        UsageReport {
+            daily: self.usage_ledger.lock().unwrap().daily(),
+            sessions,
+        }
+    }
+
+    /// Run a message and collect what it produced. Errors are only returned
+    /// directly if the message couldn't be started.
+    async fn run_opencode<F>(
+        &self,
+        run_guard: &ActiveRunGuard<'_>,
+        outgoing: &OutgoingMessage<'_>,
+        on_event: F,
+    ) -> Result<RunOutcome, MessageError>
+    where
+        F: Fn(MessageEvent) + Send + 'static,
+    {
+        let OutgoingMessage {
+            session_id,
+            message,
+            provider_id,
+            model_id,
+            ..
+        } = *outgoing;
+        let providers = ProviderRegistry::load()?;
+        providers.get(provider_id)?;
+
+        let workspace = self.ensure_session_workspace(session_id)?;
+        // Lets the user undo whatever the agent does with this message
+        if let Err(e) = self
+            .snapshots
+            .create(&workspace, session_id, message, now_millis())
+        {
+            eprintln!("[opencode] Warning: Failed to snapshot workspace: {}", e);
+        }
+        // Keep the workspace in sync with custom providers added since it was created
+        providers.write_opencode_config(&workspace)?;
+        let policy = PermissionPolicy::load()?;
+
+        // Forward events to the callbacks through a bounded channel, so a slow
+        // consumer slows down the reader instead of buffering without limit
+        let (event_tx, mut event_rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let event_session_id = session_id.to_string();
+        let forwarder = tokio::spawn(async move {
+            let mut execution_log = Vec::new();
+            while let Some(event) = event_rx.recv().await {
+                let event = match event {
+                    MessageEvent::Status(mut status) => {
+                        status.session_id = Some(event_session_id.clone());
+                        if status.update_type != "idle" {
+                            execution_log.push(status.clone());
+                        }
+                        MessageEvent::Status(status)
+                    }
+                    MessageEvent::Text(mut delta) => {
+                        delta.session_id = Some(event_session_id.clone());
+                        MessageEvent::Text(delta)
+                    }
+                    MessageEvent::Permission(mut request) => {
+                        request.session_id = Some(event_session_id.clone());
+                        MessageEvent::Permission(request)
+                    }
+                    MessageEvent::Proposal(proposal) => MessageEvent::Proposal(proposal),
+                };
+                on_event(event)
+            }
+            execution_log
+        });
+
+        let (session_usage, dry_run) = {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions.get(session_id)?;
+            (session.usage, session.dry_run)
+        };
+        self.dry_run.set_enabled(session_id, dry_run)?;
+        let _running = self.dry_run.start_run(session_id, &workspace)?;
+        let daily_usage = self
+            .usage_ledger
+            .lock()
+            .unwrap()
+            .day_total(&today(), provider_id);
+        let mut progress = RunProgress {
+            budget: BudgetTracker::new(&BudgetLimits::load()?, provider_id, session_usage, daily_usage),
+            // Only requests held back during this message are reported
+            proposed_request_ids: self
+                .dry_run
+                .proposals(session_id)?
+                .into_iter()
+                .map(|p| p.id)
+                .collect(),
+            ..Default::default()
+        };
+
+        let result = if let Some(exceeded) = progress.budget.check_start() {
+            eprintln!("[opencode] Not sending message: {}", exceeded);
+            let _ = event_tx
+                .send(MessageEvent::Status(budget_exceeded_status(&exceeded)))
+                .await;
+            progress.budget_exceeded = Some(exceeded);
+            Ok(())
+        } else {
+            let run = RunRequest {
+                run_guard,
+                session_id,
+                message,
+                provider_id,
+                model_id,
+                opencode_session_id: outgoing.opencode_session_id.as_deref(),
+                attachments: &outgoing.attachments,
+                workspace: &workspace,
+                event_tx: &event_tx,
+            };
+            let base_url = self.server_url(run_guard, &policy).await;
+            // Only the server can ask the user for approval
+            policy.write_opencode_config(&workspace, base_url.is_some())?;
+            match base_url {
+                Some(base_url) => self.run_on_server(&run, &base_url, &mut progress).await,
+                None => self.run_cli(&run, &mut progress).await,
+            }
+        };
+
+        // The session ID is kept, so the next message continues the same conversation
+        let cancelled = run_guard.was_cancelled();
+        if cancelled {
+            eprintln!("[opencode] Run was cancelled");
+            let _ = event_tx
+                .send(MessageEvent::Status(StatusUpdate {
+                    session_id: None,
+                    update_type: "cancelled".to_string(),
+                    message: Some("Cancelled".to_string()),
+                    details: Some(StatusUpdateDetails {
+                        timestamp: now_millis(),
+                        ..Default::default()
+                    }),
+                }))
+                .await;
+        }
+
+        // Make sure every event has reached the event sink before returning
+        drop(event_tx);
+        let execution_log = forwarder
+            .await
+            .map_err(|e| format!("Failed to forward opencode events: {}", e))?;
+
+        let result = if cancelled {
+            Err(MessageError::Other("Message cancelled".to_string()))
+        } else if let Some(exceeded) = progress.budget_exceeded.take() {
+            Err(MessageError::BudgetExceeded(exceeded))
+        } else {
+            result.map_err(MessageError::from).map(|_| {
+                let response_text = progress.text.full_text();
+                if response_text.is_empty() {
+                    eprintln!("[opencode] Warning: No response text captured");
+                    "No response received.".to_string()
+                } else {
+                    response_text
+                }
+            })
+        };
+        Ok(RunOutcome {
+            result,
+            execution_log,
+            usage: progress.usage,
+            // Files written before a run failed or was cancelled are kept too
+            artifacts: artifacts::collect(&workspace, &progress.written_files),
+        })
+    }
+
+    /// Base URL of the opencode server if messages should go through it.
+    /// Falls back to the one-shot CLI if the server is disabled or can't be started.
+    async fn server_url(&self, run_guard: &ActiveRunGuard<'_>, policy: &PermissionPolicy) -> Option<String> {
+        let runner = get_config_path()
+            .and_then(|path| ConfigFile::load(&path))
+            .map(|config| config.opencode_runner)
+            .unwrap_or_default();
+        if runner != OpencodeRunner::Server {
+            return None;
+        }
+        let mut env = self.run_env().ok()?;
+        // The server reads each workspace's opencode.json only once. opencode also takes the
+        // permissions from the environment, so a changed policy restarts the server.
+        env.push((
+            "OPENCODE_PERMISSION".to_string(),
+            policy.opencode_config(true).to_string(),
+        ));
+        // Restarting for new API keys would interrupt the runs of other sessions
+        let can_restart = self.active_runs.count() <= 1;
+        match self.server.ensure_running(&env, can_restart).await {
+            Ok(base_url) if !run_guard.was_cancelled() => Some(base_url),
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("[opencode] Server unavailable, falling back to opencode run: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Run a message with a one-shot `opencode run` process
+    async fn run_cli(&self, run: &RunRequest<'_>, progress: &mut RunProgress) -> Result<(), String> {
+        let full_model = format!("{}/{}", run.provider_id, run.model_id);
+        let mut cmd = Command::from(self.build_command(
+            run.message,
+            &full_model,
+            run.workspace,
+            run.opencode_session_id,
+            run.attachments,
+        )?);
+        cmd.kill_on_drop(true);
 
-#[derive(Debug, Deserialize)]
-struct EventPart {
-    #[serde(rename = "type")]
-    part_type: String,
-    #[serde(default)]
-    text: Option<String>,
-    #[serde(default)]
-    tool: Option<String>,
-    #[serde(default)]
-    state: Option<ToolState>,
-    #[serde(default)]
-    reason: Option<String>,
-}
+        // Spawn the process
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to spawn opencode: {}", e))?;
+        let pid = child
+            .id()
+            .ok_or_else(|| "Failed to get opencode process ID".to_string())?;
+        run.run_guard.set_pid(pid)?;
 
-#[derive(Debug, Deserialize)]
-struct ToolState {
-    status: Option<String>,
-    title: Option<String>,
-    input: Option<serde_json::Value>,
-    output: Option<String>,
-    error: Option<String>,
-    time: Option<ToolTime>,
-}
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "Failed to capture stdout".to_string())?;
 
-#[derive(Debug, Deserialize)]
-struct ToolTime {
-    start: Option<u64>,
-    end: Option<u64>,
-}
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| "Failed to capture stderr".to_string())?;
 
-pub struct OpencodeManager {
-    session_id: Arc<Mutex<Option<String>>>,
-    paths: AppPaths,
-    opencode_binary: PathBuf,
-    /// Temporary workspace directory. When dropped, it is automatically removed.
-    temp_workspace_dir: TempDir,
-}
+        // Spawn a task to read stderr and log it, keeping the last lines for error reporting
+        let stderr_reader = tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            let mut tail = VecDeque::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                eprintln!("[opencode stderr] {}", line);
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            Vec::from(tail).join("\n")
+        });
 
-impl OpencodeManager {
-    pub async fn new(app: &AppHandle) -> Result<Self, String> {
-        let paths = AppPaths::new(app)?;
-        let opencode_binary = paths.get_binary_path("opencode");
-        let source_workspace = &paths.opencode_workspace_path;
+        let events = self.read_events(run.session_id, stdout, &mut child, progress, run.event_tx);
+        let status = match timeout(RUN_TIMEOUT, events).await {
+            Ok(result) => result?,
+            Err(_) => {
+                eprintln!("[opencode] Run timed out, killing process tree");
+                kill_process_tree(pid)?;
+                let _ = child.wait().await;
+                return Err(Self::timeout_error());
+            }
+        };
 
-        // Create a temporary directory for the workspace
-        let temp_workspace_dir = TempDir::new()
-            .map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+        if !status.success() {
+            let stderr_tail = timeout(Duration::from_secs(5), stderr_reader)
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .unwrap_or_default();
 
-        // Copy the source workspace contents directly into the temp directory
-        copy_dir_contents(source_workspace, temp_workspace_dir.path())?;
+            // Resuming a conversation whose opencode session is gone: start over next time
+            if run.opencode_session_id.is_some() && is_missing_session_error(&stderr_tail) {
+                return Err(self.forget_opencode_session(run.session_id)?);
+            }
 
-        eprintln!(
-            "[opencode] Created temporary workspace at: {:?}",
-            temp_workspace_dir.path()
-        );
+            return Err(progress
+                .error
+                .take()
+                .unwrap_or_else(|| format!("opencode exited with status: {}", status)));
+        }
 
-        Ok(Self {
-            session_id: Arc::new(Mutex::new(None)),
-            paths,
-            opencode_binary,
-            temp_workspace_dir,
-        })
+        // opencode may exit cleanly after reporting that the message failed
+        if let Some(error) = progress.error.take() {
+            if progress.text.full_text().is_empty() {
+                return Err(error);
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn send_message<F>(
+    /// Run a message on the long-lived opencode server, following its progress through the event stream
+    async fn run_on_server(
         &self,
-        message: &str,
-        provider_id: &str,
-        model_id: &str,
-        status_callback: F,
-    ) -> Result<String, String>
-    where
-        F: Fn(StatusUpdate) + Send + 'static,
-    {
-        // Build the model string for opencode: "provider/model"
-        // Also strip date suffixes like "-20251101" from model IDs
-        let model_base = Self::strip_model_date_suffix(model_id);
-        let full_model = format!("{}/{}", provider_id, model_base);
+        run: &RunRequest<'_>,
+        base_url: &str,
+        progress: &mut RunProgress,
+    ) -> Result<(), String> {
+        // Subscribe before prompting so no event is missed
+        let mut events = self.server.events(base_url, run.workspace).await?;
 
-        // Build the command
-        let mut cmd = Command::new(&self.opencode_binary);
-        cmd.arg("run")
-            .arg("-m")
-            .arg(&full_model)
-            .arg("--format")
-            .arg("json");
+        let opencode_session_id = match run.opencode_session_id {
+            Some(sid) => {
+                eprintln!("[opencode] Using existing session: {}", sid);
+                sid.to_string()
+            }
+            None => {
+                let sid = self.server.create_session(base_url, run.workspace).await?;
+                eprintln!("[opencode] Started new session: {}", sid);
+                let mut sessions = self.sessions.lock().unwrap();
+                sessions.set_opencode_session_id(run.session_id, &sid)?;
+                self.save_sessions(&sessions)?;
+                sid
+            }
+        };
 
-        // Add session ID if we have one from a previous message
+        eprintln!(
+            "[opencode] Sending message to {} with model {}/{}",
+            base_url, run.provider_id, run.model_id
+        );
+        if let Err(e) = self
+            .server
+            .prompt(
+                base_url,
+                run.workspace,
+                &opencode_session_id,
+                &Prompt {
+                    provider_id: run.provider_id,
+                    model_id: run.model_id,
+                    message: run.message,
+                    attachments: run.attachments,
+                },
+            )
+            .await
         {
-            let session_guard = self.session_id.lock().unwrap();
-            if let Some(ref sid) = *session_guard {
-                cmd.arg("--session").arg(sid);
-                eprintln!("[opencode] Using existing session: {}", sid);
-            } else {
-                eprintln!("[opencode] Starting new session");
+            if run.opencode_session_id.is_some() && is_missing_session_error(&e) {
+                return Err(self.forget_opencode_session(run.session_id)?);
             }
+            return Err(e);
         }
 
-        // Add the message
-        cmd.arg(message);
+        let mut server_run = ServerRun::default();
+        let follow = async {
+            loop {
+                let event = tokio::select! {
+                    event = events.next() => event?,
+                    _ = tokio::time::sleep(CANCEL_POLL_INTERVAL) => {
+                        if run.run_guard.was_cancelled() {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                };
+                let Some(event) = event else {
+                    return Err("The opencode server stopped unexpectedly".to_string());
+                };
+                match server_run.translate(&event, &opencode_session_id) {
+                    ServerUpdate::Event(event) => {
+                        self.handle_event(run.session_id, &event, progress, run.event_tx)
+                            .await?;
+                        if let Some(error) = progress.error.take() {
+                            return Err(error);
+                        }
+                        if let Some(exceeded) = &progress.budget_exceeded {
+                            return Err(exceeded.to_string());
+                        }
+                    }
+                    ServerUpdate::Permission(request) => {
+                        eprintln!("[opencode] Waiting for approval of {} ({})", request.title, request.id);
+                        self.pending_permissions.lock().unwrap().insert(
+                            request.id.clone(),
+                            PendingPermission {
+                                session_id: run.session_id.to_string(),
+                                opencode_session_id: opencode_session_id.clone(),
+                                base_url: base_url.to_string(),
+                                workspace: run.workspace.to_path_buf(),
+                            },
+                        );
+                        let status = status_update("permission", format!("Waiting for approval: {}", request.title));
+                        let _ = run.event_tx.send(MessageEvent::Status(status)).await;
+                        let _ = run.event_tx.send(MessageEvent::Permission(request)).await;
+                    }
+                    ServerUpdate::Idle => return Ok(()),
+                    ServerUpdate::Ignore => {}
+                }
+            }
+        };
 
-        // Set up environment
-        let path_env = self.paths.get_path_env();
+        let result = match timeout(RUN_TIMEOUT, follow).await {
+            Ok(result) => result,
+            Err(_) => Err(Self::timeout_error()),
+        };
+        if result.is_err() || run.run_guard.was_cancelled() {
+            // Don't leave the session working in the background
+            if let Err(e) = self
+                .server
+                .abort(base_url, run.workspace, &opencode_session_id)
+                .await
+            {
+                eprintln!("[opencode] Failed to abort session {}: {}", opencode_session_id, e);
+            }
+        }
+        self.pending_permissions
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.session_id != run.session_id);
+        result
+    }
 
-        eprintln!(
-            "[opencode] Running: {:?} run -m {} --format json <message>",
-            self.opencode_binary, full_model
-        );
-        eprintln!("[opencode] Working directory: {:?}", self.temp_workspace_dir.path());
+    /// Forget a session's opencode session, which opencode no longer knows.
+    /// Returns the error to show the user.
+    fn forget_opencode_session(&self, session_id: &str) -> Result<String, String> {
+        eprintln!("[opencode] opencode no longer knows the session of {}", session_id);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.clear_opencode_session_id(session_id)?;
+        self.save_sessions(&sessions)?;
+        Ok(
+            "This conversation can no longer be resumed because opencode no longer has its session. \
+             Send your message again to continue in a new session."
+                .to_string(),
+        )
+    }
 
-        cmd.env("PATH", &path_env)
-            .env(
-                "PLAYWRIGHT_BROWSERS_PATH",
-                self.paths.get_playwright_browsers_path(),
-            )
-            .current_dir(self.temp_workspace_dir.path())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+    fn timeout_error() -> String {
+        format!(
+            "opencode did not finish within {} minutes",
+            RUN_TIMEOUT.as_secs() / 60
+        )
+    }
 
-        // Inject API credentials from the keychain
+    /// Kill the opencode server, if one was started
+    pub fn shutdown(&self) {
+        self.server.shutdown();
+    }
+
+    /// Environment opencode runs with: tool paths and API keys
+    fn run_env(&self) -> Result<Vec<(String, String)>, String> {
+        // The latchkey shim stands in for native_tools, so it can hold back requests in dry run mode
+        let mut env = vec![
+            (
+                "PATH".to_string(),
+                self.paths.get_path_env_with(&self.dry_run.shim_dir()),
+            ),
+            (
+                "PLAYWRIGHT_BROWSERS_PATH".to_string(),
+                self.paths.get_playwright_browsers_path().to_string_lossy().to_string(),
+            ),
+        ];
+
+        // Inject API credentials from the secret store
         match CredentialManager::get_credentials_as_env_vars() {
             Ok(env_vars) => {
                 for (key, value) in env_vars {
                     eprintln!("[opencode] Setting env var: {} (key present)", key);
-                    cmd.env(key, value);
+                    env.push((key, value));
                 }
             }
+            // Running without keys would only fail later with a less helpful error
+            Err(CredentialError::Locked) => return Err(CredentialError::Locked.to_string()),
             Err(e) => {
                 eprintln!("[opencode] Warning: Failed to get credentials: {}", e);
             }
         }
 
-        // Spawn the process
-        let mut child = cmd
-            .spawn()
-            .map_err(|e| format!("Failed to spawn opencode: {}", e))?;
+        Ok(env)
+    }
 
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| "Failed to capture stdout".to_string())?;
+    /// Build the `opencode run` command for a message
+    fn build_command(
+        &self,
+        message: &str,
+        full_model: &str,
+        workspace: &Path,
+        opencode_session_id: Option<&str>,
+        attachments: &[Attachment],
+    ) -> Result<std::process::Command, String> {
+        let mut cmd = std::process::Command::new(&self.opencode_binary);
+        cmd.arg("run")
+            .arg("-m")
+            .arg(full_model)
+            .arg("--format")
+            .arg("json");
 
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| "Failed to capture stderr".to_string())?;
+        // Add session ID if we have one from a previous message
+        if let Some(sid) = opencode_session_id {
+            cmd.arg("--session").arg(sid);
+            eprintln!("[opencode] Using existing session: {}", sid);
+        } else {
+            eprintln!("[opencode] Starting new session");
+        }
 
-        // Spawn a thread to read stderr and log it
-        std::thread::spawn(move || {
-            let reader = BufReader::new(stderr);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    eprintln!("[opencode stderr] {}", line);
-                }
-            }
-        });
+        // Add the message
+        cmd.arg(message);
+
+        // After the message, since `--file` takes all the values that follow it
+        for attachment in attachments {
+            cmd.arg("--file").arg(&attachment.path);
+        }
 
-        // Read and process output line by line
-        let reader = BufReader::new(stdout);
-        let mut response_text = String::new();
-        let session_id_clone = self.session_id.clone();
+        eprintln!(
+            "[opencode] Running: {:?} run -m {} --format json <message>",
+            self.opencode_binary, full_model
+        );
+        eprintln!("[opencode] Working directory: {:?}", workspace);
+
+        cmd.envs(self.run_env()?)
+            .current_dir(workspace)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        isolate_process_group(&mut cmd);
+
+        Ok(cmd)
+    }
+
+    /// Read JSON events from opencode's stdout until it exits
+    async fn read_events(
+        &self,
+        session_id: &str,
+        stdout: ChildStdout,
+        child: &mut Child,
+        progress: &mut RunProgress,
+        event_tx: &mpsc::Sender<MessageEvent>,
+    ) -> Result<ExitStatus, String> {
+        let mut lines = BufReader::new(stdout).lines();
         let mut line_count = 0;
 
-        for line in reader.lines() {
-            let line = line.map_err(|e| format!("Failed to read line: {}", e))?;
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|e| format!("Failed to read line: {}", e))?
+        {
             if line.is_empty() {
                 continue;
             }
@@ -228,39 +1731,16 @@ impl OpencodeManager {
                 }
             };
 
-            eprintln!(
-                "[opencode] Event type: {}, has part: {}",
-                event.event_type,
-                event.part.is_some()
-            );
-
-            // Capture session ID from first event
-            if let Some(ref sid) = event.session_id {
-                let mut session_guard = session_id_clone.lock().unwrap();
-                if session_guard.is_none() {
-                    eprintln!("[opencode] Captured session ID: {}", sid);
-                    *session_guard = Some(sid.clone());
-                }
-            }
+            eprintln!("[opencode] Event type: {}", event.event_type());
 
-            // Process the event and send status updates
-            if let Some(status) = Self::process_event(&event) {
-                status_callback(status);
-            }
+            self.handle_event(session_id, &event, progress, event_tx)
+                .await?;
 
-            // Extract text from text events
-            if event.event_type == "text" {
-                if let Some(ref part) = event.part {
-                    eprintln!(
-                        "[opencode] Text event - part_type: {}, has text: {}",
-                        part.part_type,
-                        part.text.is_some()
-                    );
-                    if let Some(ref text) = part.text {
-                        eprintln!("[opencode] Got response text ({} chars)", text.len());
-                        response_text = text.clone();
-                    }
+            if progress.budget_exceeded.is_some() {
+                if let Some(pid) = child.id() {
+                    kill_process_tree(pid)?;
                 }
+                break;
             }
         }
 
@@ -269,146 +1749,93 @@ impl OpencodeManager {
         // Wait for the process to finish
         let status = child
             .wait()
+            .await
             .map_err(|e| format!("Failed to wait for opencode: {}", e))?;
 
         eprintln!("[opencode] Process exited with status: {}", status);
 
-        if !status.success() {
-            return Err(format!("opencode exited with status: {}", status));
+        Ok(status)
+    }
+
+    /// Record an event's session and text and pass its status on to the callbacks
+    async fn handle_event(
+        &self,
+        session_id: &str,
+        event: &OpencodeEvent,
+        progress: &mut RunProgress,
+        event_tx: &mpsc::Sender<MessageEvent>,
+    ) -> Result<(), String> {
+        // Capture session ID from first event
+        if let Some(ref sid) = event.session_id {
+            self.sessions
+                .lock()
+                .unwrap()
+                .set_opencode_session_id(session_id, sid)?;
         }
 
-        if response_text.is_empty() {
-            eprintln!("[opencode] Warning: No response text captured");
-            Ok("No response received.".to_string())
-        } else {
-            Ok(response_text)
-        }
-    }
-
-    fn process_event(event: &OpencodeEvent) -> Option<StatusUpdate> {
-        match event.event_type.as_str() {
-            "step_start" => Some(StatusUpdate {
-                update_type: "busy".to_string(),
-                message: Some("Thinking...".to_string()),
-                details: Some(StatusUpdateDetails {
-                    timestamp: Self::now_millis(),
-                    ..Default::default()
-                }),
-            }),
-            "step_finish" => {
-                let reason = event
-                    .part
-                    .as_ref()
-                    .and_then(|p| p.reason.clone())
-                    .unwrap_or_default();
-                Some(StatusUpdate {
-                    update_type: "idle".to_string(),
-                    message: Some(format!("Finished ({})", reason)),
-                    details: Some(StatusUpdateDetails {
-                        timestamp: Self::now_millis(),
-                        ..Default::default()
-                    }),
-                })
+        if let EventKind::Raw(_) = event.kind {
+            eprintln!("[opencode] Ignoring unknown event type: {}", event.event_type());
+        }
+
+        for status in progress.process_event(event) {
+            let _ = event_tx.send(MessageEvent::Status(status)).await;
+        }
+
+        match event.kind {
+            EventKind::Text { ref part } => {
+                if let Some(delta) = progress.text.update(&part.message_id, &part.id, &part.text) {
+                    let _ = event_tx.send(MessageEvent::Text(delta)).await;
+                }
             }
-            "text" => Some(StatusUpdate {
-                update_type: "generating".to_string(),
-                message: Some("Generating response...".to_string()),
-                details: Some(StatusUpdateDetails {
-                    timestamp: Self::now_millis(),
-                    ..Default::default()
-                }),
-            }),
-            "tool_start" => {
-                let part = event.part.as_ref()?;
-                let tool_name = part.tool.as_ref()?;
-                let state = part.state.as_ref();
-                let title = state.and_then(|s| s.title.as_deref());
-                let description = Self::get_tool_description(tool_name, title);
-                let input = state.and_then(|s| s.input.clone());
-                let input_short = Self::format_tool_input_for_status(tool_name, &input);
-                let input_full = Self::format_tool_input_for_log(tool_name, &input);
-
-                let format_with_input = |desc: &str, input_str: &str| {
-                    if input_str.is_empty() {
-                        desc.to_string()
-                    } else {
-                        format!("{}: {}", desc, input_str)
+            EventKind::StepFinish { ref part }
+                if part.id.is_empty() || progress.finished_step_ids.insert(part.id.clone()) =>
+            {
+                progress.usage.add(&Usage::from_step(part));
+                if progress.budget_exceeded.is_none() {
+                    if let Some(exceeded) = progress.budget.check(&progress.usage) {
+                        eprintln!("[opencode] Stopping run: {}", exceeded);
+                        let status = budget_exceeded_status(&exceeded);
+                        let _ = event_tx.send(MessageEvent::Status(status)).await;
+                        progress.budget_exceeded = Some(exceeded);
                     }
-                };
-
-                Some(StatusUpdate {
-                    update_type: "tool".to_string(),
-                    message: Some(format_with_input(&description, &input_short)),
-                    details: Some(StatusUpdateDetails {
-                        full_message: Some(format_with_input(&description, &input_full)),
-                        tool_name: Some(tool_name.clone()),
-                        timestamp: Self::now_millis(),
-                        input,
-                        ..Default::default()
-                    }),
-                })
+                }
             }
-            "tool_finish" => {
-                let part = event.part.as_ref()?;
-                let tool_name = part.tool.as_ref()?;
-                let state = part.state.as_ref();
-                let title = state.and_then(|s| s.title.as_deref());
-                let description = Self::get_tool_description(tool_name, title);
-                let error = state.and_then(|s| s.error.clone());
-                let output = state.and_then(|s| s.output.clone());
-                let duration = state
-                    .and_then(|s| s.time.as_ref())
-                    .and_then(|t| Some(t.end? - t.start?));
-
-                let is_error = error.is_some();
-                let (update_type, message) = if is_error {
-                    (
-                        "tool-error",
-                        format!(
-                            "Error: {}",
-                            error.as_deref().unwrap_or("Unknown error")
-                        ),
-                    )
-                } else {
-                    ("tool-completed", format!("{} completed", description))
-                };
-
-                Some(StatusUpdate {
-                    update_type: update_type.to_string(),
-                    message: Some(message),
-                    details: Some(StatusUpdateDetails {
-                        tool_name: Some(tool_name.clone()),
-                        timestamp: Self::now_millis(),
-                        output: if is_error { None } else { output },
-                        error: if is_error { error } else { None },
-                        duration,
-                        ..Default::default()
-                    }),
-                })
+            EventKind::ToolUse { ref part } | EventKind::ToolFinish { ref part }
+                if part.state.is_finished() =>
+            {
+                if part.tool == "bash" && progress.audited_tool_ids.insert(part.id.clone()) {
+                    self.audit_bash_call(session_id, event.timestamp, part);
+                }
+                // The latchkey shim records requests it holds back before the tool finishes
+                for proposal in self.dry_run.proposals(session_id)? {
+                    if progress.proposed_request_ids.insert(proposal.id.clone()) {
+                        eprintln!("[dry-run] Held back {} {:?}", proposal.method, proposal.url);
+                        let _ = event_tx.send(MessageEvent::Proposal(proposal)).await;
+                    }
+                }
             }
-            _ => None,
+            _ => {}
         }
-    }
 
-    fn now_millis() -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0)
+        Ok(())
     }
 
-    /// Strip date suffixes like "-20251101" from model IDs
-    /// e.g., "claude-opus-4-5-20251101" -> "claude-opus-4-5"
-    fn strip_model_date_suffix(model_id: &str) -> &str {
-        // Look for pattern like "-YYYYMMDD" at the end (8 digits after a dash)
-        if let Some(pos) = model_id.rfind('-') {
-            let suffix = &model_id[pos + 1..];
-            // Check if suffix is exactly 8 digits (a date like 20251101)
-            if suffix.len() == 8 && suffix.chars().all(|c| c.is_ascii_digit()) {
-                return &model_id[..pos];
-            }
+    /// Log the latchkey requests a finished bash call made
+    fn audit_bash_call(&self, session_id: &str, timestamp: Option<u64>, part: &ToolPart) {
+        let Some(command) = part.state.input().and_then(|input| input["command"].as_str()) else {
+            return;
+        };
+        let output = match &part.state {
+            ToolState::Completed { output, .. } => output.as_str(),
+            ToolState::Error { error, .. } => error.as_str(),
+            ToolState::Pending { .. } | ToolState::Running { .. } => "",
+        };
+        let timestamp = timestamp.unwrap_or_else(now_millis);
+        let entries = AuditEntry::from_bash(session_id, timestamp, command, output);
+        // A failure to log shouldn't fail the message, the request has been made anyway
+        if let Err(e) = self.audit_log.append(&entries) {
+            eprintln!("[audit] Warning: Failed to log latchkey requests: {}", e);
         }
-        model_id
     }
 
     fn get_tool_description(tool_name: &str, title: Option<&str>) -> String {
@@ -431,11 +1858,12 @@ impl OpencodeManager {
         .to_string()
     }
 
+    /// Shorten text to at most `max_length` characters, cutting on character boundaries
     fn truncate_for_status(text: &str, max_length: usize) -> String {
-        if text.len() <= max_length {
+        if text.chars().count() <= max_length {
             text.to_string()
         } else {
-            format!("{}...", &text[..max_length - 3])
+            format!("{}...", text.chars().take(max_length.saturating_sub(3)).collect::<String>())
         }
     }
 
@@ -539,3 +1967,322 @@ impl OpencodeManager {
         }
     }
 }
+
+/// Whether opencode's stderr says the session passed with `--session` doesn't exist
+fn is_missing_session_error(stderr: &str) -> bool {
+    stderr.lines().any(|line| {
+        let line = line.to_lowercase();
+        line.contains("session") && (line.contains("not found") || line.contains("notfounderror"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_text_streams_appended_text_as_deltas() {
+        let mut response = ResponseText::default();
+        let first = response.update("msg1", "prt1", "Hello").unwrap();
+        assert_eq!(first.delta, "Hello");
+        assert!(!first.reset);
+
+        let second = response.update("msg1", "prt1", "Hello, world").unwrap();
+        assert_eq!(second.delta, ", world");
+        assert!(!second.reset);
+
+        assert!(response.update("msg1", "prt1", "Hello, world").is_none());
+        assert_eq!(response.full_text(), "Hello, world");
+    }
+
+    #[test]
+    fn test_response_text_resets_rewritten_part() {
+        let mut response = ResponseText::default();
+        response.update("msg1", "prt1", "Draft answer");
+        let delta = response.update("msg1", "prt1", "Final answer").unwrap();
+        assert_eq!(delta.delta, "Final answer");
+        assert!(delta.reset);
+        assert_eq!(response.full_text(), "Final answer");
+    }
+
+    #[test]
+    fn test_response_text_joins_parts_in_order() {
+        let mut response = ResponseText::default();
+        response.update("msg1", "prt1", "Let me check Slack.");
+        response.update("msg2", "prt2", "You have 3 unread messages.");
+        response.update("msg1", "prt1", "Let me check Slack for you.");
+        assert_eq!(
+            response.full_text(),
+            "Let me check Slack for you.\n\nYou have 3 unread messages."
+        );
+    }
+
+    #[test]
+    fn test_is_missing_session_error() {
+        assert!(is_missing_session_error(
+            "Error: Session not found: ses_4a1b2c\n    at Storage.read"
+        ));
+        assert!(is_missing_session_error("NotFoundError: Resource not found: session/ses_1"));
+        assert!(!is_missing_session_error("Error: Invalid API key"));
+        assert!(!is_missing_session_error(""));
+    }
+
+    fn server_event(value: serde_json::Value) -> ServerEvent {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn translated_type(update: ServerUpdate) -> Option<String> {
+        match update {
+            ServerUpdate::Event(event) => Some(event.event_type().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Run a recorded `opencode run --format json` stream through a run's progress
+    fn replay(jsonl: &str) -> (RunProgress, Vec<StatusUpdate>) {
+        let mut progress = RunProgress::default();
+        let mut statuses = Vec::new();
+        for line in jsonl.lines().filter(|line| !line.trim().is_empty()) {
+            let event: OpencodeEvent = serde_json::from_str(line).unwrap();
+            statuses.extend(progress.process_event(&event));
+            if let EventKind::Text { part } = &event.kind {
+                progress.text.update(&part.message_id, &part.id, &part.text);
+            }
+        }
+        (progress, statuses)
+    }
+
+    fn status_types(statuses: &[StatusUpdate]) -> Vec<&str> {
+        statuses.iter().map(|s| s.update_type.as_str()).collect()
+    }
+
+    #[test]
+    fn test_tool_run_statuses() {
+        let (progress, statuses) = replay(include_str!("../tests/fixtures/tool_run.jsonl"));
+        assert_eq!(
+            status_types(&statuses),
+            vec!["busy", "tool", "tool-completed", "idle", "busy", "generating", "idle"]
+        );
+        assert_eq!(
+            statuses[1].details.as_ref().unwrap().full_message.as_deref(),
+            Some("latchkey services list: latchkey services list")
+        );
+        let completed = statuses[2].details.as_ref().unwrap();
+        assert_eq!(completed.output.as_deref(), Some("slack\ngithub\nlinear\n"));
+        assert_eq!(completed.duration, Some(412));
+        assert_eq!(statuses[6].message.as_deref(), Some("Finished (stop)"));
+        assert_eq!(
+            progress.text.full_text(),
+            "I can reach **Slack**, **GitHub** and **Linear** for you."
+        );
+        assert!(progress.error.is_none());
+    }
+
+    #[test]
+    fn test_reasoning_retry_and_error_statuses() {
+        let (progress, statuses) = replay(include_str!("../tests/fixtures/reasoning_error.jsonl"));
+        assert_eq!(
+            status_types(&statuses),
+            vec!["busy", "reasoning", "retry", "tool", "tool-error", "error"]
+        );
+        assert_eq!(
+            statuses[1].message.as_deref(),
+            Some("The user wants last week's invoices.")
+        );
+        assert!(statuses[1]
+            .details
+            .as_ref()
+            .unwrap()
+            .full_message
+            .as_deref()
+            .unwrap()
+            .ends_with("read the exported CSV first."));
+        assert_eq!(statuses[2].message.as_deref(), Some("Retrying (attempt 1): Overloaded"));
+        assert_eq!(
+            statuses[4].details.as_ref().unwrap().error.as_deref(),
+            Some("File not found: /tmp/invoices.csv")
+        );
+        assert_eq!(
+            progress.error.as_deref(),
+            Some("Rate limit exceeded, please slow down")
+        );
+    }
+
+    #[test]
+    fn test_truncate_for_status_keeps_characters_whole() {
+        assert_eq!(OpencodeManager::truncate_for_status("short", 10), "short");
+        assert_eq!(OpencodeManager::truncate_for_status("abcdefghijk", 10), "abcdefg...");
+        // Every cut point falls inside a multibyte character when slicing bytes
+        let reasoning = "“Überprüfe” die Rechnungen 📄 für 東京 und Zürich, bevor ich antworte";
+        for max_length in 4..reasoning.chars().count() {
+            let truncated = OpencodeManager::truncate_for_status(reasoning, max_length);
+            assert_eq!(truncated.chars().count(), max_length);
+            assert!(truncated.ends_with("..."));
+        }
+    }
+
+    #[test]
+    fn test_written_files_are_recorded() {
+        let tool_use = |id: &str, tool: &str, status: &str, path: &str| {
+            serde_json::json!({
+                "type": "tool_use",
+                "part": {
+                    "id": id,
+                    "tool": tool,
+                    "state": {"status": status, "input": {"filePath": path}, "error": "Failed"},
+                },
+            })
+            .to_string()
+        };
+        let jsonl = [
+            tool_use("prt_1", "write", "completed", "/tmp/workspace/report.md"),
+            tool_use("prt_1", "write", "completed", "/tmp/workspace/report.md"),
+            tool_use("prt_2", "edit", "error", "notes.md"),
+            tool_use("prt_3", "read", "completed", "data.csv"),
+            tool_use("prt_4", "edit", "completed", "report.md"),
+        ]
+        .join("\n");
+        let (progress, _) = replay(&jsonl);
+        assert_eq!(
+            progress.written_files,
+            vec![
+                WrittenFile {
+                    tool: "write".to_string(),
+                    path: "/tmp/workspace/report.md".to_string()
+                },
+                WrittenFile {
+                    tool: "edit".to_string(),
+                    path: "report.md".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_events_cause_no_status() {
+        let (progress, statuses) = replay(include_str!("../tests/fixtures/unknown_events.jsonl"));
+        assert_eq!(status_types(&statuses), vec!["generating"]);
+        assert_eq!(progress.text.full_text(), "Done.");
+    }
+
+    #[test]
+    fn test_server_events_translate_to_cli_events() {
+        let mut run = ServerRun::default();
+        let user_message = server_event(serde_json::json!({
+            "type": "message.updated",
+            "properties": {"info": {"id": "msg_user", "sessionID": "ses_1", "role": "user"}}
+        }));
+        assert!(matches!(run.translate(&user_message, "ses_1"), ServerUpdate::Ignore));
+
+        let part = |message_id: &str, part: serde_json::Value| {
+            let mut part = part;
+            part["sessionID"] = "ses_1".into();
+            part["messageID"] = message_id.into();
+            server_event(serde_json::json!({"type": "message.part.updated", "properties": {"part": part}}))
+        };
+
+        // The user's own text is not part of the response
+        let user_text = part("msg_user", serde_json::json!({"id": "prt_0", "type": "text", "text": "Hi"}));
+        assert!(translated_type(run.translate(&user_text, "ses_1")).is_none());
+
+        let text = part("msg_bot", serde_json::json!({"id": "prt_1", "type": "text", "text": "He"}));
+        assert_eq!(translated_type(run.translate(&text, "ses_1")).as_deref(), Some("text"));
+
+        let reasoning = part(
+            "msg_bot",
+            serde_json::json!({"id": "prt_3", "type": "reasoning", "text": "Hmm", "time": {"start": 1}}),
+        );
+        assert_eq!(translated_type(run.translate(&reasoning, "ses_1")).as_deref(), Some("reasoning"));
+
+        let tool = part(
+            "msg_bot",
+            serde_json::json!({"id": "prt_2", "type": "tool", "tool": "bash", "state": {"status": "running"}}),
+        );
+        assert_eq!(translated_type(run.translate(&tool, "ses_1")).as_deref(), Some("tool_use"));
+
+        let retry = server_event(serde_json::json!({
+            "type": "session.status",
+            "properties": {"sessionID": "ses_1", "status": {"type": "retry", "attempt": 2, "message": "Overloaded", "next": 5}}
+        }));
+        assert_eq!(translated_type(run.translate(&retry, "ses_1")).as_deref(), Some("retry"));
+        let busy = server_event(serde_json::json!({
+            "type": "session.status",
+            "properties": {"sessionID": "ses_1", "status": {"type": "busy"}}
+        }));
+        assert!(matches!(run.translate(&busy, "ses_1"), ServerUpdate::Ignore));
+
+        // Events of other sessions are ignored
+        let other = server_event(serde_json::json!({"type": "session.idle", "properties": {"sessionID": "ses_2"}}));
+        assert!(matches!(run.translate(&other, "ses_1"), ServerUpdate::Ignore));
+        let idle = server_event(serde_json::json!({"type": "session.idle", "properties": {"sessionID": "ses_1"}}));
+        assert!(matches!(run.translate(&idle, "ses_1"), ServerUpdate::Idle));
+    }
+
+    #[test]
+    fn test_server_updates_are_reported_once() {
+        let mut progress = RunProgress::default();
+        let mut report = |part: serde_json::Value| {
+            let event: OpencodeEvent = serde_json::from_value(part).unwrap();
+            status_types(&progress.process_event(&event))
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        let tool = |status: &str| {
+            serde_json::json!({"type": "tool_use", "part": {"id": "prt_2", "tool": "bash", "state": {"status": status}}})
+        };
+        assert!(report(tool("pending")).is_empty());
+        assert_eq!(report(tool("running")), vec!["tool"]);
+        assert!(report(tool("running")).is_empty());
+        assert_eq!(report(tool("completed")), vec!["tool-completed"]);
+        assert!(report(tool("completed")).is_empty());
+
+        let reasoning = |end: Option<u64>| {
+            serde_json::json!({"type": "reasoning", "part": {"id": "prt_3", "text": "Hmm", "time": {"start": 1, "end": end}}})
+        };
+        assert!(report(reasoning(None)).is_empty());
+        assert_eq!(report(reasoning(Some(2))), vec!["reasoning"]);
+        assert!(report(reasoning(Some(2))).is_empty());
+    }
+
+    #[test]
+    fn test_server_permission_request() {
+        let mut run = ServerRun::default();
+        let asked = |session_id: &str| {
+            server_event(serde_json::json!({
+                "type": "permission.updated",
+                "properties": {
+                    "id": "per_1",
+                    "type": "bash",
+                    "pattern": ["latchkey curl -X DELETE *"],
+                    "sessionID": session_id,
+                    "messageID": "msg_bot",
+                    "title": "latchkey curl -X DELETE https://api.github.com/repos/o/r"
+                }
+            }))
+        };
+        assert!(matches!(
+            run.translate(&asked("ses_1"), "ses_1"),
+            ServerUpdate::Permission(request) if request.id == "per_1" && request.permission == "bash"
+        ));
+        assert!(matches!(run.translate(&asked("ses_2"), "ses_1"), ServerUpdate::Ignore));
+    }
+
+    #[test]
+    fn test_server_session_error() {
+        let mut run = ServerRun::default();
+        let error = server_event(serde_json::json!({
+            "type": "session.error",
+            "properties": {
+                "sessionID": "ses_1",
+                "error": {"name": "ProviderAuthError", "data": {"message": "Invalid API key"}}
+            }
+        }));
+        let ServerUpdate::Event(event) = run.translate(&error, "ses_1") else {
+            panic!("expected an error event");
+        };
+        let mut progress = RunProgress::default();
+        assert_eq!(status_types(&progress.process_event(&event)), vec!["error"]);
+        assert_eq!(progress.error.as_deref(), Some("Invalid API key"));
+    }
+}
@@ -0,0 +1,362 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// A line of `opencode run --format json` output
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpencodeEvent {
+    pub session_id: Option<String>,
+    /// Milliseconds since the epoch
+    pub timestamp: Option<u64>,
+    pub kind: EventKind,
+}
+
+/// The events opencode reports, tagged by their `type`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    StepStart {
+        part: StepStartPart,
+    },
+    StepFinish {
+        part: StepFinishPart,
+    },
+    /// A finished text part, or the latest state of one streamed by the server
+    Text {
+        part: TextPart,
+    },
+    Reasoning {
+        part: TextPart,
+    },
+    ToolUse {
+        part: ToolPart,
+    },
+    /// Older opencode versions report tools starting and finishing separately
+    ToolStart {
+        part: ToolPart,
+    },
+    ToolFinish {
+        part: ToolPart,
+    },
+    /// A request to the provider failed and will be retried
+    Retry {
+        attempt: u32,
+        #[serde(default)]
+        message: String,
+        /// When the next attempt is made, in milliseconds since the epoch
+        next: Option<u64>,
+    },
+    Error {
+        error: EventError,
+    },
+    /// An event this version doesn't know, or that doesn't match its schema, as received
+    #[serde(skip)]
+    Raw(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StepStartPart {
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "messageID", default)]
+    pub message_id: String,
+    pub snapshot: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StepFinishPart {
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "messageID", default)]
+    pub message_id: String,
+    /// Why the step ended, e.g. "stop" or "tool-calls"
+    pub reason: Option<String>,
+    /// Cost of the step in US dollars
+    pub cost: Option<f64>,
+    pub tokens: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TokenUsage {
+    pub input: u64,
+    pub output: u64,
+    pub reasoning: u64,
+    pub cache: CacheTokens,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CacheTokens {
+    pub read: u64,
+    pub write: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TextPart {
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "messageID", default)]
+    pub message_id: String,
+    #[serde(default)]
+    pub text: String,
+    pub time: Option<PartTime>,
+}
+
+impl TextPart {
+    /// Whether the part is complete. The CLI only reports finished parts,
+    /// the server also reports them while they are being streamed.
+    pub fn is_finished(&self) -> bool {
+        self.time.as_ref().is_none_or(|time| time.end.is_some())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ToolPart {
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "messageID", default)]
+    pub message_id: String,
+    pub tool: String,
+    #[serde(rename = "callID")]
+    pub call_id: Option<String>,
+    pub state: ToolState,
+}
+
+/// A tool call's progress, tagged by its `status`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ToolState {
+    Pending {
+        input: Option<Value>,
+    },
+    Running {
+        input: Option<Value>,
+        title: Option<String>,
+        time: Option<PartTime>,
+    },
+    Completed {
+        input: Option<Value>,
+        #[serde(default)]
+        output: String,
+        title: Option<String>,
+        time: Option<PartTime>,
+    },
+    Error {
+        input: Option<Value>,
+        #[serde(default)]
+        error: String,
+        time: Option<PartTime>,
+    },
+}
+
+impl ToolState {
+    pub fn input(&self) -> Option<&Value> {
+        match self {
+            ToolState::Pending { input }
+            | ToolState::Running { input, .. }
+            | ToolState::Completed { input, .. }
+            | ToolState::Error { input, .. } => input.as_ref(),
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            ToolState::Running { title, .. } | ToolState::Completed { title, .. } => title.as_deref(),
+            ToolState::Pending { .. } | ToolState::Error { .. } => None,
+        }
+    }
+
+    /// How long the tool ran, in milliseconds, once it has finished
+    pub fn duration(&self) -> Option<u64> {
+        match self {
+            ToolState::Completed { time, .. } | ToolState::Error { time, .. } => {
+                let time = time.as_ref()?;
+                Some(time.end?.saturating_sub(time.start?))
+            }
+            ToolState::Pending { .. } | ToolState::Running { .. } => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, ToolState::Completed { .. } | ToolState::Error { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PartTime {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+/// An error as opencode names it, e.g. `{"name": "ProviderAuthError", "data": {"message": "..."}}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EventError {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub data: Value,
+}
+
+impl EventError {
+    pub fn message(&self) -> String {
+        self.data["message"]
+            .as_str()
+            .filter(|message| !message.is_empty())
+            .or(Some(self.name.as_str()).filter(|name| !name.is_empty()))
+            .unwrap_or("opencode reported an error")
+            .to_string()
+    }
+}
+
+impl OpencodeEvent {
+    /// The event's `type`, for logging
+    pub fn event_type(&self) -> &str {
+        match &self.kind {
+            EventKind::StepStart { .. } => "step_start",
+            EventKind::StepFinish { .. } => "step_finish",
+            EventKind::Text { .. } => "text",
+            EventKind::Reasoning { .. } => "reasoning",
+            EventKind::ToolUse { .. } => "tool_use",
+            EventKind::ToolStart { .. } => "tool_start",
+            EventKind::ToolFinish { .. } => "tool_finish",
+            EventKind::Retry { .. } => "retry",
+            EventKind::Error { .. } => "error",
+            EventKind::Raw(value) => value["type"].as_str().unwrap_or("unknown"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OpencodeEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if !value.is_object() {
+            return Err(D::Error::custom("expected an event object"));
+        }
+        let session_id = value["sessionID"].as_str().map(str::to_string);
+        let timestamp = value["timestamp"].as_u64();
+        let kind = match EventKind::deserialize(&value) {
+            Ok(kind) => kind,
+            Err(_) => EventKind::Raw(value),
+        };
+        Ok(OpencodeEvent {
+            session_id,
+            timestamp,
+            kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fixture(jsonl: &str) -> Vec<OpencodeEvent> {
+        jsonl
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_tool_run() {
+        let events = parse_fixture(include_str!("../tests/fixtures/tool_run.jsonl"));
+        let types: Vec<_> = events.iter().map(OpencodeEvent::event_type).collect();
+        assert_eq!(
+            types,
+            vec!["step_start", "tool_use", "step_finish", "step_start", "text", "step_finish"]
+        );
+        assert!(events
+            .iter()
+            .all(|e| e.session_id.as_deref() == Some("ses_4c2e8f1a9ffe3JmVbHk2xQrT5d")));
+
+        let EventKind::ToolUse { part } = &events[1].kind else {
+            panic!("expected a tool event");
+        };
+        assert_eq!(part.tool, "bash");
+        assert_eq!(part.state.input().unwrap()["command"], "latchkey services list");
+        assert_eq!(part.state.duration(), Some(412));
+        assert!(matches!(&part.state, ToolState::Completed { output, .. } if output.contains("slack")));
+
+        let EventKind::StepFinish { part } = &events[5].kind else {
+            panic!("expected a step_finish event");
+        };
+        assert_eq!(part.reason.as_deref(), Some("stop"));
+        assert_eq!(part.cost, Some(0.0061215));
+        assert_eq!(
+            part.tokens,
+            Some(TokenUsage {
+                input: 1204,
+                output: 87,
+                reasoning: 0,
+                cache: CacheTokens {
+                    read: 11520,
+                    write: 310
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_reasoning_retry_and_error() {
+        let events = parse_fixture(include_str!("../tests/fixtures/reasoning_error.jsonl"));
+
+        let EventKind::Reasoning { part } = &events[1].kind else {
+            panic!("expected a reasoning event");
+        };
+        assert!(part.text.starts_with("The user wants"));
+        assert!(part.is_finished());
+
+        assert_eq!(
+            events[2].kind,
+            EventKind::Retry {
+                attempt: 1,
+                message: "Overloaded".to_string(),
+                next: Some(1760693400000),
+            }
+        );
+
+        let EventKind::ToolUse { part } = &events[3].kind else {
+            panic!("expected a tool event");
+        };
+        assert!(matches!(&part.state, ToolState::Error { error, .. } if error.starts_with("File not found")));
+
+        let EventKind::Error { error } = &events[4].kind else {
+            panic!("expected an error event");
+        };
+        assert_eq!(error.name, "APIError");
+        assert_eq!(error.message(), "Rate limit exceeded, please slow down");
+    }
+
+    #[test]
+    fn test_unknown_events_are_kept_raw() {
+        let events = parse_fixture(include_str!("../tests/fixtures/unknown_events.jsonl"));
+        assert_eq!(events.len(), 3);
+
+        // A type this version doesn't know
+        assert_eq!(events[0].event_type(), "snapshot");
+        assert!(matches!(&events[0].kind, EventKind::Raw(value) if value["part"]["hash"] == "9f3a1c"));
+        assert_eq!(events[0].timestamp, Some(1760693345120));
+
+        // A known type with an unexpected shape
+        assert_eq!(events[1].event_type(), "tool_use");
+        assert!(matches!(&events[1].kind, EventKind::Raw(value) if value["part"]["state"]["status"] == "queued"));
+
+        assert!(matches!(events[2].kind, EventKind::Text { .. }));
+    }
+
+    #[test]
+    fn test_non_object_lines_are_rejected() {
+        assert!(serde_json::from_str::<OpencodeEvent>("[1, 2]").is_err());
+        assert!(serde_json::from_str::<OpencodeEvent>("\"text\"").is_err());
+    }
+
+    #[test]
+    fn test_error_message_falls_back_to_name() {
+        let error: EventError = serde_json::from_value(serde_json::json!({"name": "UnknownError"})).unwrap();
+        assert_eq!(error.message(), "UnknownError");
+        let error: EventError = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(error.message(), "opencode reported an error");
+    }
+}
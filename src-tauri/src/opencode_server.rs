@@ -0,0 +1,436 @@
+use crate::attachments::Attachment;
+use crate::process_utils::{isolate_process_group, kill_process_tree};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// How long the server may take to answer its first health check
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause between health checks while the server starts
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Timeout for plain API requests; the event stream has none
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A long-lived `opencode serve` process shared by all sessions.
+/// Each session's workspace is selected per request with the `directory` parameter.
+pub struct OpencodeServer {
+    binary: PathBuf,
+    /// Working directory of the server process
+    root: PathBuf,
+    client: reqwest::Client,
+    process: Mutex<Option<ServerProcess>>,
+}
+
+struct ServerProcess {
+    child: Child,
+    pid: u32,
+    base_url: String,
+    /// Hash of the environment the server was started with, to notice changed API keys
+    env_fingerprint: u64,
+}
+
+/// An event from the server's `/event` stream
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub properties: Value,
+}
+
+impl OpencodeServer {
+    pub fn new(binary: &Path, root: &Path) -> Self {
+        Self {
+            binary: binary.to_path_buf(),
+            root: root.to_path_buf(),
+            client: reqwest::Client::new(),
+            process: Mutex::new(None),
+        }
+    }
+
+    /// Start the server if it isn't running, or restart it if it has crashed.
+    /// A server started with a different environment (e.g. changed API keys) is only
+    /// restarted when `can_restart` is set, since that interrupts runs in other sessions.
+    /// Returns the server's base URL.
+    pub async fn ensure_running(
+        &self,
+        env: &[(String, String)],
+        can_restart: bool,
+    ) -> Result<String, String> {
+        let env_fingerprint = fingerprint(env);
+        let mut process = self.process.lock().await;
+
+        if let Some(running) = process.as_mut() {
+            match running.child.try_wait() {
+                Ok(None) if running.env_fingerprint == env_fingerprint || !can_restart => {
+                    return Ok(running.base_url.clone());
+                }
+                Ok(None) => {
+                    eprintln!("[opencode-server] Environment changed, restarting server");
+                    kill_process_tree(running.pid)?;
+                    let _ = running.child.wait().await;
+                }
+                Ok(Some(status)) => {
+                    eprintln!("[opencode-server] Server exited with {}, restarting", status);
+                }
+                Err(e) => {
+                    eprintln!("[opencode-server] Failed to check server status, restarting: {}", e);
+                    let _ = kill_process_tree(running.pid);
+                }
+            }
+            *process = None;
+        }
+
+        let started = self.start(env, env_fingerprint).await?;
+        let base_url = started.base_url.clone();
+        *process = Some(started);
+        Ok(base_url)
+    }
+
+    async fn start(&self, env: &[(String, String)], env_fingerprint: u64) -> Result<ServerProcess, String> {
+        let port = free_port()?;
+        let base_url = format!("http://127.0.0.1:{}", port);
+
+        let mut cmd = std::process::Command::new(&self.binary);
+        cmd.arg("serve")
+            .arg("--hostname")
+            .arg("127.0.0.1")
+            .arg("--port")
+            .arg(port.to_string())
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .current_dir(&self.root)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        isolate_process_group(&mut cmd);
+        let mut cmd = Command::from(cmd);
+        cmd.kill_on_drop(true);
+
+        eprintln!("[opencode-server] Starting {:?} serve on port {}", self.binary, port);
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to start opencode server: {}", e))?;
+        let pid = child
+            .id()
+            .ok_or_else(|| "Failed to get opencode server process ID".to_string())?;
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(log_lines(stdout, "stdout"));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(log_lines(stderr, "stderr"));
+        }
+
+        if let Err(e) = self.wait_until_healthy(&base_url, &mut child).await {
+            let _ = kill_process_tree(pid);
+            return Err(e);
+        }
+        eprintln!("[opencode-server] Server is ready at {}", base_url);
+
+        Ok(ServerProcess {
+            child,
+            pid,
+            base_url,
+            env_fingerprint,
+        })
+    }
+
+    async fn wait_until_healthy(&self, base_url: &str, child: &mut Child) -> Result<(), String> {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if let Ok(Some(status)) = child.try_wait() {
+                return Err(format!("opencode server exited during startup with {}", status));
+            }
+            if self.is_healthy(base_url).await {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "opencode server did not become healthy within {} seconds",
+                    STARTUP_TIMEOUT.as_secs()
+                ));
+            }
+            tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn is_healthy(&self, base_url: &str) -> bool {
+        self.client
+            .get(format!("{}/global/health", base_url))
+            .timeout(HEALTH_CHECK_INTERVAL * 5)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    }
+
+    /// Kill the server, e.g. when the app exits
+    pub fn shutdown(&self) {
+        if let Ok(mut process) = self.process.try_lock() {
+            if let Some(running) = process.take() {
+                eprintln!("[opencode-server] Stopping server (pid {})", running.pid);
+                let _ = kill_process_tree(running.pid);
+            }
+        }
+    }
+
+    /// Create an opencode session for a workspace. Returns its ID.
+    pub async fn create_session(&self, base_url: &str, directory: &Path) -> Result<String, String> {
+        let response = self
+            .client
+            .post(format!("{}/session", base_url))
+            .query(&[("directory", directory)])
+            .timeout(REQUEST_TIMEOUT)
+            .json(&json!({}))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to create opencode session: {}", e))?;
+        let session: Value = check_status(response, "create opencode session")
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse opencode session: {}", e))?;
+        session
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| "opencode returned a session without an ID".to_string())
+    }
+
+    /// Send a message to a session without waiting for the reply, which arrives as events
+    pub async fn prompt(
+        &self,
+        base_url: &str,
+        directory: &Path,
+        opencode_session_id: &str,
+        prompt: &Prompt<'_>,
+    ) -> Result<(), String> {
+        let mut parts = vec![json!({ "type": "text", "text": prompt.message })];
+        for attachment in prompt.attachments {
+            let path = directory.join(&attachment.path);
+            let url = Url::from_file_path(&path).map_err(|_| format!("Cannot attach {:?}", path))?;
+            parts.push(json!({
+                "type": "file",
+                "mime": attachment.mime_type(),
+                "filename": attachment.name(),
+                "url": url.to_string(),
+            }));
+        }
+        let response = self
+            .client
+            .post(format!("{}/session/{}/prompt_async", base_url, opencode_session_id))
+            .query(&[("directory", directory)])
+            .timeout(REQUEST_TIMEOUT)
+            .json(&json!({
+                "model": { "providerID": prompt.provider_id, "modelID": prompt.model_id },
+                "parts": parts,
+            }))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send message to opencode: {}", e))?;
+        check_status(response, "send message").await.map(|_| ())
+    }
+
+    /// Stop whatever the session is doing
+    pub async fn abort(&self, base_url: &str, directory: &Path, opencode_session_id: &str) -> Result<(), String> {
+        let response = self
+            .client
+            .post(format!("{}/session/{}/abort", base_url, opencode_session_id))
+            .query(&[("directory", directory)])
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to abort opencode session: {}", e))?;
+        check_status(response, "abort session").await.map(|_| ())
+    }
+
+    /// Answer a permission request with "once", "always" or "reject"
+    pub async fn respond_permission(
+        &self,
+        base_url: &str,
+        directory: &Path,
+        opencode_session_id: &str,
+        permission_id: &str,
+        response: &str,
+    ) -> Result<(), String> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/session/{}/permissions/{}",
+                base_url, opencode_session_id, permission_id
+            ))
+            .query(&[("directory", directory)])
+            .timeout(REQUEST_TIMEOUT)
+            .json(&json!({ "response": response }))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to answer opencode permission request: {}", e))?;
+        check_status(response, "answer permission request").await.map(|_| ())
+    }
+
+    /// Subscribe to the events of a workspace
+    pub async fn events(&self, base_url: &str, directory: &Path) -> Result<EventStream, String> {
+        let response = self
+            .client
+            .get(format!("{}/event", base_url))
+            .query(&[("directory", directory)])
+            .header("Accept", "text/event-stream")
+            .send()
+            .await
+            .map_err(|e| format!("Failed to subscribe to opencode events: {}", e))?;
+        Ok(EventStream {
+            response: check_status(response, "subscribe to events").await?,
+            parser: SseParser::default(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// A message for an opencode session
+pub struct Prompt<'a> {
+    pub provider_id: &'a str,
+    pub model_id: &'a str,
+    pub message: &'a str,
+    /// Files in the workspace passed along with the message
+    pub attachments: &'a [Attachment],
+}
+
+/// Server-sent events from `/event`, parsed as they arrive
+pub struct EventStream {
+    response: reqwest::Response,
+    parser: SseParser,
+    pending: VecDeque<ServerEvent>,
+}
+
+impl EventStream {
+    /// The next event, or None once the server closes the stream.
+    /// Dropping the returned future before it completes loses no events.
+    pub async fn next(&mut self) -> Result<Option<ServerEvent>, String> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            let Some(chunk) = self
+                .response
+                .chunk()
+                .await
+                .map_err(|e| format!("Lost connection to the opencode server: {}", e))?
+            else {
+                return Ok(None);
+            };
+            for data in self.parser.feed(&chunk) {
+                match serde_json::from_str(&data) {
+                    Ok(event) => self.pending.push_back(event),
+                    Err(e) => eprintln!("[opencode-server] Failed to parse event: {}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Splits a server-sent event stream into the data of each event
+#[derive(Debug, Default)]
+struct SseParser {
+    /// Bytes of a line that hasn't ended yet, which may end inside a character
+    buffer: Vec<u8>,
+    /// Data lines of the event being read
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Add received bytes and return the data of every event they complete.
+    /// Only whole lines are decoded, so characters split between chunks stay intact.
+    fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+        }
+        events
+    }
+}
+
+async fn check_status(response: reqwest::Response, action: &str) -> Result<reqwest::Response, String> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("Failed to {}: opencode responded with {}: {}", action, status, body))
+}
+
+async fn log_lines(output: impl AsyncRead + Unpin, stream: &'static str) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        eprintln!("[opencode-server {}] {}", stream, line);
+    }
+}
+
+/// A port that was free a moment ago
+fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("Failed to find a free port: {}", e))
+}
+
+fn fingerprint(env: &[(String, String)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"data: {\"type\":\"server.connected\"").is_empty());
+        assert_eq!(
+            parser.feed(b"}\n\ndata: {\"type\":\"a\"}\r\n\r\n: comment\n\n"),
+            vec![r#"{"type":"server.connected"}"#, r#"{"type":"a"}"#]
+        );
+        assert_eq!(parser.feed(b"event: x\ndata: line1\ndata:line2\n\n"), vec!["line1\nline2"]);
+    }
+
+    #[test]
+    fn test_sse_parser_keeps_characters_split_between_chunks() {
+        let mut parser = SseParser::default();
+        let event = "data: {\"text\":\"Grüße → 日本\"}\r\n\r\n".as_bytes();
+        // Inside "ü", then inside "→", then between "\r" and "\n"
+        let cuts = [18, 24, event.len() - 3];
+        assert!(std::str::from_utf8(&event[..cuts[0]]).is_err());
+        assert!(parser.feed(&event[..cuts[0]]).is_empty());
+        assert!(parser.feed(&event[cuts[0]..cuts[1]]).is_empty());
+        assert!(parser.feed(&event[cuts[1]..cuts[2]]).is_empty());
+        assert_eq!(parser.feed(&event[cuts[2]..]), vec![r#"{"text":"Grüße → 日本"}"#]);
+    }
+
+    #[test]
+    fn test_fingerprint_changes_with_env() {
+        let env = vec![("ANTHROPIC_API_KEY".to_string(), "sk-1".to_string())];
+        let changed = vec![("ANTHROPIC_API_KEY".to_string(), "sk-2".to_string())];
+        assert_eq!(fingerprint(&env), fingerprint(&env.clone()));
+        assert_ne!(fingerprint(&env), fingerprint(&changed));
+    }
+}
@@ -1,11 +1,13 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::AppHandle;
 
 /// Common paths used by the application
 pub struct AppPaths {
     pub native_tools_path: PathBuf,
     pub opencode_workspace_path: PathBuf,
+    /// Per-user directory for persistent app data such as conversations
+    pub app_data_path: PathBuf,
 }
 
 impl AppPaths {
@@ -15,26 +17,26 @@ impl AppPaths {
         use tauri::Manager;
 
         let resource_path = app.path().resource_dir().map_err(|e| e.to_string())?;
-        let native_tools_path = resource_path.join("native_tools");
-        let opencode_workspace_path = resource_path.join("opencode_workspace");
+        let app_data_path = app.path().app_data_dir().map_err(|e| e.to_string())?;
+        let resources = Self::resources(&resource_path);
+        Ok(Self {
+            native_tools_path: resources.join("native_tools"),
+            opencode_workspace_path: resources.join("opencode_workspace"),
+            app_data_path,
+        })
+    }
 
-        let (native_tools_path, opencode_workspace_path) = if native_tools_path.exists() {
-            (native_tools_path, opencode_workspace_path)
+    /// The directory holding native_tools and opencode_workspace: the bundled resources
+    /// in production, the project directory in development
+    pub fn resources(resource_path: &Path) -> PathBuf {
+        if resource_path.join("native_tools").exists() {
+            resource_path.to_path_buf()
         } else {
-            let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
                 .parent()
                 .unwrap()
-                .to_path_buf();
-            (
-                project_root.join("native_tools"),
-                project_root.join("opencode_workspace"),
-            )
-        };
-
-        Ok(Self {
-            native_tools_path,
-            opencode_workspace_path,
-        })
+                .to_path_buf()
+        }
     }
 
     /// Get PATH environment variable with native_tools prepended
@@ -46,6 +48,11 @@ impl AppPaths {
         path_env
     }
 
+    /// Get PATH environment variable with `dir` first, in place of native_tools
+    pub fn get_path_env_with(&self, dir: &Path) -> String {
+        format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default())
+    }
+
     /// Get the path to a binary in native_tools, falling back to system PATH
     pub fn get_binary_path(&self, name: &str) -> PathBuf {
         let binary_path = self.native_tools_path.join(name);
@@ -0,0 +1,329 @@
+use crate::config::{get_config_path, ConfigFile};
+use crate::workspace_config::update_opencode_config;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Options before the URL that leave a `latchkey curl` request a plain GET
+const READ_ONLY_CURL_PREFIXES: [&str; 5] = ["", "-s ", "-sS ", "-i ", "-s -w '%{http_code}' "];
+
+/// Quotes a URL may be written in
+const URL_QUOTES: [&str; 3] = ["", "'", "\""];
+
+/// Shell characters that could run another command after one that looks harmless
+const SHELL_METACHARACTERS: [&str; 7] = [";", "|", "&", "`", "$", "<", ">"];
+
+/// The default `bash_rules`: ask about every command that mentions latchkey, including
+/// wrappers such as `bash -c '...'`, except the forms that can only read. A `latchkey curl`
+/// is only allowed as one of the prefixes followed by an `https://` URL and nothing else.
+fn default_bash_rules() -> Vec<CommandRule> {
+    let rule = |pattern: String, decision| CommandRule { pattern, decision };
+    let mut rules = vec![
+        rule("*latchkey*".to_string(), PermissionDecision::Ask),
+        rule("latchkey services list".to_string(), PermissionDecision::Allow),
+        rule("latchkey status *".to_string(), PermissionDecision::Allow),
+    ];
+    for prefix in READ_ONLY_CURL_PREFIXES {
+        for quote in URL_QUOTES {
+            rules.push(rule(
+                format!("latchkey curl {}{}https://*", prefix, quote),
+                PermissionDecision::Allow,
+            ));
+        }
+    }
+    // Anything after the URL could be another option, e.g. `-X DELETE` or `-K config`
+    for space in [" ", "\t", "\n"] {
+        rules.push(rule(format!("latchkey curl *https://*{}*", space), PermissionDecision::Ask));
+    }
+    for character in SHELL_METACHARACTERS {
+        rules.push(rule(format!("*latchkey*{}*", character), PermissionDecision::Ask));
+    }
+    rules
+}
+
+/// What opencode may do without asking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionDecision {
+    Allow,
+    /// Ask the user in the app, which only the opencode server supports.
+    /// One-shot `opencode run` processes can't ask and deny instead.
+    Ask,
+    Deny,
+}
+
+/// A decision for bash commands matching a pattern, where `*` matches any text,
+/// e.g. `latchkey curl -X DELETE *`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRule {
+    pub pattern: String,
+    pub decision: PermissionDecision,
+}
+
+/// Which tools opencode may use, stored as `permissions` in `~/.passepartout.json`
+/// and written to the `permission` section of each workspace's opencode.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionPolicy {
+    /// Creating and changing files in the workspace
+    pub edit: PermissionDecision,
+    /// Fetching web pages
+    pub webfetch: PermissionDecision,
+    /// Bash commands that match none of `bash_rules`
+    pub bash: PermissionDecision,
+    /// Later rules take precedence over earlier ones
+    pub bash_rules: Vec<CommandRule>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            edit: PermissionDecision::Allow,
+            webfetch: PermissionDecision::Allow,
+            bash: PermissionDecision::Allow,
+            bash_rules: default_bash_rules(),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    pub fn load() -> Result<Self, String> {
+        Ok(ConfigFile::load(&get_config_path()?)?.permissions)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == PermissionPolicy::default()
+    }
+
+    pub fn save(self) -> Result<(), String> {
+        if let Some(rule) = self.bash_rules.iter().find(|r| r.pattern.trim().is_empty()) {
+            return Err(format!("Command pattern must not be empty ({:?})", rule.decision));
+        }
+        ConfigFile::update(&get_config_path()?, |config| {
+            config.permissions = self;
+            Ok(())
+        })
+    }
+
+    /// The `permission` section of opencode.json. Without `ask_supported`,
+    /// everything that would ask is denied.
+    pub fn opencode_config(&self, ask_supported: bool) -> Value {
+        let decision = |decision: PermissionDecision| {
+            let decision = match decision {
+                PermissionDecision::Ask if !ask_supported => PermissionDecision::Deny,
+                decision => decision,
+            };
+            serde_json::to_value(decision).unwrap_or_default()
+        };
+
+        let bash = if self.bash_rules.is_empty() {
+            decision(self.bash)
+        } else {
+            // opencode applies the last matching pattern
+            let mut patterns = Map::new();
+            patterns.insert("*".to_string(), decision(self.bash));
+            for rule in &self.bash_rules {
+                patterns.remove(&rule.pattern);
+                patterns.insert(rule.pattern.clone(), decision(rule.decision));
+            }
+            Value::Object(patterns)
+        };
+
+        serde_json::json!({
+            "edit": decision(self.edit),
+            "webfetch": decision(self.webfetch),
+            "bash": bash,
+        })
+    }
+
+    /// Replace the `permission` section of the opencode.json in a workspace
+    pub fn write_opencode_config(&self, workspace: &Path, ask_supported: bool) -> Result<(), String> {
+        update_opencode_config(workspace, "permission", Some(self.opencode_config(ask_supported)))
+    }
+}
+
+/// A tool call opencode wants the user to approve, emitted as `chat:permissionRequest`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PermissionRequest {
+    /// The session the request belongs to, filled in when it is forwarded
+    #[serde(rename = "sessionId", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// opencode's ID for the request, passed back to approve or deny it
+    pub id: String,
+    /// The kind of permission, e.g. "bash" or "edit"
+    pub permission: String,
+    /// What the tool wants to do, e.g. the command line
+    pub title: String,
+    /// The patterns an "always" approval would allow from now on
+    pub patterns: Vec<String>,
+}
+
+impl PermissionRequest {
+    /// Read a request from the properties of a `permission.updated` event,
+    /// or of `permission.asked` as newer opencode versions call it
+    pub fn from_event(properties: &Value) -> Option<Self> {
+        let id = properties["id"].as_str()?.to_string();
+        let permission = properties["type"]
+            .as_str()
+            .or(properties["permission"].as_str())
+            .unwrap_or("tool")
+            .to_string();
+        let patterns: Vec<String> = match properties.get("pattern").or(properties.get("patterns")) {
+            Some(Value::String(pattern)) => vec![pattern.clone()],
+            Some(Value::Array(patterns)) => patterns
+                .iter()
+                .filter_map(|p| p.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let title = properties["title"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| patterns.first().cloned())
+            .unwrap_or_else(|| permission.clone());
+        Some(Self {
+            session_id: None,
+            id,
+            permission,
+            title,
+            patterns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// opencode's pattern matching, where `*` matches any text, including none
+    fn wildcard_match(pattern: &str, text: &str) -> bool {
+        match pattern.split_once('*') {
+            None => pattern == text,
+            Some((prefix, rest)) => {
+                let Some(text) = text.strip_prefix(prefix) else {
+                    return false;
+                };
+                (0..=text.len())
+                    .filter(|i| text.is_char_boundary(*i))
+                    .any(|i| wildcard_match(rest, &text[i..]))
+            }
+        }
+    }
+
+    /// The decision opencode makes for a command: the last matching pattern wins
+    fn bash_decision(config: &Value, command: &str) -> String {
+        let patterns = config["bash"].as_object().unwrap();
+        let (_, decision) = patterns
+            .iter()
+            .rev()
+            .find(|(pattern, _)| wildcard_match(pattern, command))
+            .unwrap();
+        decision.as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_default_policy_asks_before_changing_services() {
+        let config = PermissionPolicy::default().opencode_config(true);
+        assert_eq!(config["edit"], "allow");
+        assert_eq!(config["webfetch"], "allow");
+        // The catch-all pattern comes first, so the specific ones override it
+        let patterns: Vec<_> = config["bash"].as_object().unwrap().keys().collect();
+        assert_eq!(patterns[0], "*");
+
+        for command in [
+            "latchkey curl -X DELETE https://api.github.com/repos/o/r",
+            "latchkey curl -s -X DELETE https://api.github.com/repos/o/r",
+            "latchkey curl -XDELETE https://api.github.com/repos/o/r",
+            "latchkey curl -sXPATCH https://api.linear.app/graphql",
+            "latchkey curl --request=PUT https://api.github.com/repos/o/r/topics",
+            "latchkey curl https://api.github.com/repos/o/r -X DELETE",
+            "latchkey curl -X POST https://slack.com/api/chat.postMessage",
+            "latchkey curl -H 'Content-Type: application/json' -d '{\"channel\":\"C1\"}' https://slack.com/api/chat.postMessage",
+            "latchkey curl https://api.linear.app/graphql --data @query.json",
+            "latchkey curl -T notes.txt https://example.com/notes.txt",
+            // Methods in lowercase, which curl sends as they are
+            "latchkey curl -X delete https://api.github.com/repos/o/r",
+            "latchkey curl --request patch https://api.linear.app/graphql",
+            // A config file can set the method and body
+            "latchkey curl -K request.txt https://api.github.com/repos/o/r",
+            "latchkey curl https://api.github.com/repos/o/r -K request.txt",
+            "latchkey curl https://api.github.com/user --next -X DELETE https://api.github.com/repos/o/r",
+            // Wrappers and chained commands
+            "bash -c 'latchkey curl -X DELETE https://api.github.com/repos/o/r'",
+            "sh -c \"latchkey curl -XPOST https://slack.com/api/chat.postMessage\"",
+            "env latchkey curl -X DELETE https://api.github.com/repos/o/r",
+            "latchkey curl https://api.github.com/user;latchkey${IFS}curl${IFS}-XDELETE${IFS}https://x",
+            "latchkey curl https://api.github.com/user|sh",
+            "latchkey curl \"https://api.github.com/$(cat url)\"",
+            "latchkey curl https://api.github.com/user\t-XDELETE",
+            "latchkey clear slack",
+        ] {
+            assert_eq!(bash_decision(&config, command), "ask", "{}", command);
+        }
+        for command in [
+            "latchkey curl https://api.github.com/user",
+            "latchkey curl -s 'https://slack.com/api/conversations.list'",
+            "latchkey curl -sS \"https://api.linear.app/issues\"",
+            "latchkey curl -s -w '%{http_code}' https://api.github.com/user",
+            "latchkey services list",
+            "latchkey status slack",
+            "ls -la",
+        ] {
+            assert_eq!(bash_decision(&config, command), "allow", "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_ask_becomes_deny_without_approvals() {
+        let policy = PermissionPolicy {
+            edit: PermissionDecision::Ask,
+            webfetch: PermissionDecision::Deny,
+            bash: PermissionDecision::Ask,
+            bash_rules: vec![],
+        };
+        assert_eq!(
+            policy.opencode_config(false),
+            serde_json::json!({"edit": "deny", "webfetch": "deny", "bash": "deny"})
+        );
+    }
+
+    #[test]
+    fn test_policy_from_config_file() {
+        let config: ConfigFile = serde_json::from_str(
+            r#"{"permissions": {"bash": "ask", "bash_rules": [{"pattern": "latchkey services list", "decision": "allow"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.permissions.edit, PermissionDecision::Allow);
+        assert_eq!(config.permissions.bash, PermissionDecision::Ask);
+        assert_eq!(config.permissions.bash_rules.len(), 1);
+    }
+
+    #[test]
+    fn test_permission_request_from_event() {
+        let request = PermissionRequest::from_event(&serde_json::json!({
+            "id": "per_1",
+            "type": "bash",
+            "pattern": ["latchkey curl -X DELETE *"],
+            "sessionID": "ses_1",
+            "messageID": "msg_1",
+            "title": "latchkey curl -X DELETE https://slack.com/api/chat.delete",
+            "metadata": {},
+            "time": {"created": 1}
+        }))
+        .unwrap();
+        assert_eq!(request.id, "per_1");
+        assert_eq!(request.permission, "bash");
+        assert_eq!(request.patterns, vec!["latchkey curl -X DELETE *"]);
+        assert!(request.title.starts_with("latchkey curl -X DELETE"));
+
+        let request = PermissionRequest::from_event(&serde_json::json!({
+            "id": "per_2",
+            "sessionID": "ses_1",
+            "permission": "edit",
+            "patterns": ["notes.md"],
+        }))
+        .unwrap();
+        assert_eq!(request.permission, "edit");
+        assert_eq!(request.title, "notes.md");
+    }
+}
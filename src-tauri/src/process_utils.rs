@@ -0,0 +1,44 @@
+use std::process::Command;
+
+/// Put the spawned process into its own process group so that the whole tree
+/// (opencode plus any tools it runs) can be signalled at once.
+pub fn isolate_process_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+    }
+}
+
+/// Kill a process and all of its descendants.
+/// The process must have been spawned with `isolate_process_group`.
+pub fn kill_process_tree(pid: u32) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        // A negative PID addresses the whole process group
+        let result = unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+        if result != 0 {
+            let err = std::io::Error::last_os_error();
+            // The group may already be gone if the run finished on its own
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(format!("Failed to kill process group {}: {}", pid, err));
+            }
+        }
+        Ok(())
+    }
+    #[cfg(windows)]
+    {
+        let status = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .status()
+            .map_err(|e| format!("Failed to run taskkill: {}", e))?;
+        if !status.success() {
+            return Err(format!("taskkill exited with status: {}", status));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use crate::config::{get_config_path, ConfigFile};
+use crate::workspace_config::update_opencode_config;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// Providers shipped with the app. Users can override or extend them with
+/// a `providers` list in the config file.
+const BUNDLED_PROVIDERS: &str = include_str!("../providers.json");
+
+/// AI SDK package opencode uses to talk to OpenAI-compatible servers
+const OPENAI_COMPATIBLE_PACKAGE: &str = "@ai-sdk/openai-compatible";
+
+/// An LLM provider that API keys can be stored for
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProviderInfo {
+    /// Provider ID as understood by opencode, e.g. "anthropic"
+    pub id: String,
+    pub display_name: String,
+    /// Environment variables the API key is passed to opencode in.
+    /// Empty for local servers that don't need a key.
+    #[serde(default)]
+    pub env_vars: Vec<String>,
+    /// What a key looks like, shown as a placeholder in the UI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_hint: Option<String>,
+    /// API endpoint, when it differs from the provider's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// The provider's own API endpoint, used to check API keys when `base_url` isn't set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_base_url: Option<String>,
+    /// How the API key is sent in requests to the provider
+    #[serde(default)]
+    pub auth: KeyAuth,
+    /// Speaks the OpenAI chat completions API, like Ollama, LM Studio or vLLM.
+    /// Such providers are unknown to opencode and need `base_url` and `models`.
+    #[serde(default)]
+    pub openai_compatible: bool,
+    /// Models offered by a custom provider
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<ProviderModel>,
+}
+
+/// How a provider expects the API key to be sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAuth {
+    /// `Authorization: Bearer <key>`, as used by OpenAI and compatible servers
+    #[default]
+    Bearer,
+    /// `x-api-key: <key>` with an `anthropic-version` header
+    Anthropic,
+    /// `x-goog-api-key: <key>`
+    Google,
+}
+
+/// A model served by a custom provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderModel {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+impl ProviderInfo {
+    /// The endpoint requests to this provider go to, without a trailing slash
+    pub fn api_base_url(&self) -> Option<&str> {
+        self.base_url
+            .as_deref()
+            .or(self.default_base_url.as_deref())
+            .map(|url| url.trim_end_matches('/'))
+    }
+
+    /// Entry for the `provider` section of opencode.json, if opencode needs one
+    fn opencode_config(&self) -> Option<Value> {
+        if !self.openai_compatible && self.base_url.is_none() && self.models.is_empty() {
+            return None;
+        }
+
+        let mut options = Map::new();
+        if let Some(base_url) = &self.base_url {
+            options.insert("baseURL".to_string(), json!(base_url));
+        }
+        // Point opencode at the env var rather than writing the key into the workspace
+        if let Some(env_var) = self.env_vars.first() {
+            options.insert("apiKey".to_string(), json!(format!("{{env:{}}}", env_var)));
+        }
+
+        let mut entry = Map::new();
+        if self.openai_compatible {
+            entry.insert("npm".to_string(), json!(OPENAI_COMPATIBLE_PACKAGE));
+            entry.insert("name".to_string(), json!(self.display_name));
+        }
+        entry.insert("options".to_string(), Value::Object(options));
+        if !self.models.is_empty() {
+            let models: Map<String, Value> = self
+                .models
+                .iter()
+                .map(|model| {
+                    let name = model.display_name.as_deref().unwrap_or(&model.id);
+                    (model.id.clone(), json!({ "name": name }))
+                })
+                .collect();
+            entry.insert("models".to_string(), Value::Object(models));
+        }
+        Some(Value::Object(entry))
+    }
+}
+
+/// The known providers, in display order
+#[derive(Debug, Clone)]
+pub struct ProviderRegistry {
+    providers: Vec<ProviderInfo>,
+}
+
+impl ProviderRegistry {
+    /// The bundled providers with the user's overrides from the config file applied
+    pub fn load() -> Result<Self, String> {
+        let config = ConfigFile::load(&get_config_path()?)?;
+        Ok(Self::bundled()?.with_overrides(config.providers))
+    }
+
+    /// The providers shipped with the app
+    pub fn bundled() -> Result<Self, String> {
+        let providers = serde_json::from_str(BUNDLED_PROVIDERS)
+            .map_err(|e| format!("Failed to parse bundled providers: {}", e))?;
+        Ok(Self { providers })
+    }
+
+    /// Replace providers that have the same ID as an override and append the rest
+    pub fn with_overrides(mut self, overrides: Vec<ProviderInfo>) -> Self {
+        for provider in overrides {
+            match self.providers.iter_mut().find(|p| p.id == provider.id) {
+                Some(existing) => *existing = provider,
+                None => self.providers.push(provider),
+            }
+        }
+        self
+    }
+
+    pub fn all(&self) -> &[ProviderInfo] {
+        &self.providers
+    }
+
+    pub fn get(&self, id: &str) -> Result<&ProviderInfo, String> {
+        self.providers
+            .iter()
+            .find(|p| p.id == id)
+            .ok_or_else(|| format!("Unknown provider: {}", id))
+    }
+
+    /// The `provider` section of opencode.json for custom providers and base URL overrides
+    pub fn opencode_provider_section(&self) -> Map<String, Value> {
+        self.providers
+            .iter()
+            .filter_map(|p| Some((p.id.clone(), p.opencode_config()?)))
+            .collect()
+    }
+
+    /// Replace the `provider` section of the opencode.json in a workspace
+    pub fn write_opencode_config(&self, workspace: &Path) -> Result<(), String> {
+        let section = self.opencode_provider_section();
+        let value = if section.is_empty() {
+            None
+        } else {
+            Some(Value::Object(section))
+        };
+        update_opencode_config(workspace, "provider", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn provider(id: &str, env_var: &str) -> ProviderInfo {
+        ProviderInfo {
+            id: id.to_string(),
+            display_name: id.to_string(),
+            env_vars: vec![env_var.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn ollama() -> ProviderInfo {
+        ProviderInfo {
+            id: "ollama".to_string(),
+            display_name: "Ollama".to_string(),
+            base_url: Some("http://localhost:11434/v1".to_string()),
+            openai_compatible: true,
+            models: vec![ProviderModel {
+                id: "qwen2.5-coder:7b".to_string(),
+                display_name: Some("Qwen 2.5 Coder".to_string()),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bundled_providers() {
+        let registry = ProviderRegistry::bundled().unwrap();
+        let ids: Vec<&str> = registry.all().iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["anthropic", "openai", "google"]);
+        assert_eq!(registry.get("anthropic").unwrap().env_vars, vec!["ANTHROPIC_API_KEY"]);
+        assert_eq!(registry.get("openai").unwrap().display_name, "OpenAI");
+        assert!(registry.get("unknown").is_err());
+    }
+
+    #[test]
+    fn test_overrides_replace_and_extend() {
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![
+            provider("openai", "AZURE_OPENAI_API_KEY"),
+            provider("mistral", "MISTRAL_API_KEY"),
+        ]);
+        assert_eq!(registry.all().len(), 4);
+        assert_eq!(registry.all()[1].env_vars, vec!["AZURE_OPENAI_API_KEY"]);
+        assert_eq!(registry.all()[3].id, "mistral");
+    }
+
+    #[test]
+    fn test_optional_fields_default() {
+        let provider: ProviderInfo = serde_json::from_str(
+            r#"{"id": "groq", "display_name": "Groq", "env_vars": ["GROQ_API_KEY"]}"#,
+        )
+        .unwrap();
+        assert_eq!(provider.key_hint, None);
+        assert_eq!(provider.base_url, None);
+    }
+
+    #[test]
+    fn test_provider_section_only_lists_custom_providers() {
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![
+            ollama(),
+            ProviderInfo {
+                base_url: Some("https://proxy.example.com/v1".to_string()),
+                ..provider("anthropic", "ANTHROPIC_API_KEY")
+            },
+        ]);
+        let section = Value::Object(registry.opencode_provider_section());
+        assert_eq!(
+            section,
+            json!({
+                "anthropic": {
+                    "options": {
+                        "baseURL": "https://proxy.example.com/v1",
+                        "apiKey": "{env:ANTHROPIC_API_KEY}"
+                    }
+                },
+                "ollama": {
+                    "npm": "@ai-sdk/openai-compatible",
+                    "name": "Ollama",
+                    "options": { "baseURL": "http://localhost:11434/v1" },
+                    "models": { "qwen2.5-coder:7b": { "name": "Qwen 2.5 Coder" } }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_opencode_config_keeps_other_settings() {
+        let workspace = tempfile::tempdir().unwrap();
+        let path = workspace.path().join("opencode.json");
+        fs::write(&path, r#"{"permission": "allow"}"#).unwrap();
+
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![ollama()]);
+        registry.write_opencode_config(workspace.path()).unwrap();
+        let config: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config["permission"], "allow");
+        assert_eq!(config["provider"]["ollama"]["options"]["baseURL"], "http://localhost:11434/v1");
+
+        // Removing the custom provider removes its section again
+        ProviderRegistry::bundled()
+            .unwrap()
+            .write_opencode_config(workspace.path())
+            .unwrap();
+        let config: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(config.get("provider").is_none());
+    }
+
+    /// Serve canned OpenAI chat completions on a local port. Returns the base URL.
+    fn spawn_mock_openai_server(reply: &'static str) -> String {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; content_length];
+                let _ = reader.read_exact(&mut body);
+
+                let chunk = json!({
+                    "id": "chatcmpl-mock",
+                    "object": "chat.completion.chunk",
+                    "created": 0,
+                    "model": "mock-model",
+                    "choices": [{"index": 0, "delta": {"role": "assistant", "content": reply}, "finish_reason": "stop"}]
+                });
+                let events = format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    events.len(),
+                    events
+                );
+            }
+        });
+        base_url
+    }
+
+    /// End-to-end check that opencode reaches a custom provider through the generated config.
+    /// Run with `OPENCODE_BIN=/path/to/opencode cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs the opencode binary in OPENCODE_BIN"]
+    fn test_opencode_uses_custom_provider() {
+        let opencode = std::env::var("OPENCODE_BIN").expect("OPENCODE_BIN is not set");
+        let registry = ProviderRegistry::bundled().unwrap().with_overrides(vec![ProviderInfo {
+            id: "mock".to_string(),
+            display_name: "Mock".to_string(),
+            base_url: Some(spawn_mock_openai_server("Hello from the mock server")),
+            openai_compatible: true,
+            models: vec![ProviderModel {
+                id: "mock-model".to_string(),
+                display_name: None,
+            }],
+            ..Default::default()
+        }]);
+        let workspace = tempfile::tempdir().unwrap();
+        registry.write_opencode_config(workspace.path()).unwrap();
+
+        let output = std::process::Command::new(opencode)
+            .args(["run", "-m", "mock/mock-model", "--format", "json", "Say hello"])
+            .current_dir(workspace.path())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Hello from the mock server"), "{}", stdout);
+    }
+}
@@ -0,0 +1,524 @@
+use crate::config::ConfigFile;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Storage for API keys, addressed by a key such as the provider ID
+pub trait SecretStore {
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+    fn set(&self, key: &str, value: &str) -> Result<(), String>;
+    fn delete(&self, key: &str) -> Result<(), String>;
+    /// All keys that currently have a secret
+    fn keys(&self) -> Result<Vec<String>, String>;
+
+    /// The secrets of several keys, in order. Backends that are costly to open override
+    /// this to fetch them all at once.
+    fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, String> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+}
+
+/// Where API keys are stored, selected by `secret_backend` in the config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretBackend {
+    /// Plaintext in the config file
+    #[default]
+    JsonFile,
+    /// The freedesktop Secret Service (GNOME Keyring, KWallet) over D-Bus
+    SecretService,
+    /// Encrypted in the config file with a key derived from a passphrase
+    EncryptedFile,
+}
+
+/// Move every secret from one store to another. Returns the number of secrets moved.
+pub fn migrate_secrets(from: &dyn SecretStore, to: &dyn SecretStore) -> Result<usize, String> {
+    let keys = from.keys()?;
+    for key in &keys {
+        if let Some(value) = from.get(key)? {
+            to.set(key, &value)?;
+        }
+    }
+    // Only delete once everything has been copied, so a failure can't lose secrets
+    for key in &keys {
+        from.delete(key)?;
+    }
+    Ok(keys.len())
+}
+
+/// Plaintext API keys in the `api_keys` section of the config file
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl SecretStore for JsonFileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(ConfigFile::load(&self.path)?.api_keys.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        ConfigFile::update(&self.path, |config| {
+            config.api_keys.insert(key.to_string(), value.to_string());
+            Ok(())
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        ConfigFile::update(&self.path, |config| {
+            config.api_keys.remove(key);
+            Ok(())
+        })
+    }
+
+    fn keys(&self) -> Result<Vec<String>, String> {
+        Ok(ConfigFile::load(&self.path)?.api_keys.into_keys().collect())
+    }
+
+    fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, String> {
+        let api_keys = ConfigFile::load(&self.path)?.api_keys;
+        Ok(keys.iter().map(|key| api_keys.get(*key).cloned()).collect())
+    }
+}
+
+/// The sealed API keys with what is needed to open them again, binary fields base64-encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSecrets {
+    pub kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// API keys sealed with ChaCha20-Poly1305 in the `encrypted_api_keys` section of the config file,
+/// under a key derived from the user's passphrase
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    kdf: KdfParams,
+}
+
+impl EncryptedFileStore {
+    /// `key` must have been derived with `kdf`, which is stored alongside the ciphertext
+    pub fn new(path: &Path, key: [u8; 32], kdf: KdfParams) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            kdf,
+        }
+    }
+
+    /// Check that the key opens the stored secrets
+    pub fn verify(&self) -> Result<(), String> {
+        self.load_secrets().map(|_| ())
+    }
+
+    /// Seal the stored secrets again, which also records the key derivation parameters
+    pub fn reseal(&self) -> Result<(), String> {
+        self.save_secrets(&self.load_secrets()?)
+    }
+
+    fn load_secrets(&self) -> Result<BTreeMap<String, String>, String> {
+        self.open(ConfigFile::load(&self.path)?.encrypted_api_keys.as_ref())
+    }
+
+    fn save_secrets(&self, secrets: &BTreeMap<String, String>) -> Result<(), String> {
+        let sealed = self.seal(secrets)?;
+        ConfigFile::update(&self.path, |config| {
+            config.encrypted_api_keys = Some(sealed);
+            Ok(())
+        })
+    }
+
+    /// Change the stored secrets, with no other update of the config file in between
+    fn update_secrets(&self, change: impl FnOnce(&mut BTreeMap<String, String>)) -> Result<(), String> {
+        ConfigFile::update(&self.path, |config| {
+            let mut secrets = self.open(config.encrypted_api_keys.as_ref())?;
+            change(&mut secrets);
+            config.encrypted_api_keys = Some(self.seal(&secrets)?);
+            Ok(())
+        })
+    }
+
+    fn open(&self, sealed: Option<&EncryptedSecrets>) -> Result<BTreeMap<String, String>, String> {
+        let Some(sealed) = sealed else {
+            return Ok(BTreeMap::new());
+        };
+        let nonce = BASE64
+            .decode(&sealed.nonce)
+            .map_err(|e| format!("Failed to decode nonce: {}", e))?;
+        let ciphertext = BASE64
+            .decode(&sealed.ciphertext)
+            .map_err(|e| format!("Failed to decode encrypted API keys: {}", e))?;
+        if nonce.len() != 12 {
+            return Err("Encrypted API keys have an invalid nonce".to_string());
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Failed to decrypt API keys: wrong passphrase or corrupted file".to_string())?;
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| format!("Failed to parse decrypted API keys: {}", e))
+    }
+
+    fn seal(&self, secrets: &BTreeMap<String, String>) -> Result<EncryptedSecrets, String> {
+        let plaintext = serde_json::to_vec(secrets)
+            .map_err(|e| format!("Failed to serialize API keys: {}", e))?;
+        // A fresh nonce for every write; nonces must never be reused with the same key
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| "Failed to encrypt API keys".to_string())?;
+        Ok(EncryptedSecrets {
+            kdf: self.kdf.clone(),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.load_secrets()?.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        self.update_secrets(|secrets| {
+            secrets.insert(key.to_string(), value.to_string());
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.update_secrets(|secrets| {
+            secrets.remove(key);
+        })
+    }
+
+    fn keys(&self) -> Result<Vec<String>, String> {
+        Ok(self.load_secrets()?.into_keys().collect())
+    }
+
+    fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, String> {
+        let secrets = self.load_secrets()?;
+        Ok(keys.iter().map(|key| secrets.get(*key).cloned()).collect())
+    }
+}
+
+/// Argon2id parameters and salt used to derive the encryption key from a passphrase
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    /// Fresh parameters with a random salt and Argon2's recommended costs
+    pub fn generate() -> Self {
+        Self::generate_with_costs(
+            argon2::Params::DEFAULT_M_COST,
+            argon2::Params::DEFAULT_T_COST,
+            argon2::Params::DEFAULT_P_COST,
+        )
+    }
+
+    pub fn generate_with_costs(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt: BASE64.encode(salt),
+            memory_kib,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// Derive the 256-bit encryption key for a passphrase
+    pub fn derive_key(&self, passphrase: &str) -> Result<[u8; 32], String> {
+        let salt = BASE64
+            .decode(&self.salt)
+            .map_err(|e| format!("Failed to decode salt: {}", e))?;
+        let params = argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+        let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+        let mut key = [0u8; 32];
+        argon2
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        Ok(key)
+    }
+}
+
+/// Attribute identifying items created by Passepartout in the Secret Service
+const SECRET_SERVICE_APPLICATION: &str = "passepartout";
+
+/// API keys stored as items in the default Secret Service collection
+pub struct SecretServiceStore;
+
+#[cfg(target_os = "linux")]
+impl SecretServiceStore {
+    /// Run a Secret Service operation to completion.
+    /// The D-Bus client is async, so it gets its own runtime on a separate thread,
+    /// which keeps this usable from both sync code and async commands.
+    fn run<T, F, Fut>(operation: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(secret_service::SecretService<'static>) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = Result<T, secret_service::Error>>,
+    {
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| format!("Failed to start Secret Service runtime: {}", e))?;
+            runtime.block_on(async move {
+                let service =
+                    secret_service::SecretService::connect(secret_service::EncryptionType::Dh)
+                        .await
+                        .map_err(|e| format!("Failed to connect to the Secret Service: {}", e))?;
+                operation(service)
+                    .await
+                    .map_err(|e| format!("Secret Service request failed: {}", e))
+            })
+        })
+        .join()
+        .map_err(|_| "Secret Service thread panicked".to_string())?
+    }
+
+    fn attributes(key: &str) -> HashMap<&str, &str> {
+        HashMap::from([("application", SECRET_SERVICE_APPLICATION), ("key", key)])
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SecretStore for SecretServiceStore {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.get_many(&[key])?.pop().flatten())
+    }
+
+    /// One connection for all keys, since each connection is a thread, a runtime and a D-Bus session
+    fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, String> {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let secrets = Self::run(move |service| async move {
+            let collection = service.get_default_collection().await?;
+            collection.ensure_unlocked().await?;
+            let mut secrets = Vec::new();
+            for key in &keys {
+                let items = collection.search_items(Self::attributes(key)).await?;
+                secrets.push(match items.first() {
+                    Some(item) => Some(item.get_secret().await?),
+                    None => None,
+                });
+            }
+            Ok(secrets)
+        })?;
+        secrets
+            .into_iter()
+            .map(|secret| {
+                secret
+                    .map(|bytes| String::from_utf8(bytes).map_err(|e| format!("Secret is not valid UTF-8: {}", e)))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        let key = key.to_string();
+        let value = value.to_string();
+        Self::run(move |service| async move {
+            let collection = service.get_default_collection().await?;
+            collection.ensure_unlocked().await?;
+            let label = format!("Passepartout: {}", key);
+            collection
+                .create_item(&label, Self::attributes(&key), value.as_bytes(), true, "text/plain")
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let key = key.to_string();
+        Self::run(move |service| async move {
+            let collection = service.get_default_collection().await?;
+            collection.ensure_unlocked().await?;
+            for item in collection.search_items(Self::attributes(&key)).await? {
+                item.delete().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn keys(&self) -> Result<Vec<String>, String> {
+        Self::run(|service| async move {
+            let collection = service.get_default_collection().await?;
+            collection.ensure_unlocked().await?;
+            let items = collection
+                .search_items(HashMap::from([("application", SECRET_SERVICE_APPLICATION)]))
+                .await?;
+            let mut keys = Vec::new();
+            for item in items {
+                if let Some(key) = item.get_attributes().await?.remove("key") {
+                    keys.push(key);
+                }
+            }
+            Ok(keys)
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SecretStore for SecretServiceStore {
+    fn get(&self, _key: &str) -> Result<Option<String>, String> {
+        Err(Self::UNSUPPORTED.to_string())
+    }
+
+    fn set(&self, _key: &str, _value: &str) -> Result<(), String> {
+        Err(Self::UNSUPPORTED.to_string())
+    }
+
+    fn delete(&self, _key: &str) -> Result<(), String> {
+        Err(Self::UNSUPPORTED.to_string())
+    }
+
+    fn keys(&self) -> Result<Vec<String>, String> {
+        Err(Self::UNSUPPORTED.to_string())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SecretServiceStore {
+    const UNSUPPORTED: &'static str = "The Secret Service backend is only available on Linux";
+}
+
+/// Secrets held in memory only, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    secrets: std::sync::Mutex<BTreeMap<String, String>>,
+}
+
+#[cfg(test)]
+impl SecretStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(self.secrets.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        self.secrets
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.secrets.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>, String> {
+        Ok(self.secrets.lock().unwrap().keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Behaviour every backend must share
+    fn exercise_store(store: &dyn SecretStore) {
+        assert_eq!(store.get("anthropic").unwrap(), None);
+        store.set("anthropic", "sk-ant-1").unwrap();
+        store.set("openai", "sk-1").unwrap();
+        store.set("anthropic", "sk-ant-2").unwrap();
+        assert_eq!(store.get("anthropic").unwrap().as_deref(), Some("sk-ant-2"));
+
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["anthropic", "openai"]);
+        assert_eq!(
+            store.get_many(&["openai", "mistral", "anthropic"]).unwrap(),
+            vec![Some("sk-1".to_string()), None, Some("sk-ant-2".to_string())]
+        );
+
+        store.delete("anthropic").unwrap();
+        assert_eq!(store.get("anthropic").unwrap(), None);
+        assert_eq!(store.keys().unwrap(), vec!["openai"]);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        exercise_store(&MemoryStore::default());
+    }
+
+    #[test]
+    fn test_json_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise_store(&JsonFileStore::new(&dir.path().join("config.json")));
+    }
+
+    /// Cheap parameters so tests don't spend seconds deriving keys
+    fn test_kdf() -> KdfParams {
+        KdfParams::generate_with_costs(64, 1, 1)
+    }
+
+    #[test]
+    fn test_encrypted_file_store_does_not_leak_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let kdf = test_kdf();
+        let key = kdf.derive_key("correct horse").unwrap();
+        exercise_store(&EncryptedFileStore::new(&path, key, kdf.clone()));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("sk-1"));
+
+        let wrong_key = kdf.derive_key("battery staple").unwrap();
+        assert!(EncryptedFileStore::new(&path, wrong_key, kdf).verify().is_err());
+    }
+
+    #[test]
+    fn test_derive_key_depends_on_passphrase_and_salt() {
+        let kdf = test_kdf();
+        let key = kdf.derive_key("passphrase").unwrap();
+        assert_eq!(kdf.derive_key("passphrase").unwrap(), key);
+        assert_ne!(kdf.derive_key("other").unwrap(), key);
+        assert_ne!(test_kdf().derive_key("passphrase").unwrap(), key);
+    }
+
+    #[test]
+    fn test_migrate_secrets_moves_everything() {
+        let from = MemoryStore::default();
+        let to = MemoryStore::default();
+        from.set("anthropic", "sk-ant").unwrap();
+        from.set("google", "AIza").unwrap();
+
+        assert_eq!(migrate_secrets(&from, &to).unwrap(), 2);
+        assert!(from.keys().unwrap().is_empty());
+        assert_eq!(to.get("google").unwrap().as_deref(), Some("AIza"));
+    }
+
+    #[test]
+    fn test_backend_names() {
+        let backend: SecretBackend = serde_json::from_str("\"secret-service\"").unwrap();
+        assert_eq!(backend, SecretBackend::SecretService);
+        assert_eq!(
+            serde_json::to_string(&SecretBackend::EncryptedFile).unwrap(),
+            "\"encrypted-file\""
+        );
+    }
+}
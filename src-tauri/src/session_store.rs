@@ -0,0 +1,186 @@
+use crate::artifacts::Artifact;
+use crate::fs_utils::write_file_atomic;
+use crate::opencode::StatusUpdate;
+use crate::sessions::{SessionInfo, SessionListing};
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Who sent a message in a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSender {
+    User,
+    Bot,
+}
+
+/// A message in a conversation as persisted on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub sender: MessageSender,
+    pub text: String,
+    /// Time the message was recorded, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Status updates emitted while the agent produced this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub execution_log: Vec<StatusUpdate>,
+    /// Tokens and cost spent producing this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Files the agent wrote in the workspace while producing this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<Artifact>,
+}
+
+/// A past conversation as returned to the frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionHistory {
+    pub session: SessionInfo,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Index of all sessions, stored in `sessions.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionIndex {
+    #[serde(default)]
+    sessions: Vec<SessionInfo>,
+    #[serde(default)]
+    active_session_id: Option<String>,
+}
+
+/// Persists session metadata and message history under the app data directory:
+///
+/// ```text
+/// <root>/sessions.json              session index
+/// <root>/sessions/<id>/messages.json message history of one session
+/// ```
+pub struct SessionStore {
+    root: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join("sessions.json")
+    }
+
+    fn session_dir(&self, session_id: &str) -> PathBuf {
+        self.root.join("sessions").join(session_id)
+    }
+
+    fn messages_path(&self, session_id: &str) -> PathBuf {
+        self.session_dir(session_id).join("messages.json")
+    }
+
+    /// Load the session index. A missing index means there are no sessions yet.
+    pub fn load_listing(&self) -> Result<SessionListing, String> {
+        let index: SessionIndex = read_json_or_default(&self.index_path())?;
+        Ok(SessionListing {
+            sessions: index.sessions,
+            active_session_id: index.active_session_id,
+        })
+    }
+
+    pub fn save_listing(&self, listing: &SessionListing) -> Result<(), String> {
+        let index = SessionIndex {
+            sessions: listing.sessions.clone(),
+            active_session_id: listing.active_session_id.clone(),
+        };
+        write_json(&self.index_path(), &index)
+    }
+
+    pub fn load_messages(&self, session_id: &str) -> Result<Vec<ChatMessage>, String> {
+        read_json_or_default(&self.messages_path(session_id))
+    }
+
+    pub fn append_message(&self, session_id: &str, message: ChatMessage) -> Result<(), String> {
+        let mut messages = self.load_messages(session_id)?;
+        messages.push(message);
+        write_json(&self.messages_path(session_id), &messages)
+    }
+
+    /// Remove everything stored for a session
+    pub fn delete_session(&self, session_id: &str) -> Result<(), String> {
+        let dir = self.session_dir(session_id);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .map_err(|e| format!("Failed to remove session data {:?}: {}", dir, e))?;
+        }
+        Ok(())
+    }
+}
+
+fn read_json_or_default<T: Default + for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse {:?}: {}", path, e))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize {:?}: {}", path, e))?;
+    write_file_atomic(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::SessionRegistry;
+
+    fn message(sender: MessageSender, text: &str) -> ChatMessage {
+        ChatMessage {
+            sender,
+            text: text.to_string(),
+            timestamp: 1,
+            execution_log: Vec::new(),
+            usage: None,
+            artifacts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_listing_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        assert!(store.load_listing().unwrap().sessions.is_empty());
+
+        let mut registry = SessionRegistry::default();
+        let session = registry.create(None, "anthropic", "claude-sonnet-4-5");
+        registry.set_opencode_session_id(&session.id, "ses_1").unwrap();
+        store.save_listing(&registry.listing()).unwrap();
+
+        let listing = store.load_listing().unwrap();
+        assert_eq!(listing.active_session_id, Some(session.id.clone()));
+        assert_eq!(listing.sessions[0].opencode_session_id.as_deref(), Some("ses_1"));
+    }
+
+    #[test]
+    fn test_messages_are_appended_and_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+
+        store
+            .append_message("s1", message(MessageSender::User, "Hi"))
+            .unwrap();
+        store
+            .append_message("s1", message(MessageSender::Bot, "Hello!"))
+            .unwrap();
+
+        let messages = store.load_messages("s1").unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].sender, MessageSender::Bot);
+        assert_eq!(messages[1].text, "Hello!");
+
+        store.delete_session("s1").unwrap();
+        assert!(store.load_messages("s1").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::attachments::Attachment;
+use crate::usage::Usage;
+use crate::time_utils::now_millis;
+use serde::{Deserialize, Serialize};
+
+/// Title given to sessions until their first message arrives
+const DEFAULT_TITLE: &str = "New conversation";
+
+/// Maximum length of a title derived from the first message
+const MAX_TITLE_LENGTH: usize = 60;
+
+/// A chat session and the opencode session backing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    /// Assigned by opencode on the first message of the session
+    pub opencode_session_id: Option<String>,
+    pub title: String,
+    pub provider_id: String,
+    pub model_id: String,
+    /// Creation time in milliseconds since the Unix epoch
+    pub created_at: u64,
+    /// Tokens and cost of all messages so far
+    #[serde(default)]
+    pub usage: Usage,
+    /// Hold back latchkey requests that would change something until the user confirms them
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Files copied into the workspace for the agent, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+/// Snapshot of the registry returned to the frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionListing {
+    pub sessions: Vec<SessionInfo>,
+    pub active_session_id: Option<String>,
+}
+
+/// In-memory registry of chat sessions, ordered by creation time
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: Vec<SessionInfo>,
+    active_session_id: Option<String>,
+}
+
+impl SessionRegistry {
+    /// Restore a registry from a previously saved listing
+    pub fn from_listing(listing: SessionListing) -> Self {
+        Self {
+            sessions: listing.sessions,
+            active_session_id: listing.active_session_id,
+        }
+    }
+
+    /// Create a session and make it the active one
+    pub fn create(
+        &mut self,
+        title: Option<String>,
+        provider_id: &str,
+        model_id: &str,
+    ) -> SessionInfo {
+        let session = SessionInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            opencode_session_id: None,
+            title: title
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_TITLE.to_string()),
+            provider_id: provider_id.to_string(),
+            model_id: model_id.to_string(),
+            created_at: now_millis(),
+            usage: Usage::default(),
+            dry_run: false,
+            attachments: Vec::new(),
+        };
+        self.sessions.push(session.clone());
+        self.active_session_id = Some(session.id.clone());
+        session
+    }
+
+    pub fn listing(&self) -> SessionListing {
+        SessionListing {
+            sessions: self.sessions.clone(),
+            active_session_id: self.active_session_id.clone(),
+        }
+    }
+
+    pub fn get(&self, session_id: &str) -> Result<&SessionInfo, String> {
+        self.sessions
+            .iter()
+            .find(|s| s.id == session_id)
+            .ok_or_else(|| format!("Unknown session: {}", session_id))
+    }
+
+    fn get_mut(&mut self, session_id: &str) -> Result<&mut SessionInfo, String> {
+        self.sessions
+            .iter_mut()
+            .find(|s| s.id == session_id)
+            .ok_or_else(|| format!("Unknown session: {}", session_id))
+    }
+
+    /// Make an existing session the active one
+    pub fn switch(&mut self, session_id: &str) -> Result<SessionInfo, String> {
+        let session = self.get(session_id)?.clone();
+        self.active_session_id = Some(session.id.clone());
+        Ok(session)
+    }
+
+    /// Remove a session. If it was active, the most recent remaining session becomes active.
+    pub fn delete(&mut self, session_id: &str) -> Result<SessionInfo, String> {
+        let index = self
+            .sessions
+            .iter()
+            .position(|s| s.id == session_id)
+            .ok_or_else(|| format!("Unknown session: {}", session_id))?;
+        let session = self.sessions.remove(index);
+        if self.active_session_id.as_deref() == Some(session_id) {
+            self.active_session_id = self.sessions.last().map(|s| s.id.clone());
+        }
+        Ok(session)
+    }
+
+    /// Record the model used for the latest message and name the session after
+    /// its first message if it still has the default title
+    pub fn record_message(
+        &mut self,
+        session_id: &str,
+        message: &str,
+        provider_id: &str,
+        model_id: &str,
+    ) -> Result<(), String> {
+        let session = self.get_mut(session_id)?;
+        session.provider_id = provider_id.to_string();
+        session.model_id = model_id.to_string();
+        if session.title == DEFAULT_TITLE {
+            if let Some(title) = title_from_message(message) {
+                session.title = title;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the opencode session ID the first time opencode reports one
+    pub fn set_opencode_session_id(
+        &mut self,
+        session_id: &str,
+        opencode_session_id: &str,
+    ) -> Result<(), String> {
+        let session = self.get_mut(session_id)?;
+        if session.opencode_session_id.is_none() {
+            eprintln!(
+                "[sessions] Session {} is backed by opencode session {}",
+                session_id, opencode_session_id
+            );
+            session.opencode_session_id = Some(opencode_session_id.to_string());
+        }
+        Ok(())
+    }
+
+    /// Add the usage of a message to the session's totals and return the new totals
+    pub fn record_usage(&mut self, session_id: &str, usage: &Usage) -> Result<Usage, String> {
+        let session = self.get_mut(session_id)?;
+        session.usage.add(usage);
+        Ok(session.usage)
+    }
+
+    /// Turn dry run mode on or off for a session
+    pub fn set_dry_run(&mut self, session_id: &str, enabled: bool) -> Result<SessionInfo, String> {
+        let session = self.get_mut(session_id)?;
+        session.dry_run = enabled;
+        Ok(session.clone())
+    }
+
+    /// Record files attached to a session, skipping ones it already has
+    pub fn add_attachments(&mut self, session_id: &str, attachments: &[Attachment]) -> Result<SessionInfo, String> {
+        let session = self.get_mut(session_id)?;
+        for attachment in attachments {
+            if !session.attachments.iter().any(|a| a.hash == attachment.hash) {
+                session.attachments.push(attachment.clone());
+            }
+        }
+        Ok(session.clone())
+    }
+
+    /// Attachments that haven't been passed to opencode yet
+    pub fn unsent_attachments(&self, session_id: &str) -> Result<Vec<Attachment>, String> {
+        Ok(self
+            .get(session_id)?
+            .attachments
+            .iter()
+            .filter(|a| !a.sent)
+            .cloned()
+            .collect())
+    }
+
+    /// Note that attachments were passed to opencode with a message
+    pub fn mark_attachments_sent(&mut self, session_id: &str, attachments: &[Attachment]) -> Result<(), String> {
+        for attachment in &mut self.get_mut(session_id)?.attachments {
+            if attachments.iter().any(|a| a.hash == attachment.hash) {
+                attachment.sent = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the opencode session, so the next message starts a fresh one
+    pub fn clear_opencode_session_id(&mut self, session_id: &str) -> Result<(), String> {
+        self.get_mut(session_id)?.opencode_session_id = None;
+        Ok(())
+    }
+}
+
+/// Use the first line of a message as a session title
+fn title_from_message(message: &str) -> Option<String> {
+    let line = message.lines().map(str::trim).find(|l| !l.is_empty())?;
+    if line.chars().count() <= MAX_TITLE_LENGTH {
+        Some(line.to_string())
+    } else {
+        let truncated: String = line.chars().take(MAX_TITLE_LENGTH - 3).collect();
+        Some(format!("{}...", truncated.trim_end()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_makes_session_active() {
+        let mut registry = SessionRegistry::default();
+        let first = registry.create(None, "anthropic", "claude-sonnet-4-5");
+        let second = registry.create(Some("Slack triage".to_string()), "openai", "o3");
+
+        let listing = registry.listing();
+        assert_eq!(listing.sessions.len(), 2);
+        assert_eq!(listing.active_session_id, Some(second.id.clone()));
+        assert_eq!(first.title, DEFAULT_TITLE);
+        assert_eq!(second.title, "Slack triage");
+        assert_ne!(first.id, second.id);
+    }
+
+    #[test]
+    fn test_delete_active_falls_back_to_latest() {
+        let mut registry = SessionRegistry::default();
+        let first = registry.create(None, "anthropic", "claude-sonnet-4-5");
+        let second = registry.create(None, "anthropic", "claude-sonnet-4-5");
+
+        registry.delete(&second.id).unwrap();
+        assert_eq!(registry.listing().active_session_id, Some(first.id.clone()));
+
+        registry.delete(&first.id).unwrap();
+        assert_eq!(registry.listing().active_session_id, None);
+        assert!(registry.delete(&first.id).is_err());
+    }
+
+    #[test]
+    fn test_attachments_are_sent_once() {
+        let mut registry = SessionRegistry::default();
+        let session = registry.create(None, "anthropic", "claude-sonnet-4-5");
+        let attachment = |name: &str| Attachment {
+            path: format!("attachments/{}", name),
+            source: std::path::PathBuf::from(name),
+            size: 1,
+            hash: name.to_string(),
+            attached_at: 0,
+            sent: false,
+        };
+
+        registry
+            .add_attachments(&session.id, &[attachment("a.csv"), attachment("a.csv")])
+            .unwrap();
+        let pending = registry.unsent_attachments(&session.id).unwrap();
+        assert_eq!(pending.len(), 1);
+
+        registry.mark_attachments_sent(&session.id, &pending).unwrap();
+        registry.add_attachments(&session.id, &[attachment("b.pdf")]).unwrap();
+        let pending = registry.unsent_attachments(&session.id).unwrap();
+        assert_eq!(pending, vec![attachment("b.pdf")]);
+        assert_eq!(registry.get(&session.id).unwrap().attachments.len(), 2);
+    }
+
+    #[test]
+    fn test_record_message_sets_title_once() {
+        let mut registry = SessionRegistry::default();
+        let session = registry.create(None, "anthropic", "claude-sonnet-4-5");
+
+        registry
+            .record_message(
+                &session.id,
+                "\nSummarize my unread Slack messages\nThanks",
+                "openai",
+                "o3",
+            )
+            .unwrap();
+        registry
+            .record_message(&session.id, "Now post a summary", "openai", "o3")
+            .unwrap();
+
+        let session = registry.get(&session.id).unwrap();
+        assert_eq!(session.title, "Summarize my unread Slack messages");
+        assert_eq!(session.provider_id, "openai");
+        assert_eq!(session.model_id, "o3");
+    }
+
+    #[test]
+    fn test_opencode_session_id_is_kept() {
+        let mut registry = SessionRegistry::default();
+        let session = registry.create(None, "anthropic", "claude-sonnet-4-5");
+
+        registry
+            .set_opencode_session_id(&session.id, "ses_1")
+            .unwrap();
+        registry
+            .set_opencode_session_id(&session.id, "ses_2")
+            .unwrap();
+        assert_eq!(
+            registry
+                .get(&session.id)
+                .unwrap()
+                .opencode_session_id
+                .as_deref(),
+            Some("ses_1")
+        );
+    }
+
+    #[test]
+    fn test_title_from_message_truncates() {
+        let long = "a".repeat(100);
+        let title = title_from_message(&long).unwrap();
+        assert_eq!(title.chars().count(), MAX_TITLE_LENGTH);
+        assert!(title.ends_with("..."));
+        assert!(title_from_message("  \n ").is_none());
+    }
+}
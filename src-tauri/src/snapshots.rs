@@ -0,0 +1,680 @@
+use crate::attachments::ATTACHMENTS_DIR;
+use crate::fs_utils::write_file_atomic;
+use crate::workspace::{content_hash, relative_path, METADATA_DIR};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Directory in the workspace's metadata directory holding the snapshots
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Directory in [`SNAPSHOTS_DIR`] holding file contents, named by their hash
+const OBJECTS_DIR: &str = "objects";
+
+/// Snapshots kept per workspace; older ones are deleted
+const MAX_SNAPSHOTS: usize = 50;
+
+/// Longest message summary stored with a snapshot
+const MAX_SUMMARY_CHARS: usize = 120;
+
+/// Files larger than this are reported as changed without a line diff
+const MAX_DIFF_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Upper bound on the lines compared against each other by [`diff_lines`]
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Unchanged lines shown around each change in a diff
+const CONTEXT_LINES: usize = 3;
+
+/// A file as it was when the snapshot was taken
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SnapshotFile {
+    /// Hex SHA-256 of the content, which is stored in [`OBJECTS_DIR`] under this name
+    hash: String,
+    size: u64,
+    /// Modification time in milliseconds since the Unix epoch
+    modified: Option<u64>,
+}
+
+/// The files of a workspace before a message was sent, stored as `<id>.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Snapshot {
+    id: String,
+    session_id: String,
+    /// Time the snapshot was taken, in milliseconds since the Unix epoch
+    created_at: u64,
+    /// What the snapshot was taken for, e.g. the message about to be sent
+    summary: String,
+    /// Keyed by path relative to the workspace, with `/` separators
+    files: BTreeMap<String, SnapshotFile>,
+}
+
+/// A snapshot as listed in the UI
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub created_at: u64,
+    pub summary: String,
+    pub file_count: usize,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Created since the snapshot
+    Added,
+    Modified,
+    /// Removed since the snapshot
+    Deleted,
+}
+
+/// How a file changed since a snapshot was taken
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub change: ChangeKind,
+    /// Unified diff from the snapshot to the current file. None for binary and large files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// Takes and restores snapshots of workspaces, so the user can undo what the agent did
+/// with a message. Unchanged files are stored once, whatever the number of snapshots.
+/// Attachments are copies of the user's own files and are left out.
+#[derive(Default)]
+pub struct Snapshots {
+    /// Held while a snapshot store is changed, since shared workspaces have one for all sessions
+    lock: Mutex<()>,
+}
+
+impl Snapshots {
+    /// Record the current files of the workspace
+    pub fn create(&self, workspace: &Path, session_id: &str, summary: &str, now: u64) -> Result<SnapshotInfo, String> {
+        let _lock = self.lock.lock().unwrap();
+        let snapshot = create_snapshot(workspace, session_id, summary, now)?;
+        prune(&store_dir(workspace))?;
+        Ok(snapshot.info())
+    }
+
+    /// The session's snapshots of the workspace, newest first
+    pub fn list(&self, workspace: &Path, session_id: &str) -> Result<Vec<SnapshotInfo>, String> {
+        let _lock = self.lock.lock().unwrap();
+        Ok(load_all(&store_dir(workspace))?
+            .into_iter()
+            .filter(|snapshot| snapshot.session_id == session_id)
+            .map(|snapshot| snapshot.info())
+            .collect())
+    }
+
+    /// What changed in the workspace since the snapshot, with line diffs of text files
+    pub fn diff(&self, workspace: &Path, id: &str) -> Result<Vec<FileChange>, String> {
+        let _lock = self.lock.lock().unwrap();
+        let store = store_dir(workspace);
+        let snapshot = load(&store, id)?;
+        let mut changes = changes(workspace, &snapshot)?;
+        for change in &mut changes {
+            let before = match snapshot.files.get(&change.path) {
+                Some(file) => Some(read_object(&store, file)?),
+                None => None,
+            };
+            let current = workspace.join(&change.path);
+            let after = match change.change {
+                ChangeKind::Deleted => None,
+                ChangeKind::Added | ChangeKind::Modified => Some(
+                    fs::read(&current).map_err(|e| format!("Failed to read {:?}: {}", current, e))?,
+                ),
+            };
+            change.diff = text_diff(before.as_deref(), after.as_deref());
+        }
+        Ok(changes)
+    }
+
+    /// Put the workspace's files back the way they were when the snapshot was taken.
+    /// The current files are snapshotted first, so the restore can be undone as well.
+    /// Returns the changes that were reverted.
+    pub fn restore(&self, workspace: &Path, session_id: &str, id: &str, now: u64) -> Result<Vec<FileChange>, String> {
+        let _lock = self.lock.lock().unwrap();
+        let store = store_dir(workspace);
+        let snapshot = load(&store, id)?;
+        let changes = changes(workspace, &snapshot)?;
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        create_snapshot(
+            workspace,
+            session_id,
+            &format!("Before restoring the snapshot of {}", snapshot.summary),
+            now,
+        )?;
+
+        for change in &changes {
+            let path = workspace.join(&change.path);
+            let unlinked = remove_link_on_path(workspace, &change.path)?;
+            match snapshot.files.get(&change.path) {
+                Some(file) => write_file_atomic(&path, &read_object(&store, file)?)?,
+                // Nothing is left to remove when the file was behind a link
+                None if unlinked => {}
+                None => {
+                    fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+                    remove_empty_parents(workspace, &path);
+                }
+            }
+        }
+        prune(&store)?;
+        Ok(changes)
+    }
+}
+
+impl Snapshot {
+    fn info(&self) -> SnapshotInfo {
+        SnapshotInfo {
+            id: self.id.clone(),
+            created_at: self.created_at,
+            summary: self.summary.clone(),
+            file_count: self.files.len(),
+            size: self.files.values().map(|file| file.size).sum(),
+        }
+    }
+}
+
+fn store_dir(workspace: &Path) -> PathBuf {
+    workspace.join(METADATA_DIR).join(SNAPSHOTS_DIR)
+}
+
+fn create_snapshot(workspace: &Path, session_id: &str, summary: &str, now: u64) -> Result<Snapshot, String> {
+    let store = store_dir(workspace);
+    let objects = store.join(OBJECTS_DIR);
+    // Files that look unchanged since the last snapshot aren't read again
+    let previous = load_all(&store)?.into_iter().next();
+
+    let mut files = BTreeMap::new();
+    for (path, current) in scan(workspace)? {
+        let unchanged = previous
+            .as_ref()
+            .and_then(|snapshot| {
+                snapshot
+                    .files
+                    .get(&path)
+                    .filter(|file| looks_unchanged(snapshot, file, &current))
+            })
+            .filter(|file| objects.join(&file.hash).exists());
+        let hash = match unchanged {
+            Some(file) => file.hash.clone(),
+            None => {
+                let file = workspace.join(&path);
+                let content = fs::read(&file).map_err(|e| format!("Failed to read {:?}: {}", file, e))?;
+                let hash = content_hash(&content);
+                let object = objects.join(&hash);
+                if !object.exists() {
+                    write_file_atomic(&object, &content)?;
+                }
+                hash
+            }
+        };
+        files.insert(path, SnapshotFile { hash, ..current });
+    }
+
+    // Snapshots taken in the same millisecond still need their own ID
+    let mut created_at = now;
+    while store.join(format!("{}.json", created_at)).exists() {
+        created_at += 1;
+    }
+    let snapshot = Snapshot {
+        id: created_at.to_string(),
+        session_id: session_id.to_string(),
+        created_at,
+        summary: summarize(summary),
+        files,
+    };
+    let content = serde_json::to_string_pretty(&snapshot)
+        .map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+    write_file_atomic(&store.join(format!("{}.json", snapshot.id)), content.as_bytes())?;
+    Ok(snapshot)
+}
+
+fn summarize(summary: &str) -> String {
+    let line = summary.lines().next().unwrap_or_default().trim();
+    if line.chars().count() > MAX_SUMMARY_CHARS {
+        format!("{}...", line.chars().take(MAX_SUMMARY_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+fn load(store: &Path, id: &str) -> Result<Snapshot, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid snapshot ID: {}", id));
+    }
+    let path = store.join(format!("{}.json", id));
+    let content = fs::read_to_string(&path).map_err(|_| format!("Snapshot not found: {}", id))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse snapshot {}: {}", id, e))
+}
+
+/// All snapshots in the store, newest first
+fn load_all(store: &Path) -> Result<Vec<Snapshot>, String> {
+    if !store.exists() {
+        return Ok(Vec::new());
+    }
+    let entries = fs::read_dir(store).map_err(|e| format!("Failed to read directory {:?}: {}", store, e))?;
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".json") {
+            match load(store, id) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => eprintln!("[snapshots] Skipping {}: {}", name, e),
+            }
+        }
+    }
+    snapshots.sort_by_key(|snapshot| Reverse(snapshot.created_at));
+    Ok(snapshots)
+}
+
+/// Delete the oldest snapshots beyond [`MAX_SNAPSHOTS`] and the contents only they used
+fn prune(store: &Path) -> Result<(), String> {
+    let snapshots = load_all(store)?;
+    if snapshots.len() <= MAX_SNAPSHOTS {
+        return Ok(());
+    }
+    let (kept, removed) = snapshots.split_at(MAX_SNAPSHOTS);
+    for snapshot in removed {
+        let path = store.join(format!("{}.json", snapshot.id));
+        fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+    }
+
+    let used: HashSet<&str> = kept
+        .iter()
+        .flat_map(|snapshot| snapshot.files.values())
+        .map(|file| file.hash.as_str())
+        .collect();
+    let objects = store.join(OBJECTS_DIR);
+    let entries = fs::read_dir(&objects).map_err(|e| format!("Failed to read directory {:?}: {}", objects, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    eprintln!("[snapshots] Deleted {} old snapshots in {:?}", removed.len(), store);
+    Ok(())
+}
+
+fn read_object(store: &Path, file: &SnapshotFile) -> Result<Vec<u8>, String> {
+    let path = store.join(OBJECTS_DIR).join(&file.hash);
+    fs::read(&path).map_err(|e| format!("Failed to read snapshot content {:?}: {}", path, e))
+}
+
+/// Files in the workspace that snapshots cover, without their hashes
+fn scan(workspace: &Path) -> Result<BTreeMap<String, SnapshotFile>, String> {
+    let mut files = BTreeMap::new();
+    if workspace.exists() {
+        scan_dir(workspace, workspace, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn scan_dir(workspace: &Path, dir: &Path, files: &mut BTreeMap<String, SnapshotFile>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory {:?}: {}", dir, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let path = entry.path();
+        if dir == workspace && (entry.file_name() == METADATA_DIR || entry.file_name() == ATTACHMENTS_DIR) {
+            continue;
+        }
+        // Links aren't followed, they may lead out of the workspace
+        let metadata = entry
+            .metadata()
+            .map_err(|e| format!("Failed to read metadata of {:?}: {}", path, e))?;
+        if metadata.is_dir() {
+            scan_dir(workspace, &path, files)?;
+        } else if metadata.is_file() {
+            files.insert(
+                relative_path(workspace, &path),
+                SnapshotFile {
+                    hash: String::new(),
+                    size: metadata.len(),
+                    modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_millis() as u64),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether a file has the size and modification time it had in the snapshot. Times too
+/// close to the snapshot are not trusted, since a later write may not have changed them.
+fn looks_unchanged(snapshot: &Snapshot, old: &SnapshotFile, current: &SnapshotFile) -> bool {
+    old.size == current.size
+        && old.modified == current.modified
+        && old.modified.is_some_and(|modified| modified < snapshot.created_at)
+}
+
+/// Files added, modified and deleted since the snapshot, sorted by path
+fn changes(workspace: &Path, snapshot: &Snapshot) -> Result<Vec<FileChange>, String> {
+    let current = scan(workspace)?;
+    let mut changes = Vec::new();
+    for (path, file) in &current {
+        let change = match snapshot.files.get(path) {
+            None => Some(ChangeKind::Added),
+            Some(old) if old.size != file.size => Some(ChangeKind::Modified),
+            Some(old) if looks_unchanged(snapshot, old, file) => None,
+            Some(old) => {
+                let full_path = workspace.join(path);
+                let content = fs::read(&full_path).map_err(|e| format!("Failed to read {:?}: {}", full_path, e))?;
+                (content_hash(&content) != old.hash).then_some(ChangeKind::Modified)
+            }
+        };
+        if let Some(change) = change {
+            changes.push(FileChange {
+                path: path.clone(),
+                change,
+                diff: None,
+            });
+        }
+    }
+    for path in snapshot.files.keys().filter(|path| !current.contains_key(*path)) {
+        changes.push(FileChange {
+            path: path.clone(),
+            change: ChangeKind::Deleted,
+            diff: None,
+        });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Don't write through a link the agent put in place of the file or one of its directories:
+/// remove the first link on the way from the workspace to `relative`. Returns whether there was one.
+fn remove_link_on_path(workspace: &Path, relative: &str) -> Result<bool, String> {
+    let mut path = workspace.to_path_buf();
+    for component in relative.split('/') {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+                return Ok(true);
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(false)
+}
+
+/// Remove the directories a deleted file leaves empty, up to the workspace
+fn remove_empty_parents(workspace: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == workspace || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Unified diff between two versions of a file, missing on one side if it was added or
+/// deleted. None if either isn't text or is too large.
+fn text_diff(before: Option<&[u8]>, after: Option<&[u8]>) -> Option<String> {
+    let lines = diff_lines(as_text(before)?, as_text(after)?)?;
+    Some(unified(&lines))
+}
+
+/// A missing file counts as empty
+fn as_text(content: Option<&[u8]>) -> Option<&str> {
+    match content {
+        None => Some(""),
+        Some(content) if content.len() as u64 > MAX_DIFF_FILE_BYTES || content.contains(&0) => None,
+        Some(content) => std::str::from_utf8(content).ok(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line by line differences, from the longest common subsequence of the lines
+fn diff_lines<'a>(before: &'a str, after: &'a str) -> Option<Vec<Line<'a>>> {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    // Only the part between the common start and end needs comparing
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    // common[i][j] is the length of the common subsequence of old_middle[i..] and new_middle[j..]
+    let mut common = vec![vec![0u32; new_middle.len() + 1]; old_middle.len() + 1];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            common[i][j] = if old_middle[i] == new_middle[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<Line> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() && j < new_middle.len() {
+        if old_middle[i] == new_middle[j] {
+            lines.push(Line::Same(old_middle[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            lines.push(Line::Removed(old_middle[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new_middle[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old_middle[i..].iter().map(|line| Line::Removed(line)));
+    lines.extend(new_middle[j..].iter().map(|line| Line::Added(line)));
+    lines.extend(old[old.len() - suffix..].iter().map(|line| Line::Same(line)));
+    Some(lines)
+}
+
+/// Format differences as hunks with a few lines of context, like `diff -u`
+fn unified(lines: &[Line]) -> String {
+    // Line numbers in the old and new version at each line
+    let mut positions = Vec::with_capacity(lines.len());
+    let (mut old, mut new) = (0, 0);
+    for line in lines {
+        positions.push((old, new));
+        match line {
+            Line::Same(_) => {
+                old += 1;
+                new += 1;
+            }
+            Line::Removed(_) => old += 1,
+            Line::Added(_) => new += 1,
+        }
+    }
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if matches!(lines[i], Line::Same(_)) {
+            i += 1;
+            continue;
+        }
+        // Changes close to each other share a hunk
+        let mut changes_end = i + 1;
+        for (j, line) in lines.iter().enumerate().skip(i + 1) {
+            if !matches!(line, Line::Same(_)) {
+                changes_end = j + 1;
+            } else if j >= changes_end + 2 * CONTEXT_LINES {
+                break;
+            }
+        }
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (changes_end + CONTEXT_LINES).min(lines.len());
+        let hunk = &lines[start..end];
+        let old_count = hunk.iter().filter(|line| !matches!(line, Line::Added(_))).count();
+        let new_count = hunk.iter().filter(|line| !matches!(line, Line::Removed(_))).count();
+        // An empty range starts at the line before it
+        let range_start = |position: usize, count: usize| if count == 0 { position } else { position + 1 };
+        let (old_start, new_start) = positions[start];
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            range_start(old_start, old_count),
+            old_count,
+            range_start(new_start, new_count),
+            new_count
+        );
+        for line in hunk {
+            let _ = match line {
+                Line::Same(text) => writeln!(out, " {}", text),
+                Line::Removed(text) => writeln!(out, "-{}", text),
+                Line::Added(text) => writeln!(out, "+{}", text),
+            };
+        }
+        i = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_diff_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path();
+        fs::create_dir_all(workspace.join("notes")).unwrap();
+        fs::create_dir_all(workspace.join(ATTACHMENTS_DIR)).unwrap();
+        fs::write(workspace.join("report.md"), "# Report\n\nDraft\n").unwrap();
+        fs::write(workspace.join("notes/keep.txt"), "keep\n").unwrap();
+        fs::write(workspace.join(ATTACHMENTS_DIR).join("input.csv"), "a,b\n").unwrap();
+
+        let snapshots = Snapshots::default();
+        let before = snapshots
+            .create(workspace, "session-1", "Write the report\nwith details", 1000)
+            .unwrap();
+        assert_eq!(before.summary, "Write the report");
+        assert_eq!(before.file_count, 2);
+
+        // The agent's turn
+        fs::write(workspace.join("report.md"), "# Report\n\nFinal\n").unwrap();
+        fs::remove_file(workspace.join("notes/keep.txt")).unwrap();
+        fs::create_dir_all(workspace.join("out/charts")).unwrap();
+        fs::write(workspace.join("out/charts/chart.png"), [0x89, 0]).unwrap();
+        fs::write(workspace.join(ATTACHMENTS_DIR).join("input.csv"), "c,d\n").unwrap();
+
+        let changes = snapshots.diff(workspace, &before.id).unwrap();
+        let summary: Vec<_> = changes.iter().map(|c| (c.path.as_str(), c.change)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("notes/keep.txt", ChangeKind::Deleted),
+                ("out/charts/chart.png", ChangeKind::Added),
+                ("report.md", ChangeKind::Modified),
+            ]
+        );
+        assert_eq!(changes[0].diff.as_deref(), Some("@@ -1,1 +0,0 @@\n-keep\n"));
+        assert_eq!(changes[1].diff, None);
+        assert_eq!(
+            changes[2].diff.as_deref(),
+            Some("@@ -1,3 +1,3 @@\n # Report\n \n-Draft\n+Final\n")
+        );
+
+        let reverted = snapshots.restore(workspace, "session-1", &before.id, 2000).unwrap();
+        assert_eq!(reverted, changes.into_iter().map(|c| FileChange { diff: None, ..c }).collect::<Vec<_>>());
+        assert_eq!(fs::read_to_string(workspace.join("report.md")).unwrap(), "# Report\n\nDraft\n");
+        assert_eq!(fs::read_to_string(workspace.join("notes/keep.txt")).unwrap(), "keep\n");
+        assert!(!workspace.join("out").exists());
+        // Attachments are left alone
+        assert_eq!(fs::read_to_string(workspace.join(ATTACHMENTS_DIR).join("input.csv")).unwrap(), "c,d\n");
+        assert!(snapshots.diff(workspace, &before.id).unwrap().is_empty());
+
+        // The restore itself can be undone
+        let listed = snapshots.list(workspace, "session-1").unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].summary.starts_with("Before restoring"));
+        snapshots.restore(workspace, "session-1", &listed[0].id, 3000).unwrap();
+        assert_eq!(fs::read_to_string(workspace.join("report.md")).unwrap(), "# Report\n\nFinal\n");
+
+        assert!(snapshots.list(workspace, "session-2").unwrap().is_empty());
+        assert!(snapshots.diff(workspace, "../sessions").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_does_not_write_through_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path().join("workspace");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(workspace.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(workspace.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(workspace.join("notes.txt"), "notes\n").unwrap();
+
+        let snapshots = Snapshots::default();
+        let before = snapshots.create(&workspace, "session-1", "Start", 1000).unwrap();
+
+        // The agent replaces a directory and a file with links out of the workspace
+        fs::remove_dir_all(workspace.join("src")).unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join("src")).unwrap();
+        fs::remove_file(workspace.join("notes.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.join("notes.txt"), workspace.join("notes.txt")).unwrap();
+
+        snapshots.restore(&workspace, "session-1", &before.id, 2000).unwrap();
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+        assert!(!fs::symlink_metadata(workspace.join("src")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(workspace.join("src/main.rs")).unwrap(), "fn main() {}\n");
+        assert_eq!(fs::read_to_string(workspace.join("notes.txt")).unwrap(), "notes\n");
+    }
+
+    #[test]
+    fn test_old_snapshots_are_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = dir.path();
+        let snapshots = Snapshots::default();
+        for i in 0..MAX_SNAPSHOTS + 2 {
+            fs::write(workspace.join("counter.txt"), i.to_string()).unwrap();
+            snapshots.create(workspace, "session-1", "Count", i as u64).unwrap();
+        }
+        let listed = snapshots.list(workspace, "session-1").unwrap();
+        assert_eq!(listed.len(), MAX_SNAPSHOTS);
+        assert_eq!(listed.last().unwrap().id, "2");
+        let objects = fs::read_dir(store_dir(workspace).join(OBJECTS_DIR)).unwrap().count();
+        assert_eq!(objects, MAX_SNAPSHOTS);
+    }
+
+    #[test]
+    fn test_unified_diff_hunks() {
+        let before = (1..=20).map(|i| format!("line {}\n", i)).collect::<String>();
+        let after = before.replace("line 2\n", "line two\n").replace("line 18\n", "");
+        let lines = diff_lines(&before, &after).unwrap();
+        assert_eq!(
+            unified(&lines),
+            "@@ -1,5 +1,5 @@\n line 1\n-line 2\n+line two\n line 3\n line 4\n line 5\n\
+             @@ -15,6 +15,5 @@\n line 15\n line 16\n line 17\n-line 18\n line 19\n line 20\n"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::fs_utils::write_file_atomic;
+use crate::opencode_events::StepFinishPart;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tokens and cost spent by opencode, as reported by its `step_finish` events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    /// Cost in US dollars
+    pub cost: f64,
+}
+
+impl Usage {
+    pub fn from_step(part: &StepFinishPart) -> Self {
+        let tokens = part.tokens.clone().unwrap_or_default();
+        Self {
+            input_tokens: tokens.input,
+            output_tokens: tokens.output,
+            reasoning_tokens: tokens.reasoning,
+            cache_read_tokens: tokens.cache.read,
+            cache_write_tokens: tokens.cache.write,
+            cost: part.cost.unwrap_or_default(),
+        }
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cost += other.cost;
+    }
+
+    /// All tokens processed, including those read from and written to the prompt cache
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens
+            + self.output_tokens
+            + self.reasoning_tokens
+            + self.cache_read_tokens
+            + self.cache_write_tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Usage::default()
+    }
+}
+
+/// Usage of one provider on one day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyUsage {
+    /// Local date as `YYYY-MM-DD`
+    pub date: String,
+    pub provider_id: String,
+    pub usage: Usage,
+}
+
+/// Spending of a session, for the usage report
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionUsage {
+    pub session_id: String,
+    pub title: String,
+    pub usage: Usage,
+}
+
+/// Returned by `get_usage`
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    /// Most recent day first
+    pub daily: Vec<DailyUsage>,
+    pub sessions: Vec<SessionUsage>,
+}
+
+/// Daily usage totals per provider, stored in `<root>/usage.json`.
+/// Kept apart from the sessions so deleting a conversation doesn't hide what it cost.
+pub struct UsageLedger {
+    path: PathBuf,
+    days: Vec<DailyUsage>,
+}
+
+impl UsageLedger {
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join("usage.json");
+        let days = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+            serde_json::from_str(&content).map_err(|e| format!("Failed to parse {:?}: {}", path, e))?
+        } else {
+            Vec::new()
+        };
+        Ok(Self { path, days })
+    }
+
+    /// Add usage to a provider's total for a day and save the ledger
+    pub fn record(&mut self, date: &str, provider_id: &str, usage: &Usage) -> Result<(), String> {
+        match self
+            .days
+            .iter_mut()
+            .find(|d| d.date == date && d.provider_id == provider_id)
+        {
+            Some(day) => day.usage.add(usage),
+            None => self.days.push(DailyUsage {
+                date: date.to_string(),
+                provider_id: provider_id.to_string(),
+                usage: *usage,
+            }),
+        }
+        let content = serde_json::to_string_pretty(&self.days)
+            .map_err(|e| format!("Failed to serialize {:?}: {}", self.path, e))?;
+        write_file_atomic(&self.path, content.as_bytes())
+    }
+
+    /// A provider's total for a day
+    pub fn day_total(&self, date: &str, provider_id: &str) -> Usage {
+        self.days
+            .iter()
+            .find(|d| d.date == date && d.provider_id == provider_id)
+            .map(|d| d.usage)
+            .unwrap_or_default()
+    }
+
+    /// All daily totals, most recent day first
+    pub fn daily(&self) -> Vec<DailyUsage> {
+        let mut days = self.days.clone();
+        days.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.provider_id.cmp(&b.provider_id)));
+        days
+    }
+}
+
+/// Today's local date as stored in the ledger
+pub fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input_tokens: u64, cost: f64) -> Usage {
+        Usage {
+            input_tokens,
+            cost,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_usage_from_step() {
+        let part: StepFinishPart = serde_json::from_value(serde_json::json!({
+            "reason": "stop",
+            "cost": 0.0125,
+            "tokens": {"input": 1200, "output": 80, "reasoning": 16, "cache": {"read": 11000, "write": 300}}
+        }))
+        .unwrap();
+        let mut total = Usage::from_step(&part);
+        total.add(&Usage::from_step(&part));
+        assert_eq!(
+            total,
+            Usage {
+                input_tokens: 2400,
+                output_tokens: 160,
+                reasoning_tokens: 32,
+                cache_read_tokens: 22000,
+                cache_write_tokens: 600,
+                cost: 0.025,
+            }
+        );
+
+        // Providers that don't report cost or tokens count as free
+        let part: StepFinishPart = serde_json::from_value(serde_json::json!({"reason": "stop"})).unwrap();
+        assert!(Usage::from_step(&part).is_empty());
+    }
+
+    #[test]
+    fn test_ledger_sums_per_day_and_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ledger = UsageLedger::load(dir.path()).unwrap();
+        ledger.record("2026-10-16", "anthropic", &usage(100, 0.5)).unwrap();
+        ledger.record("2026-10-17", "anthropic", &usage(10, 0.25)).unwrap();
+        ledger.record("2026-10-17", "openai", &usage(5, 0.125)).unwrap();
+        ledger.record("2026-10-17", "anthropic", &usage(20, 0.25)).unwrap();
+
+        let ledger = UsageLedger::load(dir.path()).unwrap();
+        assert_eq!(ledger.day_total("2026-10-17", "anthropic"), usage(30, 0.5));
+        assert!(ledger.day_total("2026-10-15", "anthropic").is_empty());
+        let daily: Vec<_> = ledger
+            .daily()
+            .into_iter()
+            .map(|d| (d.date, d.provider_id, d.usage))
+            .collect();
+        assert_eq!(
+            daily,
+            vec![
+                ("2026-10-17".to_string(), "anthropic".to_string(), usage(30, 0.5)),
+                ("2026-10-17".to_string(), "openai".to_string(), usage(5, 0.125)),
+                ("2026-10-16".to_string(), "anthropic".to_string(), usage(100, 0.5)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use crate::config::{get_config_path, ConfigFile};
+use crate::fs_utils::write_file_atomic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_shell::ShellExt;
+
+/// Directory in each workspace for the app's own bookkeeping, hidden from listings
+pub const METADATA_DIR: &str = ".passepartout";
+
+/// Records which template files were installed, see [`sync_template`]
+const TEMPLATE_MANIFEST: &str = "template.json";
+
+/// Upper bound on the files returned by [`Workspaces::list`]
+const MAX_LISTED_FILES: usize = 1000;
+
+/// Whether each session has a workspace of its own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkspaceMode {
+    #[default]
+    PerSession,
+    /// All sessions work in the same directory
+    Shared,
+}
+
+/// Where opencode works, stored as `workspace` in `~/.passepartout.json`.
+/// Changes apply the next time the app starts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceSettings {
+    /// Directory holding the workspaces, `workspaces` in the app data directory if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    #[serde(default)]
+    pub mode: WorkspaceMode,
+}
+
+impl WorkspaceSettings {
+    pub fn load() -> Result<Self, String> {
+        Ok(ConfigFile::load(&get_config_path()?)?.workspace)
+    }
+
+    pub fn save(self) -> Result<(), String> {
+        if let Some(root) = &self.root {
+            if !root.is_absolute() {
+                return Err(format!("Workspace directory must be an absolute path: {:?}", root));
+            }
+        }
+        ConfigFile::update(&get_config_path()?, |config| {
+            config.workspace = self;
+            Ok(())
+        })
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == WorkspaceSettings::default()
+    }
+}
+
+/// A file in a workspace
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceFile {
+    /// Relative to the workspace, with `/` separators
+    pub path: String,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+/// The persistent directories opencode works in, created from the bundled template
+pub struct Workspaces {
+    root: PathBuf,
+    mode: WorkspaceMode,
+    template: PathBuf,
+    /// Workspaces the template has been merged into since the app started
+    synced: Mutex<HashSet<PathBuf>>,
+}
+
+impl Workspaces {
+    pub fn new(settings: &WorkspaceSettings, app_data_path: &Path, template: &Path) -> Self {
+        Self {
+            root: settings
+                .root
+                .clone()
+                .unwrap_or_else(|| app_data_path.join("workspaces")),
+            mode: settings.mode,
+            template: template.to_path_buf(),
+            synced: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn mode(&self) -> WorkspaceMode {
+        self.mode
+    }
+
+    /// Directory holding one workspace per session, named by session ID
+    pub fn sessions_dir(&self) -> PathBuf {
+        self.root.join("sessions")
+    }
+
+    /// Directory opencode runs in for a given session
+    pub fn dir(&self, session_id: &str) -> PathBuf {
+        match self.mode {
+            WorkspaceMode::PerSession => self.sessions_dir().join(session_id),
+            WorkspaceMode::Shared => self.root.join("shared"),
+        }
+    }
+
+    /// Create the session's workspace from the template if it doesn't exist yet,
+    /// and merge template upgrades into it once per app start
+    pub fn ensure(&self, session_id: &str) -> Result<PathBuf, String> {
+        let workspace = self.dir(session_id);
+        if !workspace.exists() {
+            fs::create_dir_all(&workspace)
+                .map_err(|e| format!("Failed to create workspace {:?}: {}", workspace, e))?;
+            eprintln!("[workspace] Created workspace at: {:?}", workspace);
+        }
+        if self.synced.lock().unwrap().insert(workspace.clone()) {
+            let sync = sync_template(&self.template, &workspace)?;
+            if !sync.kept.is_empty() {
+                eprintln!(
+                    "[workspace] Kept changed files in {:?} instead of updating them: {}",
+                    workspace,
+                    sync.kept.join(", ")
+                );
+            }
+        }
+        Ok(workspace)
+    }
+
+    /// Files in the session's workspace, sorted by path
+    pub fn list(&self, session_id: &str) -> Result<Vec<WorkspaceFile>, String> {
+        let workspace = self.ensure(session_id)?;
+        let mut files = Vec::new();
+        list_files(&workspace, &workspace, &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    /// Delete everything in the session's workspace and start over from the template
+    pub fn reset(&self, session_id: &str) -> Result<PathBuf, String> {
+        self.remove_dir(&self.dir(session_id))?;
+        self.ensure(session_id)
+    }
+
+    /// Delete the session's workspace, unless it is shared with other sessions
+    pub fn remove(&self, session_id: &str) -> Result<(), String> {
+        match self.mode {
+            WorkspaceMode::PerSession => self.remove_dir(&self.dir(session_id)),
+            WorkspaceMode::Shared => Ok(()),
+        }
+    }
+
+    fn remove_dir(&self, workspace: &Path) -> Result<(), String> {
+        if workspace.exists() {
+            fs::remove_dir_all(workspace)
+                .map_err(|e| format!("Failed to remove workspace {:?}: {}", workspace, e))?;
+        }
+        self.synced.lock().unwrap().remove(workspace);
+        Ok(())
+    }
+}
+
+fn list_files(workspace: &Path, dir: &Path, files: &mut Vec<WorkspaceFile>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory {:?}: {}", dir, e))?;
+    for entry in entries {
+        if files.len() >= MAX_LISTED_FILES {
+            break;
+        }
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let path = entry.path();
+        if dir == workspace && entry.file_name() == METADATA_DIR {
+            continue;
+        }
+        let metadata = entry
+            .metadata()
+            .map_err(|e| format!("Failed to read metadata of {:?}: {}", path, e))?;
+        if metadata.is_dir() {
+            list_files(workspace, &path, files)?;
+        } else {
+            files.push(WorkspaceFile {
+                path: relative_path(workspace, &path),
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_millis() as u64),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// `path` relative to `base`, with `/` separators
+pub fn relative_path(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Hex SHA-256 of some content
+pub fn content_hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn file_hash(path: &Path) -> Result<String, String> {
+    let content = fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    Ok(content_hash(&content))
+}
+
+/// Hashes of the template files as they were installed, by relative path
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TemplateManifest {
+    files: BTreeMap<String, String>,
+}
+
+/// What merging the template into a workspace did, by path relative to the workspace
+#[derive(Debug, Default, PartialEq)]
+pub struct TemplateSync {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    /// Template files that changed but were left alone, since the user changed them too
+    pub kept: Vec<String>,
+}
+
+/// Bring the template files in a workspace up to date without clobbering the user's changes.
+/// A file is only replaced if it is still exactly as it was installed, and files the user
+/// deleted stay deleted.
+pub fn sync_template(template: &Path, workspace: &Path) -> Result<TemplateSync, String> {
+    let mut sync = TemplateSync::default();
+    if !template.exists() {
+        return Ok(sync);
+    }
+    let manifest_path = workspace.join(METADATA_DIR).join(TEMPLATE_MANIFEST);
+    let mut manifest: TemplateManifest = match fs::read_to_string(&manifest_path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse {:?}: {}", manifest_path, e))?,
+        Err(_) => TemplateManifest::default(),
+    };
+
+    let mut template_files = Vec::new();
+    list_files(template, template, &mut template_files)?;
+    for file in template_files {
+        let source = template.join(&file.path);
+        let target = workspace.join(&file.path);
+        let template_hash = file_hash(&source)?;
+        let installed_hash = manifest.files.get(&file.path).cloned();
+
+        let action = if !target.exists() {
+            match installed_hash {
+                Some(_) => None,
+                None => Some(&mut sync.added),
+            }
+        } else {
+            let current_hash = file_hash(&target)?;
+            if current_hash == template_hash {
+                manifest.files.insert(file.path.clone(), current_hash);
+                None
+            } else if installed_hash == Some(current_hash) {
+                Some(&mut sync.updated)
+            } else {
+                sync.kept.push(file.path.clone());
+                None
+            }
+        };
+        if let Some(list) = action {
+            let content = fs::read(&source).map_err(|e| format!("Failed to read {:?}: {}", source, e))?;
+            write_file_atomic(&target, &content)?;
+            manifest.files.insert(file.path.clone(), template_hash);
+            list.push(file.path);
+        }
+    }
+
+    let content = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| format!("Failed to serialize template manifest: {}", e))?;
+    write_file_atomic(&manifest_path, content.as_bytes())?;
+    Ok(sync)
+}
+
+/// Show a file or directory in the system file manager
+pub fn reveal<R: Runtime>(app: &AppHandle<R>, path: &Path) -> Result<(), String> {
+    let shell = app.shell();
+    #[cfg(target_os = "macos")]
+    let cmd = {
+        let cmd = shell.command("open");
+        let cmd = if path.is_file() { cmd.arg("-R") } else { cmd };
+        cmd.arg(path)
+    };
+    #[cfg(windows)]
+    let cmd = if path.is_file() {
+        shell.command("explorer").arg(format!("/select,{}", path.display()))
+    } else {
+        shell.command("explorer").arg(path)
+    };
+    // xdg-open can't select a file, so open its folder instead
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let cmd = shell
+        .command("xdg-open")
+        .arg(if path.is_file() { path.parent().unwrap_or(path) } else { path });
+    cmd.spawn()
+        .map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_template_upgrades_keep_user_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("template");
+        let workspace = dir.path().join("workspace");
+        write(&template.join(".opencode/skills/latchkey/SKILL.md"), "v1");
+        write(&template.join("README.md"), "v1");
+        write(&template.join("notes.md"), "v1");
+
+        let sync = sync_template(&template, &workspace).unwrap();
+        assert_eq!(sync.added.len(), 3);
+        assert_eq!(fs::read_to_string(workspace.join("README.md")).unwrap(), "v1");
+
+        // The user edits one file and deletes another, then the template is upgraded
+        write(&workspace.join("README.md"), "mine");
+        fs::remove_file(workspace.join("notes.md")).unwrap();
+        write(&workspace.join("report.csv"), "a,b");
+        for file in [".opencode/skills/latchkey/SKILL.md", "README.md", "notes.md"] {
+            write(&template.join(file), "v2");
+        }
+        write(&template.join("new.md"), "v2");
+
+        let sync = sync_template(&template, &workspace).unwrap();
+        assert_eq!(
+            sync,
+            TemplateSync {
+                added: vec!["new.md".to_string()],
+                updated: vec![".opencode/skills/latchkey/SKILL.md".to_string()],
+                kept: vec!["README.md".to_string()],
+            }
+        );
+        assert_eq!(fs::read_to_string(workspace.join("README.md")).unwrap(), "mine");
+        assert!(!workspace.join("notes.md").exists());
+        assert_eq!(fs::read_to_string(workspace.join("report.csv")).unwrap(), "a,b");
+    }
+
+    #[test]
+    fn test_per_session_and_shared_workspaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("template");
+        write(&template.join("README.md"), "hello");
+
+        let workspaces = Workspaces::new(&WorkspaceSettings::default(), dir.path(), &template);
+        let workspace = workspaces.ensure("session-1").unwrap();
+        assert_eq!(workspace, dir.path().join("workspaces/sessions/session-1"));
+        write(&workspace.join("out/report.csv"), "a,b");
+        let paths: Vec<String> = workspaces.list("session-1").unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec!["README.md", "out/report.csv"]);
+
+        workspaces.reset("session-1").unwrap();
+        let paths: Vec<String> = workspaces.list("session-1").unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(paths, vec!["README.md"]);
+        workspaces.remove("session-1").unwrap();
+        assert!(!workspace.exists());
+
+        let settings = WorkspaceSettings {
+            root: Some(dir.path().join("custom")),
+            mode: WorkspaceMode::Shared,
+        };
+        let workspaces = Workspaces::new(&settings, dir.path(), &template);
+        assert_eq!(workspaces.dir("session-1"), workspaces.dir("session-2"));
+        let workspace = workspaces.ensure("session-1").unwrap();
+        workspaces.remove("session-1").unwrap();
+        assert!(workspace.join("README.md").exists());
+    }
+}
@@ -0,0 +1,33 @@
+use crate::fs_utils::write_file_atomic;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// JSON schema of opencode.json, for editors
+const OPENCODE_CONFIG_SCHEMA: &str = "https://opencode.ai/config.json";
+
+/// Set or remove a top-level section of the opencode.json in a workspace,
+/// keeping the other sections. Creates the file if it doesn't exist.
+pub fn update_opencode_config(workspace: &Path, key: &str, value: Option<Value>) -> Result<(), String> {
+    let path = workspace.join("opencode.json");
+    let mut config = if path.exists() {
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        serde_json::from_str::<Map<String, Value>>(&content)
+            .map_err(|e| format!("Failed to parse {:?}: {}", path, e))?
+    } else {
+        Map::new()
+    };
+
+    if !config.contains_key("$schema") {
+        config.insert("$schema".to_string(), OPENCODE_CONFIG_SCHEMA.into());
+    }
+    match value {
+        Some(value) => config.insert(key.to_string(), value),
+        None => config.remove(key),
+    };
+
+    let content = serde_json::to_string_pretty(&config)
+        .map_err(|e| format!("Failed to serialize opencode config: {}", e))?;
+    write_file_atomic(&path, content.as_bytes())
+}
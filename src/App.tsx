@@ -2,6 +2,7 @@ import React, { useState, useRef, useEffect, useCallback } from 'react';
 import Markdown from 'react-markdown';
 import { invoke } from '@tauri-apps/api/core';
 import { listen, UnlistenFn } from '@tauri-apps/api/event';
+import { getCurrentWebview } from '@tauri-apps/api/webview';
 
 // Credential types
 interface CredentialStatus {
@@ -9,24 +10,137 @@ interface CredentialStatus {
   has_key: boolean;
 }
 
-// Provider display info
-const providerInfo: Record<string, { displayName: string; placeholder: string }> = {
-  anthropic: {
-    displayName: 'Anthropic',
-    placeholder: 'sk-ant-...',
-  },
-  openai: {
-    displayName: 'OpenAI',
-    placeholder: 'sk-...',
-  },
-  google: {
-    displayName: 'Google',
-    placeholder: 'AIza...',
-  },
+type SecretBackend = 'json-file' | 'secret-service' | 'encrypted-file';
+
+// Typed errors returned by the credential commands
+interface CredentialError {
+  kind: 'locked' | 'wrong-passphrase' | 'other';
+  message: string;
+}
+
+// Result of checking an API key with its provider
+type KeyValidation =
+  | { status: 'valid' }
+  | { status: 'invalid'; message: string }
+  | { status: 'network-error'; message: string }
+  | { status: 'rate-limited' };
+
+const describeValidation = (validation: KeyValidation): string => {
+  switch (validation.status) {
+    case 'valid':
+      return 'Key is valid';
+    case 'invalid':
+      return `Key was rejected: ${validation.message}`;
+    case 'network-error':
+      return `Could not check key: ${validation.message}`;
+    case 'rate-limited':
+      return 'Key is valid but rate limited';
+  }
 };
 
+interface LockStatus {
+  encrypted: boolean;
+  unlocked: boolean;
+}
+
+const isCredentialError = (err: unknown): err is CredentialError =>
+  typeof err === 'object' && err !== null && 'kind' in err && 'message' in err;
+
+const errorMessage = (err: unknown): string =>
+  isCredentialError(err) ? err.message : String(err);
+
+const secretBackendLabels: Record<SecretBackend, string> = {
+  'json-file': 'Plain file (~/.passepartout.json)',
+  'secret-service': 'System keyring (Secret Service)',
+  'encrypted-file': 'Encrypted file',
+};
+
+// An LLM provider from the backend's provider registry
+interface ProviderInfo {
+  id: string;
+  display_name: string;
+  env_vars: string[];
+  key_hint?: string;
+  base_url?: string;
+  openai_compatible: boolean;
+  models?: { id: string; display_name?: string }[];
+}
+
+// Tokens and cost (in US dollars) spent by opencode
+interface Usage {
+  input_tokens: number;
+  output_tokens: number;
+  reasoning_tokens: number;
+  cache_read_tokens: number;
+  cache_write_tokens: number;
+  cost: number;
+}
+
+interface SessionInfo {
+  id: string;
+  opencode_session_id: string | null;
+  title: string;
+  provider_id: string;
+  model_id: string;
+  created_at: number;
+  usage?: Usage;
+  dry_run?: boolean;
+  attachments?: Attachment[];
+}
+
+// A file copied into the session's workspace for the agent
+interface Attachment {
+  path: string;
+  source: string;
+  size: number;
+  hash: string;
+  attached_at: number;
+  sent: boolean;
+}
+
+// A file the agent wrote in the session's workspace
+interface Artifact {
+  path: string;
+  tool: string;
+  kind: 'text' | 'image' | 'binary';
+  size: number;
+}
+
+type ArtifactPreview =
+  | { type: 'text'; content: string; truncated: boolean }
+  | { type: 'image'; mime: string; data: string }
+  | { type: 'unsupported'; size: number };
+
+interface ChatMessage {
+  sender: 'user' | 'bot';
+  text: string;
+  timestamp: number;
+  execution_log?: StatusUpdate[];
+  usage?: Usage;
+  artifacts?: Artifact[];
+}
+
+// Returned by send_message
+interface MessageResponse {
+  text: string;
+  usage: Usage;
+  session_usage: Usage;
+  artifacts: Artifact[];
+}
+
+interface SessionHistory {
+  session: SessionInfo;
+  messages: ChatMessage[];
+}
+
+interface SessionListing {
+  sessions: SessionInfo[];
+  active_session_id: string | null;
+}
+
 type StatusUpdate = {
-  type: 'idle' | 'busy' | 'tool' | 'tool-completed' | 'tool-error' | 'reasoning' | 'generating' | 'retry';
+  sessionId?: string;
+  type: 'idle' | 'busy' | 'tool' | 'tool-completed' | 'tool-error' | 'reasoning' | 'generating' | 'retry' | 'error' | 'budget-exceeded' | 'permission' | 'cancelled';
   message?: string; // Truncated message for the status bubble
   details?: {
     fullMessage?: string; // Full message for the execution log
@@ -39,6 +153,44 @@ type StatusUpdate = {
   };
 };
 
+type TextDelta = {
+  sessionId?: string;
+  messageId: string;
+  partId: string;
+  delta: string; // Text appended since the previous delta, or the whole part if reset
+  reset: boolean;
+};
+
+// A tool call waiting for the user's approval
+type PermissionRequest = {
+  sessionId?: string;
+  id: string;
+  permission: string; // e.g. "bash" or "edit"
+  title: string; // What the tool wants to do, e.g. the command line
+  patterns: string[]; // What "Always allow" would allow from now on
+};
+
+// A latchkey request held back in dry run mode
+interface ProposedRequest {
+  sessionId: string;
+  id: string;
+  method: string;
+  url: string | null;
+  body: string | null;
+  args: string[];
+  created_at: number;
+}
+
+interface ProposedRequestResult {
+  success: boolean;
+  output: string;
+}
+
+interface StreamedPart {
+  key: string;
+  text: string;
+}
+
 interface ExecutionLogEntry {
   id: number;
   type: StatusUpdate['type'];
@@ -55,6 +207,8 @@ interface Message {
   text: string;
   sender: 'user' | 'bot';
   executionLog?: ExecutionLogEntry[];
+  usage?: Usage;
+  artifacts?: Artifact[];
 }
 
 type ModelOption = {
@@ -63,35 +217,101 @@ type ModelOption = {
   displayName: string;
 };
 
-// Browser check state
-type BrowserCheckStatus = 'loading' | 'success' | 'error';
+// Browser check state, as reported by ensure_browser and browser_status
+type BrowserState = 'unknown' | 'checking' | 'ready' | 'failed' | 'cancelled';
 
-interface BrowserCheckResult {
-  success: boolean;
+interface BrowserStatus {
+  state: BrowserState;
+  percent: number | null;
   output: string;
+  error: string | null;
+}
+
+// A line of latchkey ensure-browser output, sent while it runs
+interface BrowserProgress {
+  stream: 'stdout' | 'stderr';
+  line: string;
+  percent: number | null;
+}
+
+const browserStatusLabel = (status: BrowserStatus): string => {
+  switch (status.state) {
+    case 'ready':
+      return 'Browser is ready';
+    case 'failed':
+      return 'Browser check failed';
+    case 'cancelled':
+      return 'Browser check cancelled';
+    default:
+      return status.percent === null
+        ? 'Checking browser...'
+        : `Downloading browser... ${Math.round(status.percent)}%`;
+  }
+};
+
+// A model offered by the backend's list_models command
+interface ModelInfo {
+  provider_id: string;
+  model_id: string;
+  display_name: string;
+  provider_name: string;
 }
 
-// Available models configuration
-const availableModels: ModelOption[] = [
-  // Anthropic models
-  { providerID: 'anthropic', modelID: 'claude-opus-4-5-20251101', displayName: 'Claude Opus 4.5' },
-  { providerID: 'anthropic', modelID: 'claude-sonnet-4-5-20250929', displayName: 'Claude Sonnet 4.5' },
-  { providerID: 'anthropic', modelID: 'claude-haiku-4-5-20251017', displayName: 'Claude Haiku 4.5' },
-  // OpenAI models
-  { providerID: 'openai', modelID: 'o3', displayName: 'OpenAI o3' },
-  { providerID: 'openai', modelID: 'o4-mini', displayName: 'OpenAI o4-mini' },
-  { providerID: 'openai', modelID: 'gpt-4.1', displayName: 'GPT-4.1' },
-  { providerID: 'openai', modelID: 'gpt-4.1-mini', displayName: 'GPT-4.1 Mini' },
-  // Google models
-  { providerID: 'google', modelID: 'gemini-3-pro', displayName: 'Gemini 3 Pro' },
-  { providerID: 'google', modelID: 'gemini-3-flash', displayName: 'Gemini 3 Flash' },
-];
-
-// Default model
-const defaultModel = availableModels[0];
-
-// Monotonically increasing counter for unique IDs
+// Used until the model list has been loaded
+const defaultModel: ModelOption = {
+  providerID: 'anthropic',
+  modelID: 'claude-sonnet-4-5',
+  displayName: 'claude-sonnet-4-5',
+};
+
+// Monotonically increasing counters for unique IDs
 let nextLogEntryId = 0;
+let nextLoadedMessageId = 0;
+
+// Convert a status update into an execution log entry, if it belongs in the log
+const toLogEntry = (status: StatusUpdate): ExecutionLogEntry | null => {
+  if (!status.message || !status.details?.timestamp) return null;
+  return {
+    id: nextLogEntryId++,
+    type: status.type,
+    // Use fullMessage for the log if available, otherwise fall back to message
+    message: status.details.fullMessage || status.message,
+    timestamp: status.details.timestamp,
+    toolName: status.details.toolName,
+    duration: status.details.duration,
+    output: status.details.output,
+    error: status.details.error,
+  };
+};
+
+// Summarize a message's usage, e.g. "1,204 in · 87 out · 11,520 cached · $0.0061"
+const formatUsage = (usage: Usage): string => {
+  const parts = [
+    `${usage.input_tokens.toLocaleString()} in`,
+    `${usage.output_tokens.toLocaleString()} out`,
+  ];
+  if (usage.cache_read_tokens > 0) {
+    parts.push(`${usage.cache_read_tokens.toLocaleString()} cached`);
+  }
+  parts.push(`$${usage.cost.toFixed(4)}`);
+  return parts.join(' · ');
+};
+
+// Convert a persisted message into the shape rendered by the chat view
+const toMessage = (message: ChatMessage): Message => {
+  const executionLog = (message.execution_log ?? [])
+    .map(toLogEntry)
+    .filter((entry): entry is ExecutionLogEntry => entry !== null);
+  return {
+    // Negative IDs never collide with the Date.now() IDs of new messages
+    id: -2 - nextLoadedMessageId++,
+    text: message.text,
+    sender: message.sender,
+    executionLog: executionLog.length > 0 ? executionLog : undefined,
+    usage: message.usage,
+    artifacts: message.artifacts,
+  };
+};
 
 // CredentialsPanel component for managing API keys
 interface CredentialsPanelProps {
@@ -105,10 +325,24 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
   const [apiKeyInput, setApiKeyInput] = useState('');
   const [error, setError] = useState<string | null>(null);
   const [isSaving, setIsSaving] = useState(false);
+  const [secretBackend, setSecretBackend] = useState<SecretBackend | null>(null);
+  const [needsPassphrase, setNeedsPassphrase] = useState(false);
+  const [passphraseInput, setPassphraseInput] = useState('');
+  const [providers, setProviders] = useState<ProviderInfo[]>([]);
+  const [validationNotes, setValidationNotes] = useState<Record<string, string>>({});
 
   // Load credentials on mount
   useEffect(() => {
     loadCredentials();
+    invoke<ProviderInfo[]>('list_providers')
+      .then(setProviders)
+      .catch((err) => console.error('[Credentials] Failed to list providers:', err));
+    invoke<SecretBackend>('get_secret_backend')
+      .then(setSecretBackend)
+      .catch((err) => console.error('[Credentials] Failed to get secret backend:', err));
+    invoke<LockStatus>('credentials_lock_status')
+      .then((status) => setNeedsPassphrase(status.encrypted && !status.unlocked))
+      .catch((err) => console.error('[Credentials] Failed to get lock status:', err));
   }, []);
 
   // Handle escape key to close modal
@@ -131,8 +365,12 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
       console.log('[Credentials] Loaded:', result);
       setCredentials(result);
     } catch (err) {
+      if (isCredentialError(err) && err.kind === 'locked') {
+        setNeedsPassphrase(true);
+        return;
+      }
       console.error('[Credentials] Failed to load:', err);
-      setError(`Failed to load credentials: ${err}`);
+      setError(`Failed to load credentials: ${errorMessage(err)}`);
     } finally {
       setIsLoading(false);
     }
@@ -148,17 +386,21 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
       setIsSaving(true);
       setError(null);
       console.log('[Credentials] Saving credential for:', providerId);
-      await invoke('save_credential', {
+      const validation = await invoke<KeyValidation | null>('save_credential', {
         providerId,
         apiKey: apiKeyInput.trim(),
+        validate: true,
       });
-      console.log('[Credentials] Save successful');
+      console.log('[Credentials] Save successful:', validation);
+      if (validation) {
+        setValidationNotes((notes) => ({ ...notes, [providerId]: describeValidation(validation) }));
+      }
       setEditingProvider(null);
       setApiKeyInput('');
       await loadCredentials();
     } catch (err) {
       console.error('[Credentials] Failed to save:', err);
-      setError(`Failed to save credential: ${err}`);
+      setError(`Failed to save credential: ${errorMessage(err)}`);
     } finally {
       setIsSaving(false);
     }
@@ -173,7 +415,57 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
       await loadCredentials();
     } catch (err) {
       console.error('[Credentials] Failed to delete:', err);
-      setError(`Failed to delete credential: ${err}`);
+      setError(`Failed to delete credential: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleValidateCredential = async (providerId: string) => {
+    setValidationNotes((notes) => ({ ...notes, [providerId]: 'Checking...' }));
+    try {
+      const validation = await invoke<KeyValidation>('validate_credential', { providerId });
+      setValidationNotes((notes) => ({ ...notes, [providerId]: describeValidation(validation) }));
+    } catch (err) {
+      console.error('[Credentials] Failed to validate:', err);
+      setValidationNotes((notes) => ({ ...notes, [providerId]: errorMessage(err) }));
+    }
+  };
+
+  const handleBackendChange = async (backend: SecretBackend) => {
+    // Encrypting the keys needs a passphrase, which unlocking sets up
+    if (backend === 'encrypted-file') {
+      setError(null);
+      setNeedsPassphrase(true);
+      return;
+    }
+    try {
+      setError(null);
+      console.log('[Credentials] Switching secret backend to:', backend);
+      const moved = await invoke<number>('set_secret_backend', { backend });
+      console.log(`[Credentials] Moved ${moved} keys`);
+      setSecretBackend(backend);
+      await loadCredentials();
+    } catch (err) {
+      console.error('[Credentials] Failed to switch backend:', err);
+      setError(`Failed to switch storage: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleUnlock = async () => {
+    if (!passphraseInput) {
+      setError('Passphrase cannot be empty');
+      return;
+    }
+    try {
+      setError(null);
+      await invoke('unlock_credentials', { passphrase: passphraseInput });
+      console.log('[Credentials] Unlocked');
+      setNeedsPassphrase(false);
+      setPassphraseInput('');
+      setSecretBackend('encrypted-file');
+      await loadCredentials();
+    } catch (err) {
+      console.error('[Credentials] Failed to unlock:', err);
+      setError(`Failed to unlock: ${errorMessage(err)}`);
     }
   };
 
@@ -205,17 +497,57 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
           </button>
         </div>
         <p className="credentials-description">
-          Configure your API keys for each LLM provider. Keys are stored securely in your system keychain.
+          Configure your API keys for each LLM provider.
         </p>
+        {secretBackend && (
+          <label className="credentials-description">
+            Store keys in:{' '}
+            <select
+              value={secretBackend}
+              onChange={(e) => handleBackendChange(e.target.value as SecretBackend)}
+            >
+              {(Object.keys(secretBackendLabels) as SecretBackend[]).map((backend) => (
+                <option key={backend} value={backend}>
+                  {secretBackendLabels[backend]}
+                </option>
+              ))}
+            </select>
+          </label>
+        )}
         {error && <div className="credentials-error">{error}</div>}
-        {isLoading ? (
+        {needsPassphrase ? (
+          <div className="credential-edit">
+            <p className="credentials-description">
+              {secretBackend === 'encrypted-file'
+                ? 'Enter your passphrase to unlock your API keys.'
+                : 'Choose a passphrase to encrypt your API keys with.'}
+            </p>
+            <input
+              type="password"
+              value={passphraseInput}
+              onChange={(e) => setPassphraseInput(e.target.value)}
+              placeholder="Passphrase"
+              className="credential-input"
+              autoFocus
+              onKeyDown={(e) => {
+                if (e.key === 'Enter') handleUnlock();
+              }}
+            />
+            <div className="credential-actions">
+              <button className="save-button" onClick={handleUnlock} type="button">
+                Unlock
+              </button>
+            </div>
+          </div>
+        ) : isLoading ? (
           <div className="credentials-loading">Loading...</div>
         ) : (
           <div className="credentials-list">
             {credentials.map((cred) => {
-              const info = providerInfo[cred.provider_id] || {
-                displayName: cred.provider_id,
-                placeholder: 'Enter API key...',
+              const provider = providers.find((p) => p.id === cred.provider_id);
+              const info = {
+                displayName: provider?.display_name ?? cred.provider_id,
+                placeholder: provider?.key_hint ?? 'Enter API key...',
               };
               const isEditing = editingProvider === cred.provider_id;
 
@@ -227,6 +559,9 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
                       {cred.has_key ? 'Configured' : 'Not configured'}
                     </span>
                   </div>
+                  {validationNotes[cred.provider_id] && (
+                    <div className="credentials-description">{validationNotes[cred.provider_id]}</div>
+                  )}
                   {isEditing ? (
                     <div className="credential-edit">
                       <input
@@ -269,6 +604,15 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
                       >
                         {cred.has_key ? 'Update' : 'Add'}
                       </button>
+                      {cred.has_key && (
+                        <button
+                          className="edit-button"
+                          onClick={() => handleValidateCredential(cred.provider_id)}
+                          type="button"
+                        >
+                          Check
+                        </button>
+                      )}
                       {cred.has_key && (
                         <button
                           className="delete-button"
@@ -290,6 +634,477 @@ function CredentialsPanel({ onClose }: CredentialsPanelProps) {
   );
 }
 
+// A third-party service latchkey can log in to
+interface LatchkeyService {
+  name: string;
+  status: 'valid' | 'invalid' | 'missing' | { other: string } | { error: { kind: string; message: string } };
+}
+
+const serviceStatusLabel = (status: LatchkeyService['status']): string => {
+  switch (status) {
+    case 'valid': return 'Connected';
+    case 'invalid': return 'Expired';
+    case 'missing': return 'Not connected';
+    default: return 'other' in status ? status.other : `Unknown (${status.error.message})`;
+  }
+};
+
+// ServicesPanel component for managing latchkey logins
+interface ServicesPanelProps {
+  onClose: () => void;
+}
+
+function ServicesPanel({ onClose }: ServicesPanelProps) {
+  const [services, setServices] = useState<LatchkeyService[]>([]);
+  const [isLoading, setIsLoading] = useState(true);
+  const [error, setError] = useState<string | null>(null);
+  const [loggingIn, setLoggingIn] = useState<string | null>(null);
+
+  useEffect(() => {
+    invoke<LatchkeyService[]>('list_latchkey_services')
+      .then(setServices)
+      .catch((err) => setError(`Failed to load services: ${errorMessage(err)}`))
+      .finally(() => setIsLoading(false));
+  }, []);
+
+  // Handle escape key to close modal
+  useEffect(() => {
+    const handleEscape = (e: KeyboardEvent) => {
+      if (e.key === 'Escape') {
+        onClose();
+      }
+    };
+    document.addEventListener('keydown', handleEscape);
+    return () => document.removeEventListener('keydown', handleEscape);
+  }, [onClose]);
+
+  const handleClear = async (name: string) => {
+    try {
+      setError(null);
+      const service = await invoke<LatchkeyService>('clear_latchkey_service', { service: name });
+      setServices((prev) => prev.map((s) => (s.name === name ? service : s)));
+    } catch (err) {
+      console.error('[Services] Failed to clear:', err);
+      setError(`Failed to disconnect ${name}: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleLogin = async (name: string) => {
+    try {
+      setError(null);
+      setLoggingIn(name);
+      const service = await invoke<LatchkeyService>('login_latchkey_service', { service: name });
+      setServices((prev) => prev.map((s) => (s.name === name ? service : s)));
+    } catch (err) {
+      console.error('[Services] Failed to log in:', err);
+      setError(`Failed to connect ${name}: ${errorMessage(err)}`);
+    } finally {
+      setLoggingIn(null);
+    }
+  };
+
+  return (
+    <div className="modal-overlay" onClick={(e) => e.target === e.currentTarget && onClose()}>
+      <div className="credentials-modal">
+        <div className="credentials-header">
+          <h2>Services</h2>
+          <button className="close-button" onClick={onClose} type="button">
+            &times;
+          </button>
+        </div>
+        <p className="credentials-description">
+          Services the assistant can use on your behalf. You're asked to log in the first time it uses one.
+        </p>
+        {error && <div className="credentials-error">{error}</div>}
+        {isLoading ? (
+          <div className="credentials-loading">Loading...</div>
+        ) : (
+          <div className="credentials-list">
+            {services.map((service) => (
+              <div key={service.name} className="credential-item">
+                <div className="credential-info">
+                  <span className="credential-name">{service.name}</span>
+                  <span className={`credential-status ${service.status === 'valid' ? 'has-key' : 'no-key'}`}>
+                    {serviceStatusLabel(service.status)}
+                  </span>
+                </div>
+                <div className="credential-actions">
+                  {service.status !== 'valid' && (
+                    <button
+                      className="edit-button"
+                      onClick={() => handleLogin(service.name)}
+                      disabled={loggingIn !== null}
+                      type="button"
+                    >
+                      {loggingIn === service.name ? 'Waiting for login...' : 'Connect'}
+                    </button>
+                  )}
+                  {service.status !== 'missing' && (
+                    <button className="delete-button" onClick={() => handleClear(service.name)} type="button">
+                      Disconnect
+                    </button>
+                  )}
+                </div>
+              </div>
+            ))}
+          </div>
+        )}
+      </div>
+    </div>
+  );
+}
+
+// A file in a session's workspace
+interface WorkspaceFile {
+  path: string;
+  size: number;
+  modified: number | null;
+}
+
+// Workspace contents before a message, which can be restored
+interface SnapshotInfo {
+  id: string;
+  created_at: number;
+  summary: string;
+  file_count: number;
+  size: number;
+}
+
+interface FileChange {
+  path: string;
+  change: 'added' | 'modified' | 'deleted';
+  diff?: string;
+}
+
+interface WorkspaceSettings {
+  root?: string | null;
+  mode: 'per-session' | 'shared';
+}
+
+const formatFileSize = (bytes: number): string => {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+};
+
+// WorkspacePanel component for the files the assistant works on
+interface WorkspacePanelProps {
+  sessionId: string;
+  onClose: () => void;
+}
+
+function WorkspacePanel({ sessionId, onClose }: WorkspacePanelProps) {
+  const [files, setFiles] = useState<WorkspaceFile[]>([]);
+  const [settings, setSettings] = useState<WorkspaceSettings>({ mode: 'per-session' });
+  const [isLoading, setIsLoading] = useState(true);
+  const [error, setError] = useState<string | null>(null);
+  const [notice, setNotice] = useState<string | null>(null);
+  const [snapshots, setSnapshots] = useState<SnapshotInfo[]>([]);
+  const [shownChanges, setShownChanges] = useState<{ snapshotId: string; changes: FileChange[] } | null>(null);
+
+  const loadFiles = useCallback(() => {
+    invoke<WorkspaceFile[]>('list_workspace', { sessionId })
+      .then(setFiles)
+      .catch((err) => setError(`Failed to load workspace: ${errorMessage(err)}`))
+      .finally(() => setIsLoading(false));
+    invoke<SnapshotInfo[]>('list_snapshots', { sessionId })
+      .then(setSnapshots)
+      .catch((err) => setError(`Failed to load workspace history: ${errorMessage(err)}`));
+  }, [sessionId]);
+
+  useEffect(() => {
+    loadFiles();
+    invoke<WorkspaceSettings>('get_workspace_settings')
+      .then(setSettings)
+      .catch((err) => setError(`Failed to load workspace settings: ${errorMessage(err)}`));
+  }, [loadFiles]);
+
+  // Handle escape key to close modal
+  useEffect(() => {
+    const handleEscape = (e: KeyboardEvent) => {
+      if (e.key === 'Escape') {
+        onClose();
+      }
+    };
+    document.addEventListener('keydown', handleEscape);
+    return () => document.removeEventListener('keydown', handleEscape);
+  }, [onClose]);
+
+  const handleOpen = async () => {
+    try {
+      setError(null);
+      await invoke<string>('open_workspace', { sessionId });
+    } catch (err) {
+      setError(`Failed to open workspace: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleReset = async () => {
+    const scope = settings.mode === 'shared' ? 'the workspace shared by all conversations' : "this conversation's workspace";
+    if (!window.confirm(`Delete all files in ${scope}, including its history, and start over?`)) return;
+    try {
+      setError(null);
+      await invoke('reset_workspace', { sessionId });
+      loadFiles();
+    } catch (err) {
+      setError(`Failed to reset workspace: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleShowChanges = async (snapshot: SnapshotInfo) => {
+    if (shownChanges?.snapshotId === snapshot.id) {
+      setShownChanges(null);
+      return;
+    }
+    try {
+      setError(null);
+      const changes = await invoke<FileChange[]>('diff_snapshot', { sessionId, snapshotId: snapshot.id });
+      setShownChanges({ snapshotId: snapshot.id, changes });
+    } catch (err) {
+      setError(`Failed to compare with snapshot: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleRestore = async (snapshot: SnapshotInfo) => {
+    const time = new Date(snapshot.created_at).toLocaleString();
+    if (!window.confirm(`Undo all changes to the workspace since ${time}? The current files are kept in the history.`)) return;
+    try {
+      setError(null);
+      const reverted = await invoke<FileChange[]>('restore_snapshot', { sessionId, snapshotId: snapshot.id });
+      setShownChanges(null);
+      setNotice(reverted.length === 0 ? 'Nothing changed since then.' : `Reverted ${reverted.length} file changes.`);
+      loadFiles();
+    } catch (err) {
+      setError(`Failed to restore snapshot: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleSaveSettings = async () => {
+    try {
+      setError(null);
+      const root = settings.root?.trim();
+      await invoke('set_workspace_settings', { settings: { ...settings, root: root || null } });
+      setNotice('Saved. The new settings apply the next time the app starts.');
+    } catch (err) {
+      setError(`Failed to save workspace settings: ${errorMessage(err)}`);
+    }
+  };
+
+  return (
+    <div className="modal-overlay" onClick={(e) => e.target === e.currentTarget && onClose()}>
+      <div className="credentials-modal">
+        <div className="credentials-header">
+          <h2>Workspace</h2>
+          <button className="close-button" onClick={onClose} type="button">
+            &times;
+          </button>
+        </div>
+        <p className="credentials-description">
+          Files the assistant creates are kept here between launches.
+        </p>
+        {error && <div className="credentials-error">{error}</div>}
+        <div className="workspace-actions">
+          <button className="edit-button" onClick={handleOpen} type="button">
+            Open folder
+          </button>
+          <button className="delete-button" onClick={handleReset} type="button">
+            Reset
+          </button>
+        </div>
+        {isLoading ? (
+          <div className="credentials-loading">Loading...</div>
+        ) : (
+          <div className="workspace-files">
+            {files.length === 0 && <div className="credentials-loading">No files yet</div>}
+            {files.map((file) => (
+              <div key={file.path} className="workspace-file">
+                <span className="workspace-file-path">{file.path}</span>
+                <span className="workspace-file-size">{formatFileSize(file.size)}</span>
+              </div>
+            ))}
+          </div>
+        )}
+        {snapshots.length > 0 && (
+          <div className="workspace-history">
+            <h3>History</h3>
+            {snapshots.map((snapshot) => (
+              <div key={snapshot.id} className="workspace-snapshot">
+                <div className="workspace-snapshot-info">
+                  <span className="workspace-snapshot-time">{new Date(snapshot.created_at).toLocaleString()}</span>
+                  <span className="workspace-snapshot-summary">Before: {snapshot.summary || '(empty message)'}</span>
+                  <button className="artifact-button" onClick={() => handleShowChanges(snapshot)} type="button">
+                    {shownChanges?.snapshotId === snapshot.id ? 'Hide changes' : 'Changes since'}
+                  </button>
+                  <button className="artifact-button" onClick={() => handleRestore(snapshot)} type="button">
+                    Restore
+                  </button>
+                </div>
+                {shownChanges?.snapshotId === snapshot.id && (
+                  <div className="workspace-changes">
+                    {shownChanges.changes.length === 0 && <div className="credentials-loading">No changes</div>}
+                    {shownChanges.changes.map((change) => (
+                      <div key={change.path} className="workspace-change">
+                        <span className={`workspace-change-kind ${change.change}`}>{change.change}</span>{' '}
+                        <span className="workspace-file-path">{change.path}</span>
+                        {change.diff && <pre className="workspace-diff">{change.diff}</pre>}
+                      </div>
+                    ))}
+                  </div>
+                )}
+              </div>
+            ))}
+          </div>
+        )}
+        <div className="workspace-settings">
+          <input
+            className="credential-input"
+            type="text"
+            value={settings.root ?? ''}
+            onChange={(e) => setSettings((prev) => ({ ...prev, root: e.target.value }))}
+            placeholder="Folder for workspaces (default: app data folder)"
+          />
+          <label className="dry-run-toggle">
+            <input
+              type="checkbox"
+              checked={settings.mode === 'shared'}
+              onChange={(e) => setSettings((prev) => ({ ...prev, mode: e.target.checked ? 'shared' : 'per-session' }))}
+            />
+            Share one workspace between conversations
+          </label>
+          <div className="credential-actions">
+            <button className="save-button" onClick={handleSaveSettings} type="button">
+              Save
+            </button>
+          </div>
+          {notice && <div className="workspace-notice">{notice}</div>}
+        </div>
+      </div>
+    </div>
+  );
+}
+
+// ArtifactList component for the files the agent wrote while answering a message
+interface ArtifactListProps {
+  sessionId: string;
+  artifacts: Artifact[];
+  onPreview: (artifact: Artifact) => void;
+}
+
+function ArtifactList({ sessionId, artifacts, onPreview }: ArtifactListProps) {
+  const [error, setError] = useState<string | null>(null);
+  const [notice, setNotice] = useState<string | null>(null);
+
+  const handleReveal = async (artifact: Artifact) => {
+    try {
+      setError(null);
+      await invoke('reveal_artifact', { sessionId, path: artifact.path });
+    } catch (err) {
+      setError(`Failed to show ${artifact.path}: ${errorMessage(err)}`);
+    }
+  };
+
+  const handleExport = async (artifact: Artifact) => {
+    const destination = window.prompt(`Export ${artifact.path} to (file or folder path):`);
+    if (!destination?.trim()) return;
+    try {
+      setError(null);
+      const target = await invoke<string>('export_artifact', {
+        sessionId,
+        path: artifact.path,
+        destination: destination.trim(),
+      });
+      setNotice(`Exported to ${target}`);
+    } catch (err) {
+      setNotice(null);
+      setError(`Failed to export ${artifact.path}: ${errorMessage(err)}`);
+    }
+  };
+
+  return (
+    <div className="artifact-list">
+      {artifacts.map((artifact) => (
+        <div key={artifact.path} className="artifact-item">
+          <span className="artifact-path">{artifact.kind === 'image' ? '🖼️' : '📄'} {artifact.path}</span>
+          <span className="workspace-file-size">{formatFileSize(artifact.size)}</span>
+          {artifact.kind !== 'binary' && (
+            <button className="artifact-button" onClick={() => onPreview(artifact)} type="button">
+              Preview
+            </button>
+          )}
+          <button className="artifact-button" onClick={() => handleReveal(artifact)} type="button">
+            Show in folder
+          </button>
+          <button className="artifact-button" onClick={() => handleExport(artifact)} type="button">
+            Export
+          </button>
+        </div>
+      ))}
+      {error && <div className="attachment-error">{error}</div>}
+      {notice && <div className="workspace-notice">{notice}</div>}
+    </div>
+  );
+}
+
+// ArtifactPreviewPanel component showing a text or image file the agent wrote
+interface ArtifactPreviewPanelProps {
+  sessionId: string;
+  artifact: Artifact;
+  onClose: () => void;
+}
+
+function ArtifactPreviewPanel({ sessionId, artifact, onClose }: ArtifactPreviewPanelProps) {
+  const [preview, setPreview] = useState<ArtifactPreview | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    setPreview(null);
+    setError(null);
+    invoke<ArtifactPreview>('preview_artifact', { sessionId, path: artifact.path })
+      .then(setPreview)
+      .catch((err) => setError(`Failed to load ${artifact.path}: ${errorMessage(err)}`));
+  }, [sessionId, artifact.path]);
+
+  // Handle escape key to close modal
+  useEffect(() => {
+    const handleEscape = (e: KeyboardEvent) => {
+      if (e.key === 'Escape') {
+        onClose();
+      }
+    };
+    document.addEventListener('keydown', handleEscape);
+    return () => document.removeEventListener('keydown', handleEscape);
+  }, [onClose]);
+
+  return (
+    <div className="modal-overlay" onClick={(e) => e.target === e.currentTarget && onClose()}>
+      <div className="credentials-modal artifact-preview-modal">
+        <div className="credentials-header">
+          <h2>{artifact.path}</h2>
+          <button className="close-button" onClick={onClose} type="button">
+            &times;
+          </button>
+        </div>
+        {error && <div className="credentials-error">{error}</div>}
+        {!preview && !error && <div className="credentials-loading">Loading...</div>}
+        {preview?.type === 'text' && (
+          <>
+            <pre className="artifact-preview-text">{preview.content}</pre>
+            {preview.truncated && <div className="credentials-loading">Only the beginning of the file is shown.</div>}
+          </>
+        )}
+        {preview?.type === 'image' && (
+          <img className="artifact-preview-image" src={`data:${preview.mime};base64,${preview.data}`} alt={artifact.path} />
+        )}
+        {preview?.type === 'unsupported' && (
+          <div className="credentials-loading">
+            This file can't be previewed ({formatFileSize(preview.size)}). Use Show in folder or Export instead.
+          </div>
+        )}
+      </div>
+    </div>
+  );
+}
+
 // ExecutionLog component with smart auto-scroll
 interface ExecutionLogProps {
   log: ExecutionLogEntry[];
@@ -362,25 +1177,202 @@ function ExecutionLog({ log, isExpanded, onToggle, formatTime, formatDuration, g
 }
 
 function App() {
-  const [messages, setMessages] = useState<Message[]>([]);
+  const [sessions, setSessions] = useState<SessionInfo[]>([]);
+  const [currentSessionId, setCurrentSessionId] = useState<string | null>(null);
+  const [messagesBySession, setMessagesBySession] = useState<Record<string, Message[]>>({});
   const [inputValue, setInputValue] = useState('');
   const [isLoading, setIsLoading] = useState(false);
   const [statusMessage, setStatusMessage] = useState<string>('');
   const [executionLog, setExecutionLog] = useState<ExecutionLogEntry[]>([]);
+  const [streamedParts, setStreamedParts] = useState<StreamedPart[]>([]);
+  const [pendingPermissions, setPendingPermissions] = useState<PermissionRequest[]>([]);
+  const [proposedRequests, setProposedRequests] = useState<ProposedRequest[]>([]);
+  const [proposalResults, setProposalResults] = useState<Record<string, ProposedRequestResult>>({});
   const [expandedLogs, setExpandedLogs] = useState<Set<number>>(new Set());
   const [selectedModel, setSelectedModel] = useState<ModelOption>(defaultModel);
+  const [models, setModels] = useState<ModelOption[]>([defaultModel]);
   const [showCredentials, setShowCredentials] = useState(false);
-  const [browserCheckStatus, setBrowserCheckStatus] = useState<BrowserCheckStatus>('loading');
-  const [browserCheckOutput, setBrowserCheckOutput] = useState<string>('');
+  const [showServices, setShowServices] = useState(false);
+  const [showWorkspace, setShowWorkspace] = useState(false);
+  const [previewedArtifact, setPreviewedArtifact] = useState<Artifact | null>(null);
+  const [attachmentError, setAttachmentError] = useState<string | null>(null);
+  const [browserStatus, setBrowserStatus] = useState<BrowserStatus>({
+    state: 'unknown',
+    percent: null,
+    output: '',
+    error: null,
+  });
   const [showBrowserPopup, setShowBrowserPopup] = useState(false);
   const messagesEndRef = useRef<HTMLDivElement>(null);
   const executionLogRef = useRef<ExecutionLogEntry[]>([]);
+  const currentSessionIdRef = useRef<string | null>(null);
+
+  const messages = currentSessionId ? messagesBySession[currentSessionId] ?? [] : [];
+  const currentSession = sessions.find((session) => session.id === currentSessionId);
+  // Sent to the agent with the next message
+  const pendingAttachments = currentSession?.attachments?.filter((attachment) => !attachment.sent) ?? [];
+
+  // Ask the backend which models are available for the configured providers
+  const loadModels = useCallback(async (refresh: boolean) => {
+    try {
+      const result = await invoke<ModelInfo[]>('list_models', { refresh });
+      const options = result.map((model) => ({
+        providerID: model.provider_id,
+        modelID: model.model_id,
+        displayName: `${model.display_name} (${model.provider_name})`,
+      }));
+      setModels(options);
+      setSelectedModel((current) =>
+        options.find((m) => m.providerID === current.providerID && m.modelID === current.modelID) ??
+        options[0] ??
+        current
+      );
+    } catch (err) {
+      console.error('[App] Failed to list models:', err);
+    }
+  }, []);
 
-  // Keep ref in sync with state for use in async handlers
+  // Keep refs in sync with state for use in async handlers
   useEffect(() => {
     executionLogRef.current = executionLog;
   }, [executionLog]);
 
+  useEffect(() => {
+    currentSessionIdRef.current = currentSessionId;
+  }, [currentSessionId]);
+
+  const appendMessage = (sessionId: string, message: Message) => {
+    setMessagesBySession((prev) => ({
+      ...prev,
+      [sessionId]: [...(prev[sessionId] ?? []), message],
+    }));
+  };
+
+  const loadSessions = async () => {
+    try {
+      const listing = await invoke<SessionListing>('list_sessions');
+      setSessions(listing.sessions);
+      const activeId = listing.active_session_id;
+      if (activeId && activeId !== currentSessionIdRef.current) {
+        await loadHistory(activeId);
+      }
+      setCurrentSessionId(activeId);
+    } catch (err) {
+      console.error('[Sessions] Failed to load:', err);
+    }
+  };
+
+  const handleNewSession = async () => {
+    try {
+      const session = await invoke<SessionInfo>('create_session', {
+        providerId: selectedModel.providerID,
+        modelId: selectedModel.modelID,
+      });
+      console.log('[Sessions] Created:', session.id);
+      await loadSessions();
+      return session.id;
+    } catch (err) {
+      console.error('[Sessions] Failed to create:', err);
+      return null;
+    }
+  };
+
+  const loadHistory = async (sessionId: string) => {
+    const history = await invoke<SessionHistory>('load_session', { sessionId });
+    setMessagesBySession((prev) => ({
+      ...prev,
+      [sessionId]: history.messages.map(toMessage),
+    }));
+    setProposedRequests(await invoke<ProposedRequest[]>('list_proposed_requests', { sessionId }));
+    setProposalResults({});
+  };
+
+  const attachFiles = async (paths: string[]) => {
+    const sessionId = currentSessionIdRef.current;
+    if (!sessionId || paths.length === 0) return;
+    try {
+      setAttachmentError(null);
+      await invoke<Attachment[]>('attach_files', { sessionId, paths });
+    } catch (err) {
+      console.error('[Attachments] Failed to attach files:', err);
+      setAttachmentError(errorMessage(err));
+    }
+    // Files copied before a failure are attached too
+    loadSessions();
+  };
+
+  // Files dropped on the window are attached to the current session
+  const attachFilesRef = useRef(attachFiles);
+  attachFilesRef.current = attachFiles;
+  useEffect(() => {
+    const unlisten = getCurrentWebview().onDragDropEvent((event) => {
+      if (event.payload.type === 'drop') {
+        attachFilesRef.current(event.payload.paths);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const handleDryRunChange = async (enabled: boolean) => {
+    if (!currentSessionId) return;
+    try {
+      const session = await invoke<SessionInfo>('set_dry_run', { sessionId: currentSessionId, enabled });
+      setSessions((prev) => prev.map((s) => (s.id === session.id ? session : s)));
+    } catch (err) {
+      console.error('[DryRun] Failed to switch dry run mode:', err);
+    }
+  };
+
+  const handleSendProposal = async (request: ProposedRequest) => {
+    try {
+      const result = await invoke<ProposedRequestResult>('send_proposed_request', {
+        sessionId: request.sessionId,
+        requestId: request.id,
+      });
+      setProposalResults((prev) => ({ ...prev, [request.id]: result }));
+    } catch (err) {
+      setProposalResults((prev) => ({ ...prev, [request.id]: { success: false, output: errorMessage(err) } }));
+    }
+  };
+
+  const handleDiscardProposal = async (request: ProposedRequest) => {
+    try {
+      await invoke('discard_proposed_request', { sessionId: request.sessionId, requestId: request.id });
+      setProposedRequests((prev) => prev.filter((p) => p.id !== request.id));
+    } catch (err) {
+      console.error('[DryRun] Failed to discard request:', err);
+    }
+  };
+
+  const handleSwitchSession = async (sessionId: string) => {
+    try {
+      await invoke<SessionInfo>('switch_session', { sessionId });
+      await loadHistory(sessionId);
+      setCurrentSessionId(sessionId);
+      setExecutionLog([]);
+      setPendingPermissions([]);
+    } catch (err) {
+      console.error('[Sessions] Failed to switch:', err);
+    }
+  };
+
+  const handleDeleteSession = async () => {
+    if (!currentSessionId) return;
+    try {
+      await invoke('delete_session', { sessionId: currentSessionId });
+      setMessagesBySession((prev) => {
+        const next = { ...prev };
+        delete next[currentSessionId];
+        return next;
+      });
+      await loadSessions();
+    } catch (err) {
+      console.error('[Sessions] Failed to delete:', err);
+    }
+  };
+
   const scrollToBottom = () => {
     messagesEndRef.current?.scrollIntoView({ behavior: 'smooth' });
   };
@@ -401,6 +1393,10 @@ function App() {
       case 'reasoning': return '💭';
       case 'generating': return '✍️';
       case 'retry': return '🔄';
+      case 'error': return '⚠️';
+      case 'budget-exceeded': return '💸';
+      case 'permission': return '🔐';
+      case 'cancelled': return '⏹';
       default: return '•';
     }
   };
@@ -428,23 +1424,109 @@ function App() {
     scrollToBottom();
   }, [messages]);
 
-  // Run browser check on app startup
+  const runBrowserCheck = useCallback(async () => {
+    setBrowserStatus({ state: 'checking', percent: null, output: '', error: null });
+    try {
+      console.log('[Browser] Starting browser check...');
+      const status = await invoke<BrowserStatus>('ensure_browser');
+      console.log('[Browser] Check completed:', status);
+      setBrowserStatus(status);
+    } catch (err) {
+      console.error('[Browser] Check failed:', err);
+      setBrowserStatus((prev) => ({
+        ...prev,
+        state: 'failed',
+        error: `Failed to check browser: ${errorMessage(err)}`,
+      }));
+    }
+  }, []);
+
+  const handleCancelBrowserCheck = async () => {
+    try {
+      await invoke<boolean>('cancel_browser_check');
+    } catch (err) {
+      console.error('[Browser] Failed to cancel check:', err);
+    }
+  };
+
+  // Run browser check on app startup, unless one is still running from before a reload
   useEffect(() => {
     const checkBrowser = async () => {
       try {
-        console.log('[Browser] Starting browser check...');
-        const result = await invoke<BrowserCheckResult>('ensure_browser');
-        console.log('[Browser] Check completed:', result);
-        setBrowserCheckOutput(result.output);
-        setBrowserCheckStatus(result.success ? 'success' : 'error');
+        const status = await invoke<BrowserStatus>('browser_status');
+        if (status.state === 'checking') {
+          setBrowserStatus(status);
+          return;
+        }
       } catch (err) {
-        console.error('[Browser] Check failed:', err);
-        setBrowserCheckOutput(`Failed to check browser: ${err}`);
-        setBrowserCheckStatus('error');
+        console.error('[Browser] Failed to get browser status:', err);
       }
+      runBrowserCheck();
     };
 
     checkBrowser();
+  }, [runBrowserCheck]);
+
+  // Follow the download while the browser is being set up
+  useEffect(() => {
+    let unlistenProgress: UnlistenFn | null = null;
+    let unlistenStatus: UnlistenFn | null = null;
+
+    const setupListener = async () => {
+      unlistenProgress = await listen<BrowserProgress>('browser:progress', (event) => {
+        const progress = event.payload;
+        setBrowserStatus((prev) => ({
+          ...prev,
+          percent: progress.percent ?? prev.percent,
+          output: prev.output ? `${prev.output}\n${progress.line}` : progress.line,
+        }));
+      });
+      unlistenStatus = await listen<BrowserStatus>('browser:status', (event) => {
+        setBrowserStatus(event.payload);
+      });
+    };
+
+    setupListener();
+
+    return () => {
+      if (unlistenProgress) {
+        unlistenProgress();
+      }
+      if (unlistenStatus) {
+        unlistenStatus();
+      }
+    };
+  }, []);
+
+  // Restore past conversations on startup. The backend may still be initializing, so retry briefly.
+  useEffect(() => {
+    let cancelled = false;
+
+    const restoreSessions = async (attempt: number) => {
+      try {
+        const listing = await invoke<SessionListing>('list_sessions');
+        if (cancelled) return;
+        setSessions(listing.sessions);
+        if (listing.active_session_id) {
+          await loadHistory(listing.active_session_id);
+          setCurrentSessionId(listing.active_session_id);
+        }
+        // The backend is ready now
+        await loadModels(false);
+      } catch (err) {
+        if (attempt < 10 && !cancelled) {
+          setTimeout(() => restoreSessions(attempt + 1), 500);
+        } else {
+          console.error('[Sessions] Failed to restore:', err);
+        }
+      }
+    };
+
+    restoreSessions(0);
+
+    return () => {
+      cancelled = true;
+    };
   }, []);
 
   // Subscribe to status updates from the Tauri backend
@@ -454,7 +1536,8 @@ function App() {
     const setupListener = async () => {
       unlisten = await listen<StatusUpdate>('chat:statusUpdate', (event) => {
         const status = event.payload;
-        if (status.type === 'idle') {
+        if (status.sessionId && status.sessionId !== currentSessionIdRef.current) return;
+        if (status.type === 'idle' || status.type === 'cancelled') {
           setIsLoading(false);
           setStatusMessage('');
         } else {
@@ -462,18 +1545,8 @@ function App() {
           setStatusMessage(status.message || 'Working...');
 
           // Add entry to execution log (only for non-idle statuses)
-          if (status.message && status.details?.timestamp) {
-            const newEntry: ExecutionLogEntry = {
-              id: nextLogEntryId++,
-              type: status.type,
-              // Use fullMessage for the log if available, otherwise fall back to message
-              message: status.details.fullMessage || status.message,
-              timestamp: status.details.timestamp,
-              toolName: status.details.toolName,
-              duration: status.details.duration,
-              output: status.details.output,
-              error: status.details.error,
-            };
+          const newEntry = toLogEntry(status);
+          if (newEntry) {
             setExecutionLog((prev) => [...prev, newEntry]);
           }
         }
@@ -489,34 +1562,143 @@ function App() {
     };
   }, []);
 
+  // Subscribe to streamed response text from the Tauri backend
+  useEffect(() => {
+    let unlisten: UnlistenFn | null = null;
+
+    const setupListener = async () => {
+      unlisten = await listen<TextDelta>('chat:textDelta', (event) => {
+        if (event.payload.sessionId && event.payload.sessionId !== currentSessionIdRef.current) return;
+        const { messageId, partId, delta, reset } = event.payload;
+        const key = `${messageId}:${partId}`;
+        setStreamedParts((prev) => {
+          const index = prev.findIndex((part) => part.key === key);
+          if (index === -1) {
+            return [...prev, { key, text: delta }];
+          }
+          const next = [...prev];
+          next[index] = { key, text: reset ? delta : next[index].text + delta };
+          return next;
+        });
+      });
+    };
+
+    setupListener();
+
+    return () => {
+      if (unlisten) {
+        unlisten();
+      }
+    };
+  }, []);
+
+  // Subscribe to tool calls that need approval
+  useEffect(() => {
+    let unlisten: UnlistenFn | null = null;
+
+    const setupListener = async () => {
+      unlisten = await listen<PermissionRequest>('chat:permissionRequest', (event) => {
+        if (event.payload.sessionId && event.payload.sessionId !== currentSessionIdRef.current) return;
+        setPendingPermissions((prev) => [...prev, event.payload]);
+      });
+    };
+
+    setupListener();
+
+    return () => {
+      if (unlisten) {
+        unlisten();
+      }
+    };
+  }, []);
+
+  // Subscribe to latchkey requests held back in dry run mode
+  useEffect(() => {
+    let unlisten: UnlistenFn | null = null;
+
+    const setupListener = async () => {
+      unlisten = await listen<ProposedRequest>('chat:proposedRequest', (event) => {
+        if (event.payload.sessionId !== currentSessionIdRef.current) return;
+        setProposedRequests((prev) => [...prev, event.payload]);
+      });
+    };
+
+    setupListener();
+
+    return () => {
+      if (unlisten) {
+        unlisten();
+      }
+    };
+  }, []);
+
+  // Streamed parts are joined the same way the backend builds the final response
+  const streamedText = streamedParts
+    .map((part) => part.text.trim())
+    .filter((text) => text.length > 0)
+    .join('\n\n');
+
   const handleModelChange = (e: React.ChangeEvent<HTMLSelectElement>) => {
     const value = e.target.value;
-    const [providerID, modelID] = value.split(':');
-    const model = availableModels.find(m => m.providerID === providerID && m.modelID === modelID);
+    // Model IDs of local servers can contain colons (e.g. "llama3:8b"), so split at the first one
+    const separator = value.indexOf(':');
+    const providerID = value.slice(0, separator);
+    const modelID = value.slice(separator + 1);
+    const model = models.find(m => m.providerID === providerID && m.modelID === modelID);
     if (model) {
       setSelectedModel(model);
     }
   };
 
+  const handleCancel = async () => {
+    if (!currentSessionId) return;
+    try {
+      console.log('[Chat] Cancelling in-flight message...');
+      await invoke<boolean>('cancel_message', { sessionId: currentSessionId });
+    } catch (err) {
+      console.error('[Chat] Failed to cancel:', err);
+    }
+  };
+
+  const handlePermission = async (request: PermissionRequest, approve: boolean, always = false) => {
+    if (!currentSessionId) return;
+    setPendingPermissions((prev) => prev.filter((p) => p.id !== request.id));
+    try {
+      if (approve) {
+        await invoke('approve_permission', { sessionId: currentSessionId, permissionId: request.id, always });
+      } else {
+        await invoke('deny_permission', { sessionId: currentSessionId, permissionId: request.id });
+      }
+    } catch (err) {
+      console.error('[Permissions] Failed to answer:', err);
+    }
+  };
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     if (!inputValue.trim() || isLoading) return;
 
+    const sessionId = currentSessionId ?? (await handleNewSession());
+    if (!sessionId) return;
+
     const userMessage: Message = {
       id: Date.now(),
       text: inputValue,
       sender: 'user',
     };
 
-    setMessages((prev) => [...prev, userMessage]);
+    appendMessage(sessionId, userMessage);
     setInputValue('');
     setIsLoading(true);
     setStatusMessage('Thinking...');
     setExecutionLog([]); // Clear execution log for new request
+    setStreamedParts([]);
+    setPendingPermissions([]);
 
     // Send message to Tauri backend via invoke and get response
     try {
-      const response = await invoke<string>('send_message', {
+      const response = await invoke<MessageResponse>('send_message', {
+        sessionId,
         message: inputValue,
         providerId: selectedModel.providerID,
         modelId: selectedModel.modelID,
@@ -524,11 +1706,13 @@ function App() {
       const botMessageId = Date.now() + 1;
       const botMessage: Message = {
         id: botMessageId,
-        text: response,
+        text: response.text,
         sender: 'bot',
         executionLog: executionLogRef.current.length > 0 ? [...executionLogRef.current] : undefined,
+        usage: response.usage,
+        artifacts: response.artifacts.length > 0 ? response.artifacts : undefined,
       };
-      setMessages((prev) => [...prev, botMessage]);
+      appendMessage(sessionId, botMessage);
       // Transfer expanded state from loading (-1) to the new message
       setExpandedLogs((prev) => {
         const next = new Set(prev);
@@ -540,13 +1724,13 @@ function App() {
       });
     } catch (error) {
       const errorMessageId = Date.now() + 1;
-      const errorMessage: Message = {
+      const errorReply: Message = {
         id: errorMessageId,
-        text: `Error: ${error instanceof Error ? error.message : String(error)}`,
+        text: `Error: ${error instanceof Error ? error.message : errorMessage(error)}`,
         sender: 'bot',
         executionLog: executionLogRef.current.length > 0 ? [...executionLogRef.current] : undefined,
       };
-      setMessages((prev) => [...prev, errorMessage]);
+      appendMessage(sessionId, errorReply);
       // Transfer expanded state from loading (-1) to the new message
       setExpandedLogs((prev) => {
         const next = new Set(prev);
@@ -559,6 +1743,9 @@ function App() {
     } finally {
       setIsLoading(false);
       setStatusMessage('');
+      setStreamedParts([]);
+      setPendingPermissions([]);
+      loadSessions();
     }
   };
 
@@ -583,15 +1770,21 @@ function App() {
 
   // Render browser status icon
   const renderBrowserStatusIcon = () => {
-    if (browserCheckStatus === 'loading') {
+    if (browserStatus.state === 'unknown' || browserStatus.state === 'checking') {
+      const percent = browserStatus.percent === null ? null : Math.round(browserStatus.percent);
       return (
-        <div className="browser-status-icon loading" title="Checking browser...">
-          <div className="browser-spinner" />
-        </div>
+        <button
+          className="browser-status-icon loading"
+          onClick={() => setShowBrowserPopup(true)}
+          title={percent === null ? 'Checking browser...' : `Downloading browser... ${percent}%`}
+          type="button"
+        >
+          {percent === null ? <div className="browser-spinner" /> : <span className="browser-percent">{percent}%</span>}
+        </button>
       );
     }
 
-    const isSuccess = browserCheckStatus === 'success';
+    const isSuccess = browserStatus.state === 'ready';
     return (
       <button
         className={`browser-status-icon ${isSuccess ? 'success' : 'error'}`}
@@ -613,13 +1806,53 @@ function App() {
       <div className="chat-header">
         <h1>Passepartout</h1>
         <div className="header-actions">
+          <select
+            className="session-selector"
+            value={currentSessionId ?? ''}
+            onChange={(e) => handleSwitchSession(e.target.value)}
+            disabled={isLoading || sessions.length === 0}
+          >
+            {sessions.length === 0 && <option value="">New conversation</option>}
+            {sessions.map((session) => (
+              <option key={session.id} value={session.id}>
+                {session.title}
+              </option>
+            ))}
+          </select>
+          <button
+            className="settings-button"
+            onClick={handleNewSession}
+            title="New conversation"
+            type="button"
+            disabled={isLoading}
+          >
+            +
+          </button>
+          <button
+            className="settings-button"
+            onClick={handleDeleteSession}
+            title="Delete conversation"
+            type="button"
+            disabled={isLoading || !currentSessionId}
+          >
+            &times;
+          </button>
+          <label className="dry-run-toggle" title="Hold back latchkey requests that would change something until you send them">
+            <input
+              type="checkbox"
+              checked={currentSession?.dry_run ?? false}
+              onChange={(e) => handleDryRunChange(e.target.checked)}
+              disabled={!currentSessionId}
+            />
+            Dry run
+          </label>
           <select
             className="model-selector"
             value={`${selectedModel.providerID}:${selectedModel.modelID}`}
             onChange={handleModelChange}
             disabled={isLoading}
           >
-            {availableModels.map((model) => (
+            {models.map((model) => (
               <option
                 key={`${model.providerID}:${model.modelID}`}
                 value={`${model.providerID}:${model.modelID}`}
@@ -628,6 +1861,28 @@ function App() {
               </option>
             ))}
           </select>
+          <button
+            className="settings-button"
+            onClick={() => setShowWorkspace(true)}
+            title="Workspace"
+            type="button"
+            disabled={!currentSessionId}
+          >
+            <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+              <path d="M22 19a2 2 0 0 1-2 2H4a2 2 0 0 1-2-2V5a2 2 0 0 1 2-2h5l2 3h9a2 2 0 0 1 2 2z" />
+            </svg>
+          </button>
+          <button
+            className="settings-button"
+            onClick={() => setShowServices(true)}
+            title="Services"
+            type="button"
+          >
+            <svg width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" strokeLinecap="round" strokeLinejoin="round">
+              <circle cx="7.5" cy="15.5" r="5.5" />
+              <path d="M21 2l-9.6 9.6M15.5 7.5l3 3L22 7l-3-3" />
+            </svg>
+          </button>
           <button
             className="settings-button"
             onClick={() => setShowCredentials(true)}
@@ -651,17 +1906,52 @@ function App() {
                 &times;
               </button>
             </div>
-            <div className={`browser-popup-status ${browserCheckStatus}`}>
-              {browserCheckStatus === 'success' ? 'Browser is ready' : 'Browser check failed'}
+            <div className={`browser-popup-status ${browserStatus.state}`}>
+              {browserStatusLabel(browserStatus)}
             </div>
+            {browserStatus.state === 'checking' && browserStatus.percent !== null && (
+              <div className="browser-progress">
+                <div className="browser-progress-bar" style={{ width: `${browserStatus.percent}%` }} />
+              </div>
+            )}
+            {browserStatus.error && <div className="browser-popup-error">{browserStatus.error}</div>}
             <div className="browser-popup-output">
-              <pre>{browserCheckOutput || 'No output'}</pre>
+              <pre>{browserStatus.output || 'No output'}</pre>
+            </div>
+            <div className="browser-popup-actions">
+              {browserStatus.state === 'checking' && (
+                <button className="cancel-button" onClick={handleCancelBrowserCheck} type="button">
+                  Cancel
+                </button>
+              )}
+              {(browserStatus.state === 'failed' || browserStatus.state === 'cancelled') && (
+                <button className="save-button" onClick={runBrowserCheck} type="button">
+                  Retry
+                </button>
+              )}
             </div>
           </div>
         </div>
       )}
+      {showServices && <ServicesPanel onClose={() => setShowServices(false)} />}
+      {showWorkspace && currentSessionId && (
+        <WorkspacePanel sessionId={currentSessionId} onClose={() => setShowWorkspace(false)} />
+      )}
+      {previewedArtifact && currentSessionId && (
+        <ArtifactPreviewPanel
+          sessionId={currentSessionId}
+          artifact={previewedArtifact}
+          onClose={() => setPreviewedArtifact(null)}
+        />
+      )}
       {showCredentials && (
-        <CredentialsPanel onClose={() => setShowCredentials(false)} />
+        <CredentialsPanel
+          onClose={() => {
+            setShowCredentials(false);
+            // Models depend on which providers have keys
+            loadModels(true);
+          }}
+        />
       )}
       <div className="messages-container">
         {messages.length === 0 && !isLoading && (
@@ -680,13 +1970,75 @@ function App() {
                 message.text
               )}
             </div>
+            {message.sender === 'bot' && message.usage && (
+              <div className="message-usage">{formatUsage(message.usage)}</div>
+            )}
+            {message.sender === 'bot' && message.artifacts && currentSessionId && (
+              <ArtifactList
+                sessionId={currentSessionId}
+                artifacts={message.artifacts}
+                onPreview={setPreviewedArtifact}
+              />
+            )}
             {message.sender === 'bot' && message.executionLog && (
               renderExecutionLog(message.executionLog, message.id, false)
             )}
           </React.Fragment>
         ))}
+        {proposedRequests.map((request) => {
+          const result = proposalResults[request.id];
+          return (
+            <div key={request.id} className="proposed-request">
+              <div className="proposed-request-title">
+                Proposed request: <code>{request.method} {request.url ?? '(no URL)'}</code>
+              </div>
+              {request.body && <pre className="proposed-request-body">{request.body}</pre>}
+              {result ? (
+                <pre className={`proposed-request-result ${result.success ? 'success' : 'error'}`}>
+                  {result.output || (result.success ? 'Sent' : 'Failed')}
+                </pre>
+              ) : (
+                <div className="permission-request-actions">
+                  <button type="button" onClick={() => handleSendProposal(request)}>
+                    Send
+                  </button>
+                  <button type="button" className="deny" onClick={() => handleDiscardProposal(request)}>
+                    Discard
+                  </button>
+                </div>
+              )}
+            </div>
+          );
+        })}
         {isLoading && (
           <>
+            {streamedText && (
+              <div className="message bot-message">
+                <Markdown>{streamedText}</Markdown>
+              </div>
+            )}
+            {pendingPermissions.map((request) => (
+              <div key={request.id} className="permission-request">
+                <div className="permission-request-title">
+                  Allow {request.permission}: <code>{request.title}</code>
+                </div>
+                <div className="permission-request-actions">
+                  <button type="button" onClick={() => handlePermission(request, true)}>
+                    Approve
+                  </button>
+                  <button
+                    type="button"
+                    onClick={() => handlePermission(request, true, true)}
+                    title={request.patterns.length > 0 ? `Allow ${request.patterns.join(', ')}` : undefined}
+                  >
+                    Always allow
+                  </button>
+                  <button type="button" className="deny" onClick={() => handlePermission(request, false)}>
+                    Deny
+                  </button>
+                </div>
+              </div>
+            ))}
             <div className="loading-indicator">
               <div className="loading-spinner" />
               <span className="loading-text">{statusMessage}</span>
@@ -696,17 +2048,33 @@ function App() {
         )}
         <div ref={messagesEndRef} />
       </div>
+      {(pendingAttachments.length > 0 || attachmentError) && (
+        <div className="attachments-bar">
+          {pendingAttachments.map((attachment) => (
+            <span key={attachment.hash} className="attachment-chip" title={attachment.source}>
+              📎 {attachment.path.split('/').pop()} ({formatFileSize(attachment.size)})
+            </span>
+          ))}
+          {attachmentError && <span className="attachment-error">{attachmentError}</span>}
+        </div>
+      )}
       <form className="input-container" onSubmit={handleSubmit}>
         <input
           type="text"
           value={inputValue}
           onChange={(e) => setInputValue(e.target.value)}
-          placeholder="Type a message..."
+          placeholder="Type a message, or drop files to attach them..."
           className="message-input"
         />
-        <button type="submit" className="send-button" disabled={isLoading}>
-          Send
-        </button>
+        {isLoading ? (
+          <button type="button" className="stop-button" onClick={handleCancel}>
+            Stop
+          </button>
+        ) : (
+          <button type="submit" className="send-button">
+            Send
+          </button>
+        )}
       </form>
     </div>
   );